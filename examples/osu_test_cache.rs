@@ -0,0 +1,63 @@
+use itertools::Itertools;
+use serde_json::{Map, Value};
+
+#[allow(dead_code)]
+#[path = "../tests/common/osu_test_client.rs"]
+mod osu_test_client;
+
+use osu_test_client::{read_osu_request_cache, save_osu_request_cache};
+
+const USAGE: &str =
+    "Usage: cargo run --example osu_test_cache -- <list|dump|delete> <cache file> [urls]";
+
+/// Inspects and edits the recorded osu! API responses in `tests/data`:
+/// - `list`: prints the recorded request URLs
+/// - `dump`: prints the whole cache as plain JSON, keyed by the request URL
+/// - `delete`: removes the given URLs. The next test run with `RECORD_MISSING_OSU_RESPONSES=true`
+///   requests them from osu! again, so a single stale response can be refreshed without
+///   recording the whole test again
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let (Some(command), Some(path)) = (arguments.first(), arguments.get(1)) else {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    };
+    let mut cache = read_osu_request_cache(path)
+        .unwrap_or_else(|| panic!("{} is not a recorded osu! request cache", path));
+
+    match command.as_str() {
+        "list" => {
+            for url in cache.keys().sorted() {
+                println!("{}", url);
+            }
+        }
+        "dump" => {
+            // Responses are JSON, anything else is printed as text
+            let responses: Map<String, Value> = cache
+                .iter()
+                .map(|(url, bytes)| {
+                    let response = serde_json::from_slice(bytes).unwrap_or_else(|_| {
+                        Value::String(String::from_utf8_lossy(bytes).into_owned())
+                    });
+                    (url.clone(), response)
+                })
+                .collect();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&responses).expect("failed to serialize the cache")
+            );
+        }
+        "delete" => {
+            for url in &arguments[2..] {
+                if cache.remove(url).is_none() {
+                    eprintln!("{} is not in the cache", url);
+                }
+            }
+            save_osu_request_cache(path, &cache).expect("failed to save the cache");
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    }
+}
@@ -2,11 +2,13 @@ use futures::future::join_all;
 use hashlink::LinkedHashSet;
 use mapper_influences_backend_rs::daily_update::update_once;
 use mapper_influences_backend_rs::database::{numerical_thing, DatabaseClient};
+use mapper_influences_backend_rs::http_client::build_http_client;
 use mapper_influences_backend_rs::osu_api::credentials_grant::CredentialsGrantClient;
 use mapper_influences_backend_rs::osu_api::request::OsuApiRequestClient;
 use mapper_influences_backend_rs::osu_api::Group;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::BufReader;
 use std::sync::Arc;
@@ -124,6 +126,18 @@ where
     data
 }
 
+/// Full reset deletes the whole namespace before the import. It only runs when it's explicitly
+/// allowed and the namespace is not `prod`, so that prod data can't be deleted by accident.
+/// Otherwise the import is incremental and skips the users and influences that already exist.
+fn destructive_import_allowed(db: &DatabaseClient) -> bool {
+    let allowed =
+        std::env::var("ALLOW_DESTRUCTIVE_IMPORT").is_ok_and(|value| value.to_lowercase() == "true");
+    if allowed && db.namespace() == "prod" {
+        panic!("Refusing to reset prod namespace. Set SURREAL_NS to import into another namespace");
+    }
+    allowed
+}
+
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
@@ -163,14 +177,13 @@ async fn main() {
         .await
         .expect("failed to initialize db connection");
 
-    // WARN: BE EXTREMELY CAUTIOUS WITH THIS!!!!
-    // YOU MIGHT ACCIDENTALLY DELETE PROD DATA
-    // resetting DB so that we don't get duplicate results
-    // TODO: run this only when env variables are `test`
-    db.get_inner_ref()
-        .query("REMOVE NAMESPACE test")
-        .await
-        .unwrap();
+    if destructive_import_allowed(&db) {
+        db.get_inner_ref()
+            .query(format!("REMOVE NAMESPACE `{}`", db.namespace()))
+            .await
+            .unwrap();
+        println!("Namespace reset done");
+    }
 
     MigrationRunner::new(db.get_inner_ref())
         .up()
@@ -178,28 +191,67 @@ async fn main() {
         .expect("Failed to apply migrations");
     println!("Migration done");
 
-    db.get_inner_ref()
-        .query("INSERT INTO user ($values)")
-        .bind(("values", full_users.clone()))
+    // Skipping existing records so that an interrupted import can be run again
+    let existing_user_ids: HashSet<u32> = db
+        .get_inner_ref()
+        .query("SELECT VALUE meta::id(id) FROM user")
         .await
-        .unwrap();
-    println!("User insertion done");
+        .unwrap()
+        .take::<Vec<u32>>(0)
+        .unwrap()
+        .into_iter()
+        .collect();
+    let new_users: Vec<UserFull> = full_users
+        .iter()
+        .filter(|user| !existing_user_ids.contains(&user.user.id))
+        .cloned()
+        .collect();
+    if !new_users.is_empty() {
+        db.get_inner_ref()
+            .query("INSERT INTO user ($values)")
+            .bind(("values", new_users.clone()))
+            .await
+            .unwrap();
+    }
+    println!(
+        "User insertion done. Inserted {}, skipped {}",
+        new_users.len(),
+        full_users.len() - new_users.len()
+    );
 
+    let existing_influences: HashSet<(u32, u32)> = db
+        .get_inner_ref()
+        .query("SELECT VALUE [meta::id(in), meta::id(out)] FROM influenced_by")
+        .await
+        .unwrap()
+        .take::<Vec<(u32, u32)>>(0)
+        .unwrap()
+        .into_iter()
+        .collect();
+    let influence_count = influences.len();
     let db_influences: Vec<InfluenceWithReferences> = influences
         .into_iter()
+        .filter(|influence| {
+            !existing_influences.contains(&(influence.influenced_by, influence.influenced_to))
+        })
         .map(InfluenceWithReferences::from)
         .collect();
+    let inserted_influence_count = db_influences.len();
 
-    db.get_inner_ref()
-        .query("INSERT RELATION INTO influenced_by ($values)")
-        .bind(("values", db_influences))
-        .await
-        .unwrap();
+    if !db_influences.is_empty() {
+        db.get_inner_ref()
+            .query("INSERT RELATION INTO influenced_by ($values)")
+            .bind(("values", db_influences))
+            .await
+            .unwrap();
+    }
 
-    println!("Influence insertion done");
+    println!(
+        "Influence insertion done. Inserted {}, skipped {}",
+        inserted_influence_count,
+        influence_count - inserted_influence_count
+    );
 
-    // WARN: BE EXTREMELY CAUTIOUS WITH THIS!!!!
-    // YOU MIGHT ACCIDENTALLY DELETE PROD DATA
     // Deleting ADD_INFLUENCE events after adding data.
     //db.get_inner_ref().query("delete activity").await.unwrap();
 
@@ -229,10 +281,13 @@ async fn main() {
     println!("custom order insertion done");
 
     let user_ids = full_users.into_iter().map(|user| user.user.id).collect();
-    let request_client = Arc::new(OsuApiRequestClient::new(100));
+    let request_client = Arc::new(OsuApiRequestClient::from_env(
+        build_http_client().unwrap(),
+        100,
+    ));
     let credentials_grant_client = CredentialsGrantClient::new(request_client).await.unwrap();
 
-    let unsuccessfuls = update_once(
+    let summary = update_once(
         credentials_grant_client,
         db,
         user_ids,
@@ -240,7 +295,7 @@ async fn main() {
     )
     .await;
 
-    dbg!(unsuccessfuls);
+    dbg!(summary);
 
     println!("done");
 }
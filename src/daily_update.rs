@@ -1,27 +1,109 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    database::DatabaseClient, osu_api::credentials_grant::CredentialsGrantClient, retry::Retryable,
+    database::DatabaseClient, env::seconds_from_env, error::AppError,
+    osu_api::credentials_grant::CredentialsGrantClient, retry::Retryable,
 };
 
+/// Defaults to a run every 24 hours with a minute between each user. Configurable with
+/// `DAILY_UPDATE_INTERVAL` and `DAILY_UPDATE_USER_SPACING` environment variables in seconds
+#[derive(Debug, Clone, Copy)]
+pub struct UpdateSchedule {
+    pub interval: Duration,
+    pub user_spacing: Duration,
+}
+
+impl Default for UpdateSchedule {
+    fn default() -> Self {
+        UpdateSchedule {
+            interval: Duration::from_secs(60 * 60 * 24),
+            user_spacing: Duration::from_secs(60),
+        }
+    }
+}
+
+impl UpdateSchedule {
+    /// Panics on invalid values, same as the rest of the environment variables
+    pub fn from_env() -> Self {
+        let default = UpdateSchedule::default();
+        let schedule = UpdateSchedule {
+            interval: seconds_from_env("DAILY_UPDATE_INTERVAL", default.interval.as_secs()),
+            user_spacing: seconds_from_env(
+                "DAILY_UPDATE_USER_SPACING",
+                default.user_spacing.as_secs(),
+            ),
+        };
+        // `tokio::time::interval` panics with zero durations
+        assert!(
+            !schedule.user_spacing.is_zero(),
+            "DAILY_UPDATE_USER_SPACING environment variable should be at least 1"
+        );
+        assert!(
+            schedule.interval > schedule.user_spacing,
+            "DAILY_UPDATE_INTERVAL environment variable should be longer than DAILY_UPDATE_USER_SPACING"
+        );
+        schedule
+    }
+
+    /// How many users can be updated in a single run without overlapping with the next one
+    pub fn users_per_run(&self) -> u64 {
+        self.interval.as_secs() / self.user_spacing.as_secs()
+    }
+}
+
+/// Result of a single [`update_once`] run
+#[derive(Debug, Clone)]
+pub struct UpdateSummary {
+    pub updated: usize,
+    /// Ids of the users that couldn't be requested or inserted
+    pub failed: Vec<u32>,
+    /// Ids of the users whose osu! accounts are deleted or restricted. They are marked in the
+    /// database and skipped in the next runs
+    pub deleted: Vec<u32>,
+    pub duration: Duration,
+}
+
 pub async fn update_once(
     client: Arc<CredentialsGrantClient>,
     database: Arc<DatabaseClient>,
     users_to_update: Vec<u32>,
     wait_duration: Duration,
-) -> Vec<u32> {
+) -> UpdateSummary {
+    let start = Instant::now();
     let mut interval = tokio::time::interval(wait_duration);
 
+    let mut updated = 0;
     let mut unsuccessfull_ids = Vec::new();
+    let mut deleted_ids = Vec::new();
     for user_id in users_to_update {
         interval.tick().await;
-        let Ok(user) = client.get_user_osu(user_id).await else {
-            unsuccessfull_ids.push(user_id);
-            tracing::error!(
-                "Failed to request {} from osu! API for daily update",
-                user_id
-            );
-            continue;
+        let user = match client.get_user_osu(user_id).await {
+            Ok(user) => user,
+            Err(AppError::OsuUserNotFound(_)) => {
+                if let Err(error) = database.mark_user_deleted(user_id).await {
+                    unsuccessfull_ids.push(user_id);
+                    tracing::error!(
+                        "Failed to mark user {} as deleted for daily update: {}",
+                        user_id,
+                        error
+                    );
+                } else {
+                    deleted_ids.push(user_id);
+                    tracing::info!("User {} is gone from osu!, marked as deleted", user_id);
+                }
+                continue;
+            }
+            Err(_) => {
+                unsuccessfull_ids.push(user_id);
+                tracing::error!(
+                    "Failed to request {} from osu! API for daily update",
+                    user_id
+                );
+                continue;
+            }
         };
         let Ok(_) = database.upsert_user(user).await else {
             unsuccessfull_ids.push(user_id);
@@ -31,29 +113,52 @@ pub async fn update_once(
             );
             continue;
         };
+        updated += 1;
         tracing::debug!("Requested and inserted user {} for daily update", user_id);
     }
-    unsuccessfull_ids
+    UpdateSummary {
+        updated,
+        failed: unsuccessfull_ids,
+        deleted: deleted_ids,
+        duration: start.elapsed(),
+    }
 }
 
 pub async fn update_routine(
     client: Arc<CredentialsGrantClient>,
     mut database: Arc<DatabaseClient>,
     initial_sleep_time: Duration,
+    schedule: UpdateSchedule,
 ) {
     tokio::time::sleep(initial_sleep_time).await;
-    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60 * 24));
+    let mut interval = tokio::time::interval(schedule.interval);
     loop {
         interval.tick().await;
         let users_to_update: Vec<u32> = database
             .retry_until_success(60, "Failed to fetch users for daily update")
             .await;
-        update_once(
+        let summary = update_once(
             client.clone(),
             database.clone(),
             users_to_update,
-            Duration::from_secs(60),
+            schedule.user_spacing,
         )
         .await;
+        tracing::info!(
+            "Daily update finished in {} seconds. {} users updated, {} deleted, {} failed: {:?}",
+            summary.duration.as_secs(),
+            summary.updated,
+            summary.deleted.len(),
+            summary.failed.len(),
+            summary.failed,
+        );
+        // Missed ticks are run right away, so the next run starts late instead of being skipped
+        if summary.duration > schedule.interval {
+            tracing::warn!(
+                "Daily update took longer than the {} second interval, it can't keep up with \
+                the number of users",
+                schedule.interval.as_secs()
+            );
+        }
     }
 }
@@ -8,6 +8,17 @@ use crate::{error::AppError, handlers::activity::Activity, retry::Retryable};
 
 use super::{numerical_thing, DatabaseClient};
 
+/// `created_at` of the latest activity of the user, excluding logins. Correlated to the selected
+/// user record, so it can be used as a field in user queries
+pub(super) const LAST_ACTIVE_SUBQUERY: &str = r#"
+    (
+        SELECT VALUE created_at FROM activity 
+        WHERE user = $parent.id AND event_type != "LOGIN" 
+        ORDER BY created_at DESC 
+        LIMIT 1
+    )[0]
+"#;
+
 impl DatabaseClient {
     // Can't automate it in database
     // db has no way of differentiating login and influence add activities
@@ -43,6 +54,7 @@ impl DatabaseClient {
             user.groups,
             user.ranked_and_approved_beatmapset_count 
                 + user.guest_beatmapset_count as user.ranked_maps,
+            user.ranked_mapper,
             user.previous_usernames,
 
             fn::id_or_null(influence.out.id) as influence.id,
@@ -55,7 +67,8 @@ impl DatabaseClient {
             fn::add_possible_nulls(
                 influence.out.ranked_and_approved_beatmapset_count, 
                 influence.out.guest_beatmapset_count
-            ) as influence.ranked_maps
+            ) as influence.ranked_maps,
+            influence.out.ranked_mapper as influence.ranked_mapper
             FROM activity
         "#
     }
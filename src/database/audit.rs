@@ -0,0 +1,159 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Datetime;
+
+use crate::error::AppError;
+
+use super::DatabaseClient;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AuditAction {
+    AddInfluence,
+    RemoveInfluence,
+    AddInfluenceBeatmap,
+    RemoveInfluenceBeatmap,
+    EditInfluenceDesc,
+    EditInfluenceTag,
+    EditInfluenceType,
+    EditInfluenceOrder,
+    PinInfluence,
+    UnpinInfluence,
+    EditBio,
+    AddUserBeatmap,
+    RemoveUserBeatmap,
+    AdminLogin,
+    RejectedAdminLogin,
+    WarmCaches,
+    RecomputeLeaderboards,
+    AddReport,
+    ResolveReport,
+    MergeUsers,
+    PurgeUser,
+}
+
+/// `AuditLog` type. `target` is the other user of influence actions
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AuditLog {
+    pub id: String,
+    /// Missing for rejected admin logins, the caller isn't authenticated. The attempted user id
+    /// is in `details` instead
+    pub actor: Option<u32>,
+    pub action: AuditAction,
+    pub target: Option<u32>,
+    pub details: Option<String>,
+    #[schemars(with = "chrono::DateTime<chrono::Utc>")]
+    pub created_at: Datetime,
+}
+
+impl DatabaseClient {
+    pub async fn write_audit(
+        &self,
+        actor: u32,
+        action: AuditAction,
+        target: Option<u32>,
+        details: Option<String>,
+    ) -> Result<(), AppError> {
+        self.db
+            .query(
+                "
+                CREATE audit_log
+                SET actor = $actor,
+                    action = $action,
+                    target = $target,
+                    details = $details
+                ",
+            )
+            .bind(("actor", actor))
+            .bind(("action", action))
+            .bind(("target", target))
+            .bind(("details", details))
+            .await?;
+        Ok(())
+    }
+
+    /// Writes a `REJECTED_ADMIN_LOGIN` entry unless `limit` of them are already written in the
+    /// last minute. Returns false if the limit is reached, nothing is written then
+    pub async fn write_rejected_admin_login(
+        &self,
+        attempted_id: u32,
+        limit: u32,
+    ) -> Result<bool, AppError> {
+        let mut response = self
+            .db
+            .query(
+                r#"
+                BEGIN TRANSACTION;
+                LET $recent = count(
+                    SELECT id FROM audit_log
+                    WHERE action = "REJECTED_ADMIN_LOGIN" AND created_at > time::now() - 1m
+                );
+                IF $recent < $limit {
+                    CREATE audit_log SET action = "REJECTED_ADMIN_LOGIN", details = $details;
+                };
+                RETURN $recent < $limit;
+                COMMIT TRANSACTION;
+                "#,
+            )
+            .bind(("limit", limit))
+            .bind(("details", attempted_id.to_string()))
+            .await?
+            .check()?;
+        let last_statement = response.num_statements() - 1;
+        let written: Option<bool> = response.take(last_statement)?;
+        Ok(written.unwrap_or(false))
+    }
+
+    /// Newest entries first
+    pub async fn get_audit_logs(
+        &self,
+        actor: Option<u32>,
+        target: Option<u32>,
+        start: u32,
+        limit: u32,
+    ) -> Result<Vec<AuditLog>, AppError> {
+        let logs: Vec<AuditLog> = self
+            .db
+            .query(
+                "
+                SELECT *, meta::id(id) AS id
+                FROM audit_log
+                WHERE ($actor = NONE OR actor = $actor)
+                    AND ($target = NONE OR target = $target)
+                ORDER BY created_at DESC
+                START $start
+                LIMIT $limit
+                ",
+            )
+            .bind(("actor", actor))
+            .bind(("target", target))
+            .bind(("start", start))
+            .bind(("limit", limit))
+            .await?
+            .take(0)?;
+        Ok(logs)
+    }
+
+    pub async fn get_audit_log_count(
+        &self,
+        actor: Option<u32>,
+        target: Option<u32>,
+    ) -> Result<u32, AppError> {
+        let count: Option<u32> = self
+            .db
+            .query(
+                "
+                RETURN count(
+                    SELECT id FROM audit_log
+                    WHERE ($actor = NONE OR actor = $actor)
+                        AND ($target = NONE OR target = $target)
+                )
+                ",
+            )
+            .bind(("actor", actor))
+            .bind(("target", target))
+            .await?
+            .take(0)?;
+        Ok(count.unwrap_or(0))
+    }
+}
@@ -0,0 +1,64 @@
+use surrealdb::sql::{Id, Thing};
+
+use crate::{error::AppError, jwt::unix_millis_now};
+
+use super::{numerical_thing, DatabaseClient};
+
+impl DatabaseClient {
+    /// Revoked tokens are stored until their expiration date. Expired entries are cleaned up
+    /// while revoking new ones, since there is no TTL support in SurrealDB
+    pub async fn revoke_token(&self, jwt_id: String, expires_at: u64) -> Result<(), AppError> {
+        self.db
+            .query(
+                "
+                UPSERT $token SET expires_at = time::from::secs($expires_at);
+                DELETE revoked_token WHERE expires_at < time::now();
+                ",
+            )
+            .bind(("token", Thing::from(("revoked_token", Id::String(jwt_id)))))
+            .bind(("expires_at", expires_at))
+            .await?;
+        Ok(())
+    }
+
+    /// Every token of the user that is issued before this call becomes invalid. The time comes
+    /// from the backend clock, the same clock that sets `issued_at_ms` of the tokens
+    pub async fn revoke_all_user_tokens(&self, user_id: u32) -> Result<(), AppError> {
+        self.db
+            .query("UPSERT $revocation SET revoked_at = time::from::millis($revoked_at_ms)")
+            .bind((
+                "revocation",
+                numerical_thing("user_token_revocation", user_id),
+            ))
+            .bind(("revoked_at_ms", unix_millis_now()))
+            .await?;
+        Ok(())
+    }
+
+    /// Tokens issued in the same millisecond as a logout-all are revoked too
+    pub async fn is_token_revoked(
+        &self,
+        user_id: u32,
+        jwt_id: String,
+        issued_at_ms: u64,
+    ) -> Result<bool, AppError> {
+        let revoked: Option<bool> = self
+            .db
+            .query(
+                "
+                LET $revoked_at = $revocation.revoked_at;
+                RETURN $token.expires_at != NONE
+                    OR ($revoked_at != NONE AND $revoked_at >= time::from::millis($issued_at_ms));
+                ",
+            )
+            .bind(("token", Thing::from(("revoked_token", Id::String(jwt_id)))))
+            .bind((
+                "revocation",
+                numerical_thing("user_token_revocation", user_id),
+            ))
+            .bind(("issued_at_ms", issued_at_ms))
+            .await?
+            .take(1)?;
+        Ok(revoked.unwrap_or(false))
+    }
+}
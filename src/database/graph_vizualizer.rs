@@ -1,9 +1,10 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
 
 use crate::error::AppError;
 
-use super::DatabaseClient;
+use super::{numerical_thing, DatabaseClient};
 
 #[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Debug)]
 pub struct GraphUser {
@@ -21,6 +22,13 @@ pub struct GraphInfluence {
     influence_type: u8,
 }
 
+/// A page of the graph stream. Records are read in id order and the next page starts after
+/// `last_id`, so pages don't get slower the further the stream goes
+pub struct GraphChunk<T> {
+    pub items: Vec<T>,
+    pub last_id: Option<Thing>,
+}
+
 #[derive(Serialize, JsonSchema, Clone)]
 pub struct GraphData {
     pub nodes: Vec<GraphUser>,
@@ -39,13 +47,13 @@ impl DatabaseClient {
                 "
                 SELECT 
                     meta::id(id) AS id, 
-                    count(<-influenced_by) AS mentions,
+                    mentions,
                     count(->influenced_by) AS influenced_by,
                     avatar_url,
                     username
                 FROM user
                 WHERE 
-                    count(<-influenced_by) > 0 
+                    mentions > 0 
                     OR count(->influenced_by) > 0;
 
                 SELECT meta::id(in) AS source, meta::id(out) AS target, influence_type FROM influenced_by;
@@ -57,4 +65,69 @@ impl DatabaseClient {
             links: query_result.take(1)?,
         })
     }
+
+    /// Paginated version of the node query in [`DatabaseClient::get_graph_data`]. Used for
+    /// streaming, so the consistency between nodes and links is not guaranteed.
+    pub async fn get_graph_nodes(
+        &self,
+        after: Option<Thing>,
+        limit: u32,
+    ) -> Result<GraphChunk<GraphUser>, AppError> {
+        let nodes: Vec<GraphUser> = self
+            .db
+            .query(
+                "
+                SELECT 
+                    meta::id(id) AS id, 
+                    mentions,
+                    count(->influenced_by) AS influenced_by,
+                    avatar_url,
+                    username
+                FROM user
+                WHERE 
+                    ($after IS NONE OR id > $after)
+                    AND (mentions > 0 OR count(->influenced_by) > 0)
+                ORDER BY id
+                LIMIT $limit;
+                ",
+            )
+            .bind(("after", after))
+            .bind(("limit", limit))
+            .await?
+            .take(0)?;
+        let last_id = nodes.last().map(|node| numerical_thing("user", node.id));
+        Ok(GraphChunk {
+            items: nodes,
+            last_id,
+        })
+    }
+
+    /// Paginated version of the link query in [`DatabaseClient::get_graph_data`]
+    pub async fn get_graph_links(
+        &self,
+        after: Option<Thing>,
+        limit: u32,
+    ) -> Result<GraphChunk<GraphInfluence>, AppError> {
+        let mut query_result = self
+            .db
+            .query(
+                "
+                LET $links = 
+                    SELECT id, meta::id(in) AS source, meta::id(out) AS target, influence_type 
+                    FROM influenced_by
+                    WHERE $after IS NONE OR id > $after
+                    ORDER BY id
+                    LIMIT $limit;
+                RETURN $links;
+                RETURN array::last($links).id;
+                ",
+            )
+            .bind(("after", after))
+            .bind(("limit", limit))
+            .await?;
+        Ok(GraphChunk {
+            items: query_result.take(1)?,
+            last_id: query_result.take(2)?,
+        })
+    }
 }
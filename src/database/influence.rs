@@ -1,13 +1,20 @@
+use std::collections::{HashMap, HashSet};
+
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
 
 use crate::{
     error::AppError,
-    handlers::influence::InfluenceCreationOptions,
-    osu_api::{BeatmapEnum, BeatmapsetSmall},
+    handlers::influence::{InfluenceCreationOptions, InfluenceTypeUpdate},
+    osu_api::{BeatmapEnum, BeatmapsetSmall, UserOsu},
 };
 
-use super::{numerical_thing, user::UserSmall, DatabaseClient};
+use super::{
+    numerical_thing,
+    user::{bind_user_details, UserSmall, UPSERT_USER_STATEMENT},
+    DatabaseClient,
+};
 
 /// `Influence` type. Used in influence and mentions related endpoints
 #[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Debug)]
@@ -20,6 +27,62 @@ pub struct Influence {
     #[serde(default)]
     #[schemars(with = "Vec<BeatmapsetSmall>")]
     pub beatmaps: Vec<BeatmapEnum>,
+    /// Free form label to categorize influences. Can be used as color too.
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Primary influence of the user. Only one influence can be pinned. Always false for
+    /// mentions endpoint
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// `InfluenceTreeNode` type. A user and the users they are influenced by. Users that are already
+/// in the tree are not expanded again, so cycles end with a `repeated` node
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Debug)]
+pub struct InfluenceTreeNode {
+    pub id: u32,
+    pub username: String,
+    pub avatar_url: String,
+    /// Type of the influence from the parent node. Null for the root
+    pub influence_type: Option<u8>,
+    /// The user is expanded somewhere else in the tree, `influences` is always empty
+    pub repeated: bool,
+    pub influences: Vec<InfluenceTreeNode>,
+}
+
+/// `InfluenceTree` type
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Debug)]
+pub struct InfluenceTree {
+    pub root: InfluenceTreeNode,
+    /// Node limit is reached before the requested depth. Some influences are left out
+    pub truncated: bool,
+}
+
+/// Single influence in a level of the tree. `parent` is the user that added the influence
+#[derive(Deserialize)]
+struct InfluenceTreeEdge {
+    parent: u32,
+    id: u32,
+    username: String,
+    avatar_url: String,
+    influence_type: u8,
+}
+
+impl InfluenceTreeNode {
+    /// Nodes are collected flat while traversing, since a level is only known after the previous
+    /// one is fetched. `children` holds the indexes of the child nodes in `nodes`
+    fn nest(
+        index: usize,
+        nodes: &mut [Option<InfluenceTreeNode>],
+        children: &[Vec<usize>],
+    ) -> Self {
+        let mut node = nodes[index].take().expect("every node has a single parent");
+        node.influences = children[index]
+            .iter()
+            .map(|child| Self::nest(*child, nodes, children))
+            .collect();
+        node
+    }
 }
 
 impl DatabaseClient {
@@ -33,41 +96,90 @@ impl DatabaseClient {
         out.groups as user.groups,
         out.ranked_and_approved_beatmapset_count 
             + out.guest_beatmapset_count as user.ranked_maps,
-        count(out<-influenced_by) as user.mentions,
+        out.ranked_mapper as user.ranked_mapper,
+        out.mentions as user.mentions,
+        count(out->influenced_by) as user.influences,
         out.previous_usernames as user.previous_usernames,
         beatmaps,
         description,
-        influence_type
+        influence_type,
+        tag,
+        pinned
         "
     }
 
+    /// Upserts the target user and creates the relation in one transaction. The relation needs
+    /// the target user to exist, and a failed relation shouldn't leave a half updated target.
+    /// Nothing is written if this returns an error
+    /// Fails with [`AppError::InfluenceLimitReached`] if the user already has `limit` influences.
+    /// The count is checked in the same transaction, so parallel requests can't go over it
     pub async fn add_influence_relation(
         &self,
         user_id: u32,
-        target_user_id: u32,
+        target_user: UserOsu,
         options: InfluenceCreationOptions,
+        limit: u32,
     ) -> Result<Influence, AppError> {
-        let influence: Option<Influence> = self
-            .db
-            .query(format!(
-                "
-                RELATE $user->influenced_by->$target
+        let target_user_id = target_user.id;
+        let query = self.db.query(format!(
+            "
+            BEGIN TRANSACTION;
+            {}
+            LET $influence_count = count(SELECT id FROM $user->influenced_by);
+            IF $influence_count < $limit {{
+                RELATE $user->influenced_by->$thing
                 SET 
                     description = $description,
                     influence_type = $influence_type,
-                    beatmaps = $beatmaps
-                RETURN {}
-                ",
-                self.single_influence_return_string()
-            ))
+                    beatmaps = array::distinct($beatmaps),
+                    tag = $tag
+                RETURN {};
+            }};
+            COMMIT TRANSACTION;
+            ",
+            UPSERT_USER_STATEMENT,
+            self.single_influence_return_string()
+        ));
+        let mut response = bind_user_details(query, target_user)
             .bind(("user", numerical_thing("user", user_id)))
-            .bind(("target", numerical_thing("user", target_user_id)))
             .bind(("description", options.description))
             .bind(("influence_type", options.influence_type))
             .bind(("beatmaps", options.beatmaps))
+            .bind(("tag", options.tag))
+            .bind(("limit", limit))
+            .await?;
+        let last_statement = response.num_statements() - 1;
+        let influence: Option<Influence> = response.take(last_statement).map_err(|error| {
+            // Errors from the remote engine are only strings, the index name is the only way
+            // to tell a duplicate influence apart
+            if error.to_string().contains("unique_in_out") {
+                AppError::InfluenceAlreadyExists(target_user_id)
+            } else {
+                AppError::from(error)
+            }
+        })?;
+        // Nothing is related when the limit is reached
+        influence.ok_or(AppError::InfluenceLimitReached(limit))
+    }
+
+    /// Subset of `target_user_ids` that `own_user_id` has an influence to
+    pub async fn get_existing_influence_targets(
+        &self,
+        own_user_id: u32,
+        target_user_ids: &[u32],
+    ) -> Result<Vec<u32>, AppError> {
+        let targets: Vec<Thing> = target_user_ids
+            .iter()
+            .map(|user_id| numerical_thing("user", *user_id))
+            .collect();
+        let existing: Vec<u32> = self
+            .db
+            .query("SELECT VALUE meta::id(out) FROM $own_user->influenced_by WHERE out IN $targets")
+            .bind(("own_user", numerical_thing("user", own_user_id)))
+            .bind(("targets", targets))
             .await?
             .take(0)?;
-        influence.ok_or(AppError::MissingInfluence)
+        Ok(existing)
     }
 
     pub async fn remove_influence_relation(
@@ -91,6 +203,24 @@ impl DatabaseClient {
         influence.ok_or(AppError::MissingInfluence)
     }
 
+    pub async fn get_influence(
+        &self,
+        own_user_id: u32,
+        target_user_id: u32,
+    ) -> Result<Influence, AppError> {
+        let influence: Option<Influence> = self
+            .db
+            .query(format!(
+                "SELECT {} FROM $own_user->influenced_by WHERE out=$target_user",
+                self.single_influence_return_string()
+            ))
+            .bind(("own_user", numerical_thing("user", own_user_id)))
+            .bind(("target_user", numerical_thing("user", target_user_id)))
+            .await?
+            .take(0)?;
+        influence.ok_or(AppError::MissingInfluence)
+    }
+
     pub async fn add_beatmap_to_influence(
         &self,
         own_user_id: u32,
@@ -101,7 +231,9 @@ impl DatabaseClient {
             .db
             .query(format!(
                 "
-                UPDATE $own_user->influenced_by SET beatmaps += $beatmap_ids WHERE out=$target_user 
+                UPDATE $own_user->influenced_by 
+                SET beatmaps = array::union(beatmaps, $beatmap_ids) 
+                WHERE out=$target_user 
                 RETURN {}
                 ",
                 self.single_influence_return_string()
@@ -161,6 +293,36 @@ impl DatabaseClient {
         influence.ok_or(AppError::MissingInfluence)
     }
 
+    /// Updates every influence in one query. Missing influences are skipped
+    pub async fn update_influence_types(
+        &self,
+        own_user_id: u32,
+        updates: &[InfluenceTypeUpdate],
+    ) -> Result<Vec<Influence>, AppError> {
+        let targets: Vec<Thing> = updates
+            .iter()
+            .map(|update| numerical_thing("user", update.influenced_to))
+            .collect();
+        let influences: Vec<Influence> = self
+            .db
+            .query(format!(
+                r#"
+                FOR $update IN $updates {{
+                    UPDATE $own_user->influenced_by SET influence_type = $update.type_id
+                    WHERE out = type::thing("user", $update.influenced_to);
+                }};
+                SELECT {}, order FROM $own_user->influenced_by WHERE out IN $targets ORDER BY order;
+                "#,
+                self.single_influence_return_string()
+            ))
+            .bind(("own_user", numerical_thing("user", own_user_id)))
+            .bind(("updates", updates.to_vec()))
+            .bind(("targets", targets))
+            .await?
+            .take(1)?;
+        Ok(influences)
+    }
+
     pub async fn update_influence_description(
         &self,
         own_user_id: u32,
@@ -185,9 +347,86 @@ impl DatabaseClient {
         influence.ok_or(AppError::MissingInfluence)
     }
 
+    pub async fn update_influence_tag(
+        &self,
+        own_user_id: u32,
+        target_user_id: u32,
+        tag: Option<String>,
+    ) -> Result<Influence, AppError> {
+        let influence: Option<Influence> = self
+            .db
+            .query(format!(
+                "
+                UPDATE $own_user->influenced_by
+                SET tag=$tag WHERE out=$target_user
+                RETURN {}
+                ",
+                self.single_influence_return_string()
+            ))
+            .bind(("own_user", numerical_thing("user", own_user_id)))
+            .bind(("target_user", numerical_thing("user", target_user_id)))
+            .bind(("tag", tag))
+            .await?
+            .take(0)?;
+        influence.ok_or(AppError::MissingInfluence)
+    }
+
+    /// Pins the influence and unpins the previously pinned one in a single statement, so a user
+    /// can't end up with two pinned influences. Nothing is unpinned if the influence doesn't exist
+    pub async fn pin_influence(
+        &self,
+        own_user_id: u32,
+        target_user_id: u32,
+    ) -> Result<Influence, AppError> {
+        let influences: Vec<Influence> = self
+            .db
+            .query(format!(
+                "
+                UPDATE $own_user->influenced_by
+                SET pinned = (out == $target_user)
+                WHERE (pinned = true OR out = $target_user)
+                    AND count($own_user->influenced_by[WHERE out = $target_user]) > 0
+                RETURN {}
+                ",
+                self.single_influence_return_string()
+            ))
+            .bind(("own_user", numerical_thing("user", own_user_id)))
+            .bind(("target_user", numerical_thing("user", target_user_id)))
+            .await?
+            .take(0)?;
+        influences
+            .into_iter()
+            .find(|influence| influence.user.id == target_user_id)
+            .ok_or(AppError::MissingInfluence)
+    }
+
+    pub async fn unpin_influence(
+        &self,
+        own_user_id: u32,
+        target_user_id: u32,
+    ) -> Result<Influence, AppError> {
+        let influence: Option<Influence> = self
+            .db
+            .query(format!(
+                "
+                UPDATE $own_user->influenced_by
+                SET pinned = false WHERE out=$target_user
+                RETURN {}
+                ",
+                self.single_influence_return_string()
+            ))
+            .bind(("own_user", numerical_thing("user", own_user_id)))
+            .bind(("target_user", numerical_thing("user", target_user_id)))
+            .await?
+            .take(0)?;
+        influence.ok_or(AppError::MissingInfluence)
+    }
+
     pub async fn get_influences(
         &self,
         user_id: u32,
+        ranked_only: bool,
+        has_beatmap: Option<u32>,
         start: u32,
         limit: u32,
     ) -> Result<Vec<Influence>, AppError> {
@@ -204,19 +443,27 @@ impl DatabaseClient {
                     out.groups as user.groups,
                     out.ranked_and_approved_beatmapset_count 
                         + out.guest_beatmapset_count as user.ranked_maps,
-                    COUNT(->user<-influenced_by) as user.mentions,
+                    out.ranked_mapper as user.ranked_mapper,
+                    out.mentions as user.mentions,
+                    count(out->influenced_by) as user.influences,
                     out.previous_usernames as user.previous_usernames,
                     influence_type,
                     description,
                     beatmaps,
+                    tag,
+                    pinned,
                     order
                 FROM $thing->influenced_by
+                WHERE ($ranked_only = false OR out.ranked_mapper = true)
+                    AND ($has_beatmap = none OR beatmaps CONTAINS $has_beatmap)
                 ORDER BY order
                 START $start
                 LIMIT $limit
                 ",
             )
             .bind(("thing", numerical_thing("user", user_id)))
+            .bind(("ranked_only", ranked_only))
+            .bind(("has_beatmap", has_beatmap))
             .bind(("limit", limit))
             .bind(("start", start))
             .await?
@@ -228,6 +475,7 @@ impl DatabaseClient {
     pub async fn get_mentions(
         &self,
         user_id: u32,
+        ranked_only: bool,
         start: u32,
         limit: u32,
     ) -> Result<Vec<Influence>, AppError> {
@@ -244,17 +492,22 @@ impl DatabaseClient {
                     in.groups as user.groups,
                     in.ranked_and_approved_beatmapset_count 
                         + in.guest_beatmapset_count as user.ranked_maps,
-                    COUNT(<-user<-influenced_by) as user.mentions,
+                    in.ranked_mapper as user.ranked_mapper,
+                    in.mentions as user.mentions,
+                    count(in->influenced_by) as user.influences,
                     in.previous_usernames as user.previous_usernames,
                     influence_type,
-                    description
+                    description,
+                    tag
                 FROM $thing<-influenced_by 
-                ORDER BY user.mentions DESC
+                WHERE $ranked_only = false OR in.ranked_mapper = true
+                ORDER BY user.mentions DESC, user.id ASC
                 START $start
                 LIMIT $limit
                 ",
             )
             .bind(("thing", numerical_thing("user", user_id)))
+            .bind(("ranked_only", ranked_only))
             .bind(("limit", limit))
             .bind(("start", start))
             .await?
@@ -262,4 +515,147 @@ impl DatabaseClient {
 
         Ok(influences)
     }
+
+    /// Fails with [`AppError::MissingUser`] if the user doesn't exist, so it can be told apart from
+    /// a user without influences
+    pub async fn get_influence_count(
+        &self,
+        user_id: u32,
+        ranked_only: bool,
+        has_beatmap: Option<u32>,
+    ) -> Result<u32, AppError> {
+        let count: Option<u32> = self
+            .db
+            .query(
+                "
+                SELECT VALUE count(
+                    ->influenced_by[WHERE ($ranked_only = false OR out.ranked_mapper = true)
+                        AND ($has_beatmap = none OR beatmaps CONTAINS $has_beatmap)]
+                ) 
+                FROM ONLY $thing
+                ",
+            )
+            .bind(("thing", numerical_thing("user", user_id)))
+            .bind(("ranked_only", ranked_only))
+            .bind(("has_beatmap", has_beatmap))
+            .await?
+            .take(0)?;
+        count.ok_or(AppError::MissingUser(user_id))
+    }
+
+    /// Fails with [`AppError::MissingUser`] if the user doesn't exist, same as
+    /// [`DatabaseClient::get_influence_count`]
+    pub async fn get_mention_count(
+        &self,
+        user_id: u32,
+        ranked_only: bool,
+    ) -> Result<u32, AppError> {
+        let count: Option<u32> = self
+            .db
+            .query(
+                "
+                SELECT VALUE count(
+                    <-influenced_by[WHERE $ranked_only = false OR in.ranked_mapper = true]
+                ) 
+                FROM ONLY $thing
+                ",
+            )
+            .bind(("thing", numerical_thing("user", user_id)))
+            .bind(("ranked_only", ranked_only))
+            .await?
+            .take(0)?;
+        count.ok_or(AppError::MissingUser(user_id))
+    }
+
+    /// Breadth first traversal of `->influenced_by->` from the user, one query per level. Every
+    /// user is expanded once, at its shallowest depth. Stops adding nodes after `max_nodes`
+    pub async fn get_influence_tree(
+        &self,
+        user_id: u32,
+        max_depth: u8,
+        max_nodes: usize,
+    ) -> Result<InfluenceTree, AppError> {
+        let root: Option<InfluenceTreeNode> = self
+            .db
+            .query(
+                "
+                SELECT 
+                    meta::id(id) AS id,
+                    username,
+                    avatar_url,
+                    none AS influence_type,
+                    false AS repeated,
+                    [] AS influences
+                FROM ONLY $thing
+                ",
+            )
+            .bind(("thing", numerical_thing("user", user_id)))
+            .await?
+            .take(0)?;
+        let root = root.ok_or(AppError::MissingUser(user_id))?;
+
+        let mut nodes = vec![Some(root)];
+        let mut children: Vec<Vec<usize>> = vec![Vec::new()];
+        let mut visited = HashSet::from([user_id]);
+        // User id and node index of the users that are expanded in the next level
+        let mut frontier = vec![(user_id, 0)];
+        let mut truncated = false;
+
+        for _ in 0..max_depth {
+            if frontier.is_empty() || truncated {
+                break;
+            }
+            let parents: Vec<Thing> = frontier
+                .iter()
+                .map(|(user_id, _)| numerical_thing("user", *user_id))
+                .collect();
+            let edges: Vec<InfluenceTreeEdge> = self
+                .db
+                .query(
+                    "
+                    SELECT 
+                        meta::id(in) AS parent,
+                        meta::id(out) AS id,
+                        out.username AS username,
+                        out.avatar_url AS avatar_url,
+                        influence_type,
+                        order
+                    FROM influenced_by
+                    WHERE in IN $parents
+                    ORDER BY order
+                    ",
+                )
+                .bind(("parents", parents))
+                .await?
+                .take(0)?;
+
+            let parent_indexes: HashMap<u32, usize> = frontier.drain(..).collect();
+            for edge in edges {
+                if nodes.len() >= max_nodes {
+                    truncated = true;
+                    break;
+                }
+                let repeated = !visited.insert(edge.id);
+                let index = nodes.len();
+                nodes.push(Some(InfluenceTreeNode {
+                    id: edge.id,
+                    username: edge.username,
+                    avatar_url: edge.avatar_url,
+                    influence_type: Some(edge.influence_type),
+                    repeated,
+                    influences: Vec::new(),
+                }));
+                children.push(Vec::new());
+                children[parent_indexes[&edge.parent]].push(index);
+                if !repeated {
+                    frontier.push((edge.id, index));
+                }
+            }
+        }
+
+        Ok(InfluenceTree {
+            root: InfluenceTreeNode::nest(0, &mut nodes, &children),
+            truncated,
+        })
+    }
 }
@@ -47,7 +47,9 @@ impl DatabaseClient {
                     out.groups as user.groups,
                     out.ranked_and_approved_beatmapset_count 
                         + out.guest_beatmapset_count as user.ranked_maps,
-                    count(out<-influenced_by) as user.mentions,
+                    out.ranked_mapper as user.ranked_mapper,
+                    out.mentions as user.mentions,
+                    count(out->influenced_by) as user.influences,
                     out.previous_usernames as user.previous_usernames
                 FROM 
                     (SELECT 
@@ -58,8 +60,8 @@ impl DatabaseClient {
                     GROUP BY out 
                     ORDER BY count DESC
                     )
-                WHERE $country = none or out.country_code = $country
-                ORDER count DESC
+                WHERE ($country = none or out.country_code = $country) AND out.deleted != true
+                ORDER count DESC, user.id ASC
                 LIMIT $limit
                 START $start;
                 ",
@@ -73,6 +75,32 @@ impl DatabaseClient {
         Ok(leaderboard)
     }
 
+    /// Total amount of users in the user leaderboard with the same filters
+    pub async fn user_leaderboard_count(
+        &self,
+        country: Option<String>,
+        ranked: bool,
+    ) -> Result<u32, AppError> {
+        let count: Option<u32> = self
+            .db
+            .query(
+                "
+                RETURN count(
+                    (SELECT out 
+                    FROM influenced_by 
+                    WHERE $ranked_only = false OR in.ranked_mapper = true 
+                    GROUP BY out
+                    )[WHERE ($country = none or out.country_code = $country) AND out.deleted != true]
+                );
+                ",
+            )
+            .bind(("country", country))
+            .bind(("ranked_only", ranked))
+            .await?
+            .take(0)?;
+        Ok(count.unwrap_or(0))
+    }
+
     pub async fn beatmap_leaderboard(
         &self,
         ranked: bool,
@@ -101,7 +129,7 @@ impl DatabaseClient {
                     )
                     GROUP BY beatmap
                 )
-                ORDER BY count DESC
+                ORDER BY count DESC, beatmap ASC
                 START $start
                 LIMIT $limit;
                 ",
@@ -113,4 +141,24 @@ impl DatabaseClient {
             .take(0)?;
         Ok(leaderboard)
     }
+    /// Total amount of distinct beatmaps in the beatmap leaderboard with the same filters
+    pub async fn beatmap_leaderboard_count(&self, ranked: bool) -> Result<u32, AppError> {
+        let count: Option<u32> = self
+            .db
+            .query(
+                "
+                RETURN count(array::distinct(
+                    (
+                        SELECT VALUE beatmaps
+                        FROM influenced_by
+                        WHERE $ranked_only = false OR <-user.ranked_mapper.at(0) = true
+                    ).flatten()
+                ));
+                ",
+            )
+            .bind(("ranked_only", ranked))
+            .await?
+            .take(0)?;
+        Ok(count.unwrap_or(0))
+    }
 }
@@ -0,0 +1,76 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use surrealdb_migrations::MigrationRunner;
+
+use crate::error::AppError;
+
+use super::DatabaseClient;
+
+/// Migration scripts folder. This should match the path in `.surrealdb` config file
+const MIGRATION_SCRIPTS_PATH: &str = "migrations/migrations";
+
+/// `AppliedMigration` type
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct AppliedMigration {
+    pub script_name: String,
+    pub executed_at: String,
+}
+
+/// `MigrationStatus` type. Schema and event definitions are applied on every migration run, so
+/// only the migration scripts are listed here.
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct MigrationStatus {
+    pub applied: Vec<AppliedMigration>,
+    pub pending: Vec<String>,
+}
+
+/// Names of the migration scripts on disk, without `.surql` extension. Down migrations are in a
+/// separate folder so they are not included.
+fn local_migration_scripts() -> Result<Vec<String>, AppError> {
+    let mut scripts = Vec::new();
+    for entry in std::fs::read_dir(MIGRATION_SCRIPTS_PATH)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(script_name) = entry.file_name().to_string_lossy().strip_suffix(".surql") {
+            scripts.push(script_name.to_string());
+        }
+    }
+    scripts.sort();
+    Ok(scripts)
+}
+
+impl DatabaseClient {
+    /// Applies schemas, events and pending migration scripts
+    pub async fn run_migrations(&self) -> Result<(), AppError> {
+        MigrationRunner::new(&self.db)
+            .up()
+            .await
+            .map_err(|error| AppError::Migration(error.to_string()))
+    }
+
+    pub async fn get_migration_status(&self) -> Result<MigrationStatus, AppError> {
+        let applied: Vec<AppliedMigration> = MigrationRunner::new(&self.db)
+            .list()
+            .await
+            .map_err(|error| AppError::Migration(error.to_string()))?
+            .into_iter()
+            .map(|migration| AppliedMigration {
+                script_name: migration.script_name,
+                executed_at: migration.executed_at,
+            })
+            .collect();
+
+        let pending = local_migration_scripts()?
+            .into_iter()
+            .filter(|script_name| {
+                !applied
+                    .iter()
+                    .any(|migration| &migration.script_name == script_name)
+            })
+            .collect();
+
+        Ok(MigrationStatus { applied, pending })
+    }
+}
@@ -1,32 +1,92 @@
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use surrealdb::{
     engine::remote::ws::{Client, Ws, Wss},
-    opt::auth::Root,
+    opt::{auth::Root, Config},
     sql::{Id, Thing},
     Surreal,
 };
 
-use crate::error::AppError;
+use crate::{env::number_from_env, error::AppError, retry::Retryable};
 
 pub mod activity;
+pub mod audit;
+pub mod auth;
 pub mod graph_vizualizer;
 pub mod influence;
 pub mod leaderboard;
+pub mod migration;
+pub mod report;
+mod tls;
 pub mod user;
 
 pub struct DatabaseClient {
     db: Surreal<Client>,
+    namespace: String,
+    database: String,
+}
+
+/// Retries the initial connection. Database might not be ready yet if it's started together with
+/// the backend.
+struct DatabaseConnector {
+    url: String,
+    namespace: String,
+    database: String,
+    tls_config: Option<rustls::ClientConfig>,
+}
+
+#[async_trait]
+impl Retryable<Arc<DatabaseClient>, AppError> for DatabaseConnector {
+    async fn retry(&mut self) -> Result<Arc<DatabaseClient>, AppError> {
+        DatabaseClient::connect(self).await
+    }
 }
 
 impl DatabaseClient {
+    /// Uses `SURREAL_NS` and `SURREAL_DB` environment variables for namespace and database names.
+    /// Both of them default to `prod`.
     pub async fn new(url: &str) -> Result<Arc<DatabaseClient>, AppError> {
-        let client = if url.starts_with("wss://") {
-            Surreal::new::<Wss>(
-                url.strip_prefix("wss://")
-                    .expect("starts_with ensures this"),
+        let namespace = std::env::var("SURREAL_NS").unwrap_or_else(|_| "prod".to_string());
+        let database = std::env::var("SURREAL_DB").unwrap_or_else(|_| "prod".to_string());
+        DatabaseClient::new_with_names(url, &namespace, &database).await
+    }
+
+    /// Waits until the database is reachable. Cooldown between attempts is capped at 30 seconds.
+    /// Gives up after `SURREAL_CONNECT_ATTEMPTS` attempts, 10 by default, so wrong credentials or
+    /// urls fail the startup instead of retrying forever
+    pub async fn new_with_names(
+        url: &str,
+        namespace: &str,
+        database: &str,
+    ) -> Result<Arc<DatabaseClient>, AppError> {
+        let mut connector = DatabaseConnector {
+            url: url.to_string(),
+            namespace: namespace.to_string(),
+            database: database.to_string(),
+            tls_config: tls::tls_config_from_env(),
+        };
+        connector
+            .retry_with_limit(
+                30,
+                number_from_env("SURREAL_CONNECT_ATTEMPTS", 10),
+                "Failed to connect to database",
             )
-            .await?
+            .await
+    }
+
+    async fn connect(connector: &DatabaseConnector) -> Result<Arc<DatabaseClient>, AppError> {
+        let url = &connector.url;
+        let client = if url.starts_with("wss://") {
+            let address = url
+                .strip_prefix("wss://")
+                .expect("starts_with ensures this");
+            match connector.tls_config.clone() {
+                Some(tls_config) => {
+                    Surreal::new::<Wss>((address, Config::new().rustls(tls_config))).await?
+                }
+                None => Surreal::new::<Wss>(address).await?,
+            }
         } else if url.starts_with("ws://") {
             Surreal::new::<Ws>(url.strip_prefix("ws://").expect("starts_with ensures this")).await?
         } else {
@@ -41,9 +101,25 @@ impl DatabaseClient {
                     .expect("Missing SURREAL_PASS envrionment variable"),
             })
             .await?;
-        client.use_ns("prod").use_db("prod").await?;
-        Ok(Arc::new(DatabaseClient { db: client }))
+        client
+            .use_ns(&connector.namespace)
+            .use_db(&connector.database)
+            .await?;
+        Ok(Arc::new(DatabaseClient {
+            db: client,
+            namespace: connector.namespace.clone(),
+            database: connector.database.clone(),
+        }))
     }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
     pub fn get_inner_ref(&self) -> &Surreal<Client> {
         &self.db
     }
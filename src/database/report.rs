@@ -0,0 +1,158 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::{Datetime, Id, Thing};
+
+use crate::error::AppError;
+
+use super::DatabaseClient;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ReportTargetType {
+    User,
+    Influence,
+}
+
+/// `Report` type
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Report {
+    pub id: String,
+    pub reporter: u32,
+    pub target_type: ReportTargetType,
+    /// Reported user. For influences, this is the user that added the influence
+    pub target_id: u32,
+    /// Influenced user. Only set for `INFLUENCE` reports
+    pub influenced_to: Option<u32>,
+    pub reason: String,
+    #[schemars(with = "chrono::DateTime<chrono::Utc>")]
+    pub created_at: Datetime,
+    #[schemars(with = "Option<chrono::DateTime<chrono::Utc>>")]
+    pub resolved_at: Option<Datetime>,
+    /// Id of the admin that resolved the report
+    pub resolved_by: Option<u32>,
+}
+
+impl DatabaseClient {
+    fn report_return_string() -> &'static str {
+        "
+        meta::id(id) AS id,
+        reporter,
+        target_type,
+        target_id,
+        influenced_to,
+        reason,
+        created_at,
+        resolved_at,
+        resolved_by
+        "
+    }
+
+    /// Fails with [`AppError::TooManyReports`] if the reporter already filed `limit` reports in
+    /// the last hour. The count is checked in the same transaction, so parallel requests can't go
+    /// over it
+    pub async fn create_report(
+        &self,
+        reporter: u32,
+        target_type: ReportTargetType,
+        target_id: u32,
+        influenced_to: Option<u32>,
+        reason: String,
+        limit: u32,
+    ) -> Result<Report, AppError> {
+        let mut response = self
+            .db
+            .query(format!(
+                "
+                BEGIN TRANSACTION;
+                LET $recent = count(
+                    SELECT id FROM report
+                    WHERE reporter = $reporter AND created_at > time::now() - 1h
+                );
+                IF $recent < $limit {{
+                    CREATE ONLY report
+                    SET reporter = $reporter,
+                        target_type = $target_type,
+                        target_id = $target_id,
+                        influenced_to = $influenced_to,
+                        reason = $reason
+                    RETURN {};
+                }};
+                COMMIT TRANSACTION;
+                ",
+                Self::report_return_string()
+            ))
+            .bind(("reporter", reporter))
+            .bind(("target_type", target_type))
+            .bind(("target_id", target_id))
+            .bind(("influenced_to", influenced_to))
+            .bind(("reason", reason))
+            .bind(("limit", limit))
+            .await?;
+        let last_statement = response.num_statements() - 1;
+        let report: Option<Report> = response.take(last_statement)?;
+        // Nothing is created when the limit is reached
+        report.ok_or(AppError::TooManyReports(limit))
+    }
+
+    /// Oldest reports first, so the ones that waited the longest are handled first
+    pub async fn get_reports(
+        &self,
+        resolved: bool,
+        start: u32,
+        limit: u32,
+    ) -> Result<Vec<Report>, AppError> {
+        let reports: Vec<Report> = self
+            .db
+            .query(format!(
+                "
+                SELECT {}
+                FROM report
+                WHERE (resolved_at != NONE) = $resolved
+                ORDER BY created_at ASC
+                START $start
+                LIMIT $limit
+                ",
+                Self::report_return_string()
+            ))
+            .bind(("resolved", resolved))
+            .bind(("start", start))
+            .bind(("limit", limit))
+            .await?
+            .take(0)?;
+        Ok(reports)
+    }
+
+    pub async fn get_report_count(&self, resolved: bool) -> Result<u32, AppError> {
+        let count: Option<u32> = self
+            .db
+            .query("RETURN count(SELECT id FROM report WHERE (resolved_at != NONE) = $resolved)")
+            .bind(("resolved", resolved))
+            .await?
+            .take(0)?;
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Resolving an already resolved report keeps the first resolution
+    pub async fn resolve_report(
+        &self,
+        report_id: String,
+        admin_id: u32,
+    ) -> Result<Report, AppError> {
+        let report: Option<Report> = self
+            .db
+            .query(format!(
+                "
+                UPDATE $report
+                SET resolved_at = resolved_at ?? time::now(),
+                    resolved_by = resolved_by ?? $admin_id
+                RETURN {}
+                ",
+                Self::report_return_string()
+            ))
+            .bind(("report", Thing::from(("report", Id::String(report_id)))))
+            .bind(("admin_id", admin_id))
+            .await?
+            .take(0)?;
+        report.ok_or(AppError::MissingReport)
+    }
+}
@@ -0,0 +1,128 @@
+use std::{fs::File, io::BufReader, sync::Arc};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+/// Builds a TLS config for `wss://` connections from environment variables. Returns `None` if
+/// none of them are set so that SurrealDB client uses its secure defaults.
+///
+/// - `SURREAL_CA_CERT`: PEM file of a private CA. Replaces the public root certificates.
+/// - `SURREAL_CLIENT_CERT` and `SURREAL_CLIENT_KEY`: PEM files for client certificate auth. Setting
+///   only one of them fails the startup.
+/// - `SURREAL_TLS_INSECURE`: skips certificate verification. Only meant for internal development
+///   setups with self signed certificates.
+pub fn tls_config_from_env() -> Option<ClientConfig> {
+    let ca_cert = std::env::var("SURREAL_CA_CERT").ok();
+    let client_cert = std::env::var("SURREAL_CLIENT_CERT").ok();
+    let client_key = std::env::var("SURREAL_CLIENT_KEY").ok();
+    let insecure =
+        std::env::var("SURREAL_TLS_INSECURE").is_ok_and(|value| value.to_lowercase() == "true");
+
+    // Checked before the early return, otherwise a lone key would be silently ignored
+    assert!(
+        client_cert.is_some() == client_key.is_some(),
+        "SURREAL_CLIENT_CERT and SURREAL_CLIENT_KEY should be set together"
+    );
+    if ca_cert.is_none() && client_cert.is_none() && !insecure {
+        return None;
+    }
+
+    let builder = if insecure {
+        tracing::warn!("TLS certificate verification for SurrealDB is disabled");
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoCertificateVerification(provider)))
+    } else {
+        let mut root_store = RootCertStore::empty();
+        match &ca_cert {
+            Some(path) => {
+                for cert in read_certs(path) {
+                    root_store
+                        .add(cert)
+                        .expect("Invalid certificate in SURREAL_CA_CERT");
+                }
+            }
+            None => root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        ClientConfig::builder().with_root_certificates(root_store)
+    };
+
+    let config = match (client_cert, client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let key = rustls_pemfile::private_key(&mut open_pem(&key_path))
+                .expect("Failed to read SURREAL_CLIENT_KEY")
+                .expect("SURREAL_CLIENT_KEY doesn't contain a private key");
+            builder
+                .with_client_auth_cert(read_certs(&cert_path), key)
+                .expect("Invalid client certificate or key")
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Some(config)
+}
+
+fn open_pem(path: &str) -> BufReader<File> {
+    let file =
+        File::open(path).unwrap_or_else(|error| panic!("Failed to open {}: {}", path, error));
+    BufReader::new(file)
+}
+
+fn read_certs(path: &str) -> Vec<CertificateDer<'static>> {
+    rustls_pemfile::certs(&mut open_pem(path))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|error| panic!("Failed to read certificates from {}: {}", path, error))
+}
+
+/// Accepts every server certificate. Signatures are still checked so that the handshake is valid
+#[derive(Debug)]
+struct NoCertificateVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
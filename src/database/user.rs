@@ -3,7 +3,11 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use surrealdb::sql::Thing;
+use surrealdb::{
+    engine::remote::ws::Client,
+    method::Query,
+    sql::{Datetime, Thing},
+};
 
 use crate::{
     error::AppError,
@@ -11,7 +15,7 @@ use crate::{
     retry::Retryable,
 };
 
-use super::{numerical_thing, DatabaseClient};
+use super::{activity::LAST_ACTIVE_SUBQUERY, numerical_thing, DatabaseClient};
 
 /// Full `User` type that has all the information. For user profile usage.
 #[derive(Serialize, Deserialize, JsonSchema, Debug)]
@@ -31,15 +35,28 @@ pub struct User {
     pub loved_beatmapset_count: u32,
     pub graveyard_beatmapset_count: u32,
     pub pending_beatmapset_count: u32,
+    pub ranked_mapper: bool,
     #[schemars(with = "Vec<BeatmapsetSmall>")]
     pub beatmaps: Vec<BeatmapEnum>,
     /// This will have a number if the data is coming from database.
     /// If the data comes from osu! API, then this will be null
     pub mentions: Option<u32>,
+    /// Null if the data comes from osu! API
+    #[schemars(with = "Option<chrono::DateTime<chrono::Utc>>")]
+    pub created_at: Option<Datetime>,
+    /// Last time the user data is refreshed from osu! API. Null if the data comes from osu! API
+    #[schemars(with = "Option<chrono::DateTime<chrono::Utc>>")]
+    pub updated_at: Option<Datetime>,
+    /// Latest activity of the user other than logging in. Null if the user has no activities or
+    /// the data comes from osu! API
+    #[serde(default)]
+    #[schemars(with = "Option<chrono::DateTime<chrono::Utc>>")]
+    pub last_active: Option<Datetime>,
 }
 
 impl From<UserOsu> for User {
     fn from(user_osu: UserOsu) -> Self {
+        let ranked_mapper = user_osu.is_ranked_mapper();
         User {
             id: user_osu.id,
             username: user_osu.username,
@@ -56,8 +73,12 @@ impl From<UserOsu> for User {
             loved_beatmapset_count: user_osu.loved_beatmapset_count,
             graveyard_beatmapset_count: user_osu.graveyard_beatmapset_count,
             pending_beatmapset_count: user_osu.pending_beatmapset_count,
+            ranked_mapper,
             beatmaps: Vec::new(),
             mentions: None,
+            created_at: None,
+            updated_at: None,
+            last_active: None,
         }
     }
 }
@@ -73,14 +94,20 @@ pub struct UserSmall {
     pub country_name: String,
     /// This is the sum of ranked_and_approved_beatmapset and guest_beatmapset counts
     pub ranked_maps: u32,
+    /// Users with ranked, loved or guest difficulties. Used for the ranked mapper badge
+    pub ranked_mapper: bool,
     /// This will have a number if the data is coming from database.
     /// If the data comes from osu! API, then this will be null
     pub mentions: Option<u32>,
+    /// Amount of users this user added as influence. Same as `mentions`, this will be null if the
+    /// data comes from osu! API
+    pub influences: Option<u32>,
     pub previous_usernames: Vec<String>,
 }
 
 impl From<UserOsu> for UserSmall {
     fn from(user: UserOsu) -> Self {
+        let ranked_mapper = user.is_ranked_mapper();
         UserSmall {
             id: user.id,
             username: user.username,
@@ -89,7 +116,9 @@ impl From<UserOsu> for UserSmall {
             country_code: user.country.code,
             country_name: user.country.name,
             ranked_maps: user.ranked_and_approved_beatmapset_count + user.guest_beatmapset_count,
+            ranked_mapper,
             mentions: None,
+            influences: None,
             previous_usernames: user.previous_usernames,
         }
     }
@@ -132,72 +161,92 @@ impl Default for ActivityPreferences {
     }
 }
 
+/// `MentionDiscrepancy` type. A user whose stored mention counter doesn't match the actual
+/// amount of mentions
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MentionDiscrepancy {
+    pub id: u32,
+    pub stored: Option<u32>,
+    pub actual: u32,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct DbUserId {
     pub id: u32,
 }
 
+/// Upserts `$thing` with the parameters from [`bind_user_details`]. Shared with the queries that
+/// need to upsert a user in the same transaction as other statements
+pub(super) const UPSERT_USER_STATEMENT: &str = "
+    UPSERT $thing 
+    SET 
+        username = $username,
+        avatar_url = $avatar_url,
+        ranked_mapper = $ranked_maps,
+        country_code = $country_code,
+        country_name = $country_name,
+        groups = $groups,
+        previous_usernames = $previous_usernames,
+        ranked_and_approved_beatmapset_count = $ranked_and_approved_beatmapset_count,
+        ranked_beatmapset_count = $ranked_beatmapset_count,
+        nominated_beatmapset_count = $nominated_beatmapset_count,
+        guest_beatmapset_count = $guest_beatmapset_count,
+        loved_beatmapset_count = $loved_beatmapset_count,
+        graveyard_beatmapset_count = $graveyard_beatmapset_count,
+        pending_beatmapset_count = $pending_beatmapset_count,
+        deleted = false;
+    ";
+
+/// Binds the user as `$thing` and their osu! data for [`UPSERT_USER_STATEMENT`]
+pub(super) fn bind_user_details(
+    query: Query<'_, Client>,
+    user_details: UserOsu,
+) -> Query<'_, Client> {
+    let ranked_mapper = user_details.is_ranked_mapper();
+    query
+        .bind(("thing", numerical_thing("user", user_details.id)))
+        .bind(("username", user_details.username))
+        .bind(("avatar_url", user_details.avatar_url))
+        .bind(("ranked_maps", ranked_mapper))
+        .bind(("country_code", user_details.country.code))
+        .bind(("country_name", user_details.country.name))
+        .bind(("groups", user_details.groups))
+        .bind(("previous_usernames", user_details.previous_usernames))
+        .bind((
+            "ranked_and_approved_beatmapset_count",
+            user_details.ranked_and_approved_beatmapset_count,
+        ))
+        .bind((
+            "ranked_beatmapset_count",
+            user_details.ranked_beatmapset_count,
+        ))
+        .bind((
+            "nominated_beatmapset_count",
+            user_details.nominated_beatmapset_count,
+        ))
+        .bind((
+            "guest_beatmapset_count",
+            user_details.guest_beatmapset_count,
+        ))
+        .bind((
+            "loved_beatmapset_count",
+            user_details.loved_beatmapset_count,
+        ))
+        .bind((
+            "graveyard_beatmapset_count",
+            user_details.graveyard_beatmapset_count,
+        ))
+        .bind((
+            "pending_beatmapset_count",
+            user_details.pending_beatmapset_count,
+        ))
+}
+
 impl DatabaseClient {
+    /// Creates the user or updates their osu! data. `created_at` is only set when the user is
+    /// created, so the first seen time is kept through daily updates
     pub async fn upsert_user(&self, user_details: UserOsu) -> Result<(), AppError> {
-        let ranked_mapper = user_details.is_ranked_mapper();
-        self.db
-            .query(
-                r#"
-                UPSERT $thing 
-                SET 
-                    username = $username,
-                    avatar_url = $avatar_url,
-                    ranked_mapper = $ranked_maps,
-                    country_code = $country_code,
-                    country_name = $country_name,
-                    groups = $groups,
-                    previous_usernames = $previous_usernames,
-                    ranked_and_approved_beatmapset_count = $ranked_and_approved_beatmapset_count,
-                    ranked_beatmapset_count = $ranked_beatmapset_count,
-                    nominated_beatmapset_count = $nominated_beatmapset_count,
-                    guest_beatmapset_count = $guest_beatmapset_count,
-                    loved_beatmapset_count = $loved_beatmapset_count,
-                    graveyard_beatmapset_count = $graveyard_beatmapset_count,
-                    pending_beatmapset_count = $pending_beatmapset_count;
-                "#,
-            )
-            .bind(("thing", numerical_thing("user", user_details.id)))
-            .bind(("username", user_details.username))
-            .bind(("avatar_url", user_details.avatar_url))
-            .bind(("ranked_maps", ranked_mapper))
-            .bind(("country_code", user_details.country.code))
-            .bind(("country_name", user_details.country.name))
-            .bind(("groups", user_details.groups))
-            .bind(("previous_usernames", user_details.previous_usernames))
-            .bind((
-                "ranked_and_approved_beatmapset_count",
-                user_details.ranked_and_approved_beatmapset_count,
-            ))
-            .bind((
-                "ranked_beatmapset_count",
-                user_details.ranked_beatmapset_count,
-            ))
-            .bind((
-                "nominated_beatmapset_count",
-                user_details.nominated_beatmapset_count,
-            ))
-            .bind((
-                "guest_beatmapset_count",
-                user_details.guest_beatmapset_count,
-            ))
-            .bind((
-                "loved_beatmapset_count",
-                user_details.loved_beatmapset_count,
-            ))
-            .bind((
-                "graveyard_beatmapset_count",
-                user_details.graveyard_beatmapset_count,
-            ))
-            .bind((
-                "pending_beatmapset_count",
-                user_details.pending_beatmapset_count,
-            ))
-            .await?;
+        bind_user_details(self.db.query(UPSERT_USER_STATEMENT), user_details).await?;
         Ok(())
     }
 
@@ -209,8 +258,9 @@ impl DatabaseClient {
         Ok(())
     }
 
-    fn single_user_return_string(&self) -> &str {
-        "
+    fn single_user_return_string(&self) -> String {
+        format!(
+            "
         meta::id(id) as id,
         username,
         avatar_url,
@@ -227,8 +277,14 @@ impl DatabaseClient {
         loved_beatmapset_count,
         graveyard_beatmapset_count,
         pending_beatmapset_count,
-        count(<-influenced_by) as mentions
-        "
+        ranked_mapper,
+        created_at,
+        updated_at,
+        mentions,
+        {} as last_active
+        ",
+            LAST_ACTIVE_SUBQUERY
+        )
     }
 
     pub async fn update_bio(&self, user_id: u32, bio: String) -> Result<User, AppError> {
@@ -254,7 +310,7 @@ impl DatabaseClient {
         let user: Option<User> = self
             .db
             .query(format!(
-                "UPDATE $thing SET beatmaps += $beatmap_ids RETURN {}",
+                "UPDATE $thing SET beatmaps = array::union(beatmaps, $beatmap_ids) RETURN {}",
                 self.single_user_return_string()
             ))
             .bind(("thing", numerical_thing("user", user_id)))
@@ -283,6 +339,8 @@ impl DatabaseClient {
         user.ok_or(AppError::MissingUser(user_id))
     }
 
+    /// Order and `updated_at` are updated in one transaction, a failed order update doesn't bump
+    /// `updated_at`
     pub async fn set_influence_order(&self, user_id: u32, order: &[u32]) -> Result<(), AppError> {
         let enumerated_array: Vec<(u32, u32)> = order
             .iter()
@@ -292,20 +350,129 @@ impl DatabaseClient {
         self.db
             .query(
                 r#"
+                BEGIN TRANSACTION;
                 FOR $order in $order_array{
                     UPDATE $thing->influenced_by SET order = $order.at(0) 
                     WHERE out = type::thing("user", $order.at(1));
-                }
+                };
+                UPDATE $thing SET updated_at = time::now();
+                COMMIT TRANSACTION;
                 "#,
             )
             .bind(("thing", numerical_thing("user", user_id)))
             .bind(("order_array", enumerated_array))
-            .query("UPDATE $thing SET updated_at = time::now()")
-            .bind(("thing", numerical_thing("user", user_id)))
-            .await?;
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Moves the influences, mentions and profile beatmaps of `source_id` to `target_id` and
+    /// deletes `source_id`, all in one transaction. Influences that `target_id` already has and
+    /// the ones between the two users are dropped instead of moved. Moved influences create
+    /// activities like newly added ones since events can't tell them apart. Activities of `source`
+    /// and the ones that mention it are deleted, they can't be read once `source` is gone
+    pub async fn merge_users(&self, source_id: u32, target_id: u32) -> Result<(), AppError> {
+        // Statement errors are only returned from `check`, there is nothing to `take` here
+        self.db
+            .query(
+                r#"
+                BEGIN TRANSACTION;
+
+                FOR $edge IN (
+                    SELECT * FROM $source->influenced_by
+                    WHERE out != $target 
+                        AND out NOTINSIDE (SELECT VALUE out FROM $target->influenced_by)
+                ) {
+                    LET $influenced = $edge.out;
+                    RELATE $target->influenced_by->$influenced
+                    SET 
+                        description = $edge.description,
+                        influence_type = $edge.influence_type,
+                        beatmaps = $edge.beatmaps,
+                        tag = $edge.tag,
+                        order = $edge.order;
+                };
+
+                FOR $edge IN (
+                    SELECT * FROM $source<-influenced_by
+                    WHERE in != $target 
+                        AND in NOTINSIDE (SELECT VALUE in FROM $target<-influenced_by)
+                ) {
+                    LET $influencer = $edge.in;
+                    RELATE $influencer->influenced_by->$target
+                    SET 
+                        description = $edge.description,
+                        influence_type = $edge.influence_type,
+                        beatmaps = $edge.beatmaps,
+                        tag = $edge.tag,
+                        order = $edge.order,
+                        pinned = $edge.pinned;
+                };
+
+                UPDATE $target SET beatmaps = array::union(beatmaps, $source.beatmaps);
+                DELETE $source->influenced_by, $source<-influenced_by;
+                DELETE activity WHERE user = $source OR influence.out = $source;
+                DELETE $source;
+
+                COMMIT TRANSACTION;
+                "#,
+            )
+            .bind(("source", numerical_thing("user", source_id)))
+            .bind(("target", numerical_thing("user", target_id)))
+            .await?
+            .check()?;
         Ok(())
     }
 
+    /// Deletes the influences and mentions of the user and clears their bio and beatmaps, all in
+    /// one transaction. Activities of the user and the ones that mention them are deleted too,
+    /// including the removals the events create for this purge. Details of the user's audit logs
+    /// and reasons of their reports are cleared, the entries themselves are kept. With
+    /// `anonymize`, the osu! profile fields are replaced and the user is marked deleted. Logging
+    /// in again brings the osu! profile back, but not the removed data.
+    ///
+    /// Returns the database time at the end of the purge. Activities created before it belong to
+    /// the purge
+    pub async fn purge_user(&self, user_id: u32, anonymize: bool) -> Result<Datetime, AppError> {
+        let mut response = self
+            .db
+            .query(
+                r#"
+                BEGIN TRANSACTION;
+
+                DELETE $thing->influenced_by, $thing<-influenced_by;
+                UPDATE $thing SET bio = "", beatmaps = [];
+                IF $anonymize {
+                    UPDATE $thing 
+                    SET 
+                        username = "Deleted user",
+                        avatar_url = $guest_avatar_url,
+                        previous_usernames = [],
+                        groups = [],
+                        deleted = true;
+                };
+                DELETE activity WHERE user = $thing OR influence.out = $thing;
+                UPDATE audit_log SET details = NONE WHERE actor = $user_id;
+                UPDATE report SET reason = "" WHERE reporter = $user_id;
+                RETURN time::now();
+
+                COMMIT TRANSACTION;
+                "#,
+            )
+            .bind(("thing", numerical_thing("user", user_id)))
+            .bind(("user_id", user_id))
+            .bind(("anonymize", anonymize))
+            .bind((
+                "guest_avatar_url",
+                "https://osu.ppy.sh/images/layout/avatar-guest.png",
+            ))
+            .await?
+            .check()?;
+        let last_statement = response.num_statements() - 1;
+        let purged_at: Option<Datetime> = response.take(last_statement)?;
+        purged_at.ok_or(AppError::MissingUser(user_id))
+    }
+
     pub async fn get_user_details(&self, user_id: u32) -> Result<User, AppError> {
         let user: Option<User> = self
             .db
@@ -320,6 +487,27 @@ impl DatabaseClient {
         user.ok_or(AppError::MissingUser(user_id))
     }
 
+    /// Beatmap ids the user credited on their profile and in all of their influences, without
+    /// duplicates
+    pub async fn get_all_credited_beatmaps(&self, user_id: u32) -> Result<Vec<u32>, AppError> {
+        let beatmaps: Option<Vec<u32>> = self
+            .db
+            .query(
+                "
+                SELECT VALUE array::union(
+                    beatmaps, 
+                    array::flatten(->influenced_by.beatmaps)
+                ) 
+                FROM ONLY $thing
+                ",
+            )
+            .bind(("thing", numerical_thing("user", user_id)))
+            .await?
+            .take(0)?;
+
+        beatmaps.ok_or(AppError::MissingUser(user_id))
+    }
+
     pub async fn get_multiple_user_details(
         &self,
         user_ids: &[u32],
@@ -341,7 +529,9 @@ impl DatabaseClient {
                     groups,
                     ranked_and_approved_beatmapset_count 
                         + guest_beatmapset_count as ranked_maps,
-                    count(<-influenced_by) as mentions,
+                    ranked_mapper,
+                    mentions,
+                    count(->influenced_by) as influences,
                     previous_usernames
                 FROM $things;
                 ",
@@ -352,6 +542,95 @@ impl DatabaseClient {
         Ok(users)
     }
 
+    /// Users that logged in at least once, newest first. Users that are only added as influences
+    /// are left out. `created_at` is the first time the user is seen, which can be before they
+    /// logged in if someone added them as influence earlier
+    pub async fn get_recent_users(
+        &self,
+        start: u32,
+        limit: u32,
+    ) -> Result<Vec<UserSmall>, AppError> {
+        let users: Vec<UserSmall> = self
+            .db
+            .query(
+                "
+                SELECT 
+                    meta::id(id) as id,
+                    username,
+                    avatar_url,
+                    country_code,
+                    country_name,
+                    groups,
+                    ranked_and_approved_beatmapset_count 
+                        + guest_beatmapset_count as ranked_maps,
+                    ranked_mapper,
+                    mentions,
+                    count(->influenced_by) as influences,
+                    previous_usernames,
+                    created_at
+                FROM user
+                WHERE authenticated = true AND deleted != true
+                ORDER BY created_at DESC, id ASC
+                START $start
+                LIMIT $limit
+                ",
+            )
+            .bind(("start", start))
+            .bind(("limit", limit))
+            .await?
+            .take(0)?;
+        Ok(users)
+    }
+
+    pub async fn get_recent_user_count(&self) -> Result<u32, AppError> {
+        let count: Option<u32> = self
+            .db
+            .query(
+                "RETURN count(SELECT id FROM user WHERE authenticated = true AND deleted != true)",
+            )
+            .await?
+            .take(0)?;
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Case insensitive search in current and previous usernames. Most mentioned users come first
+    pub async fn search_users_by_name(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<UserSmall>, AppError> {
+        // osu! usernames can't have commas, joining previous usernames with them is safe
+        let users: Vec<UserSmall> = self
+            .db
+            .query(
+                "
+                SELECT 
+                    meta::id(id) as id,
+                    username,
+                    avatar_url,
+                    country_code,
+                    country_name,
+                    groups,
+                    ranked_and_approved_beatmapset_count 
+                        + guest_beatmapset_count as ranked_maps,
+                    ranked_mapper,
+                    mentions,
+                    count(->influenced_by) as influences,
+                    previous_usernames
+                FROM user
+                WHERE string::lowercase(username) CONTAINS $query
+                    OR string::lowercase(array::join(previous_usernames, ',')) CONTAINS $query
+                ORDER BY mentions DESC, id ASC
+                LIMIT $limit
+                ",
+            )
+            .bind(("query", query.to_lowercase()))
+            .bind(("limit", limit))
+            .await?
+            .take(0)?;
+        Ok(users)
+    }
+
     pub async fn set_activity_preferences(
         &self,
         user_id: u32,
@@ -387,10 +666,58 @@ impl DatabaseClient {
         Ok(preference_wrapper.activity_preferences)
     }
 
+    pub async fn get_mention_discrepancies(&self) -> Result<Vec<MentionDiscrepancy>, AppError> {
+        let discrepancies: Vec<MentionDiscrepancy> = self
+            .db
+            .query(
+                "
+                SELECT 
+                    meta::id(id) AS id, 
+                    mentions AS stored, 
+                    count(<-influenced_by) AS actual 
+                FROM user 
+                WHERE mentions != count(<-influenced_by)
+                ",
+            )
+            .await?
+            .take(0)?;
+        Ok(discrepancies)
+    }
+
+    /// Recomputes the counter instead of using the values from
+    /// [`DatabaseClient::get_mention_discrepancies`], since mentions might change in between
+    pub async fn reconcile_mentions(&self, user_ids: &[u32]) -> Result<(), AppError> {
+        let things: Vec<Thing> = user_ids
+            .iter()
+            .map(|user_id| numerical_thing("user", *user_id))
+            .collect();
+        self.db
+            .query("UPDATE $things SET mentions = count(<-influenced_by)")
+            .bind(("things", things))
+            .await?;
+        Ok(())
+    }
+
+    /// For the users that are deleted or restricted on osu!. Influences and mentions are kept
+    /// as is, the flag is cleared the next time the user is upserted
+    pub async fn mark_user_deleted(&self, user_id: u32) -> Result<(), AppError> {
+        self.db
+            .query("UPDATE $thing SET deleted = true")
+            .bind(("thing", numerical_thing("user", user_id)))
+            .await?;
+        Ok(())
+    }
+
+    /// Deleted users are left out, see [`DatabaseClient::mark_user_deleted`]
     pub async fn get_users_to_update(&self) -> Result<Vec<u32>, AppError> {
         let ids: Vec<DbUserId> = self
             .db
-            .query("SELECT meta::id(id) as id FROM user WHERE updated_at + 1w < time::now()")
+            .query(
+                "
+                SELECT meta::id(id) as id FROM user
+                WHERE updated_at + 1w < time::now() AND deleted != true
+                ",
+            )
             .await?
             .take(0)?;
 
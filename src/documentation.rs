@@ -1,10 +1,46 @@
 //! Custom documentation types and wrappers
 
+use aide::{
+    openapi::{ApiKeyLocation, SecurityScheme},
+    transform::{TransformOpenApi, TransformOperation},
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{database::user::UserSmall, osu_api::BeatmapsetSmall};
 
+/// Name of the `user_token` cookie security scheme
+pub const USER_TOKEN_SCHEME: &str = "UserToken";
+
+/// Declares the `user_token` cookie that [`crate::handlers::auth::check_jwt_token`] reads.
+/// Used with `finish_api_with`
+pub fn security_schemes(api: TransformOpenApi) -> TransformOpenApi {
+    api.security_scheme(
+        USER_TOKEN_SCHEME,
+        SecurityScheme::ApiKey {
+            location: ApiKeyLocation::Cookie,
+            name: "user_token".to_string(),
+            description: Some(
+                "Set by osu! login. `/oauth/admin` returns a token with the `admin` role for \
+                testing"
+                    .to_string(),
+            ),
+            extensions: Default::default(),
+        },
+    )
+}
+
+/// For the operations behind [`crate::handlers::auth::check_jwt_token`]
+pub fn authenticated(op: TransformOperation) -> TransformOperation {
+    op.security_requirement(USER_TOKEN_SCHEME)
+}
+
+/// For the operations behind [`crate::handlers::auth::check_admin`]. OpenAPI 3.1 allows roles in
+/// place of the scopes for non OAuth2 schemes
+pub fn admin_only(op: TransformOperation) -> TransformOperation {
+    op.security_requirement_scopes(USER_TOKEN_SCHEME, ["admin"])
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct FlattenedActivityType {
     pub event_type: EventType,
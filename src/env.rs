@@ -0,0 +1,17 @@
+//! Parsing helpers for numeric environment variables. Unset variables fall back to the default,
+//! invalid values panic since they are only read at startup
+
+use std::{str::FromStr, time::Duration};
+
+pub(crate) fn number_from_env<T: FromStr>(variable: &str, default: T) -> T {
+    match std::env::var(variable) {
+        Ok(value) => value
+            .parse::<T>()
+            .unwrap_or_else(|_| panic!("{} environment variable is not a valid number", variable)),
+        Err(_) => default,
+    }
+}
+
+pub(crate) fn seconds_from_env(variable: &str, default: u64) -> Duration {
+    Duration::from_secs(number_from_env(variable, default))
+}
@@ -1,7 +1,11 @@
-use std::num::ParseIntError;
+use std::{any::Any, num::ParseIntError};
 
 use aide::OperationIo;
-use axum::{http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::Serialize;
 use thiserror::Error;
 
@@ -19,9 +23,15 @@ pub enum AppError {
     #[error("Jwt verification error")]
     JwtVerification,
 
+    #[error("Token has been revoked")]
+    RevokedToken,
+
     #[error("Wrong admin password")]
     WrongAdminPassword,
 
+    #[error("This endpoint is only available to admins")]
+    NotAdmin,
+
     #[error("Mutex error")]
     Mutex,
 
@@ -44,6 +54,12 @@ pub enum AppError {
     #[error("Map with id {0} could not be found on osu! API")]
     NonExistingMap(u32),
 
+    #[error("Beatmapset with id {0} could not be found on osu! API")]
+    NonExistingMapset(u32),
+
+    #[error("User with id {0} could not be found on osu! API")]
+    OsuUserNotFound(u32),
+
     #[error("Tokio task error: {0}")]
     TaskJoin(#[from] tokio::task::JoinError),
 
@@ -56,14 +72,83 @@ pub enum AppError {
     #[error("Unhandled Reqwest Error: {0}")]
     Reqwest(#[from] reqwest::Error),
 
+    #[error("Upstream request timed out")]
+    UpstreamTimeout,
+
+    #[error("osu! API responded with status {0}")]
+    UpstreamStatus(u16),
+
+    #[error("OAuth state is missing or doesn't match")]
+    InvalidOAuthState,
+
+    #[error("Redirect to {0} is not allowed")]
+    RedirectNotAllowed(String),
+
+    #[error("Failed to encode header value: {0}")]
+    HeaderEncode(#[from] http::header::InvalidHeaderValue),
+
     #[error("Failed to decode json text: {0}")]
     SerdeJson(#[from] serde_json::Error),
 
     #[error("Unhandled Jwt error: {0}")]
     Jwt(#[from] jwt_simple::Error),
 
-    #[error("Input string exceeds maximum length")]
-    StringTooLong,
+    #[error("Input string exceeds maximum length of {0}")]
+    StringTooLong(usize),
+
+    #[error("Invalid influence type: {0}")]
+    InvalidInfluenceType(u8),
+
+    #[error("Too many users. Maximum is {0}")]
+    TooManyUsers(usize),
+
+    #[error("Too many beatmaps. Maximum is {0}")]
+    TooManyBeatmaps(usize),
+
+    #[error("Beatmaps are not in the expected mode: {0:?}")]
+    WrongBeatmapMode(Vec<u32>),
+
+    #[error("Influence limit of {0} is reached")]
+    InfluenceLimitReached(u32),
+
+    #[error("User {0} is already added as influence")]
+    InfluenceAlreadyExists(u32),
+
+    #[error("Idempotency-Key header should be a visible ASCII string of 1 to 255 characters")]
+    InvalidIdempotencyKey,
+
+    #[error("A request with the same Idempotency-Key is still being processed")]
+    IdempotencyKeyInUse,
+
+    #[error("Idempotency-Key is already used with a different request body")]
+    IdempotencyKeyReused,
+
+    #[error("Request body is larger than {0} bytes")]
+    RequestBodyTooLarge(usize),
+
+    #[error("Failed to read response body: {0}")]
+    ResponseBody(#[from] axum::Error),
+
+    #[error("Missing report")]
+    MissingReport,
+
+    #[error("Influence reports need `influenced_to`")]
+    InvalidReportTarget,
+
+    #[error("Report reason can't be empty")]
+    EmptyReportReason,
+
+    #[error("Report limit of {0} per hour is reached")]
+    TooManyReports(u32),
+
+    #[error("Too many rejected admin logins, try again in a minute")]
+    TooManyAdminLogins,
+
+    #[error("Can't merge a user into itself")]
+    SelfMerge,
+
+    #[error("Unknown schema type {0}")]
+    UnknownSchemaType(String),
 
     #[error("Std IO error: {0}")]
     StdIO(#[from] std::io::Error),
@@ -73,17 +158,36 @@ pub enum AppError {
 
     #[error("Parse int: {0}")]
     ParseInt(#[from] ParseIntError),
+
+    #[error("Migration error: {0}")]
+    Migration(String),
 }
 
 #[derive(Serialize)]
 struct ErrorMessage {
     message: String,
+    /// Only set for [`AppError::StringTooLong`], so the frontend can show the limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_length: Option<usize>,
+    /// Only set for [`AppError::WrongBeatmapMode`], so the frontend can mark the beatmaps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    beatmap_ids: Option<Vec<u32>>,
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        let max_length = match self {
+            AppError::StringTooLong(max_length) => Some(max_length),
+            _ => None,
+        };
+        let beatmap_ids = match &self {
+            AppError::WrongBeatmapMode(beatmap_ids) => Some(beatmap_ids.clone()),
+            _ => None,
+        };
         let body = Json(ErrorMessage {
             message: self.to_string(),
+            max_length,
+            beatmap_ids,
         });
         let status_code = match self {
             AppError::UnhandledDb(_)
@@ -98,17 +202,68 @@ impl IntoResponse for AppError {
             | AppError::SurrealDbSerialization(_)
             | AppError::StdIO(_)
             | AppError::ActivityPreferencesQuery
+            | AppError::Migration(_)
+            | AppError::HeaderEncode(_)
+            | AppError::ResponseBody(_)
             | AppError::SephomoreError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::MissingTokenCookie
             | AppError::JwtVerification
-            | AppError::WrongAdminPassword => StatusCode::UNAUTHORIZED,
-            AppError::MissingLayerJson | AppError::StringTooLong | AppError::ParseInt(_) => {
-                StatusCode::UNPROCESSABLE_ENTITY
+            | AppError::RevokedToken
+            | AppError::WrongAdminPassword
+            | AppError::InvalidOAuthState => StatusCode::UNAUTHORIZED,
+            AppError::MissingLayerJson
+            | AppError::StringTooLong(_)
+            | AppError::ParseInt(_)
+            | AppError::InvalidInfluenceType(_)
+            | AppError::TooManyUsers(_)
+            | AppError::TooManyBeatmaps(_)
+            | AppError::WrongBeatmapMode(_)
+            | AppError::InfluenceLimitReached(_)
+            | AppError::InvalidIdempotencyKey
+            | AppError::IdempotencyKeyReused
+            | AppError::InvalidReportTarget
+            | AppError::EmptyReportReason
+            | AppError::SelfMerge
+            | AppError::RedirectNotAllowed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::MissingInfluence
+            | AppError::MissingReport
+            | AppError::UnknownSchemaType(_)
+            | AppError::MissingUser(_)
+            | Self::NonExistingMap(_)
+            | Self::NonExistingMapset(_)
+            | Self::OsuUserNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::NotAdmin => StatusCode::FORBIDDEN,
+            AppError::IdempotencyKeyInUse | AppError::InfluenceAlreadyExists(_) => {
+                StatusCode::CONFLICT
             }
-            AppError::MissingInfluence | AppError::MissingUser(_) | Self::NonExistingMap(_) => {
-                StatusCode::NOT_FOUND
+            AppError::TooManyReports(_) | AppError::TooManyAdminLogins => {
+                StatusCode::TOO_MANY_REQUESTS
             }
+            AppError::RequestBodyTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UpstreamStatus(_) => StatusCode::BAD_GATEWAY,
+            AppError::UpstreamTimeout => StatusCode::GATEWAY_TIMEOUT,
         };
         (status_code, body).into_response()
     }
 }
+
+/// Used by `CatchPanicLayer`. Logs the panic and turns it into a 500 response with the same
+/// body shape as [`AppError`] responses. Runs inside the request span, so the log line has the
+/// request id
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let details = if let Some(message) = panic.downcast_ref::<String>() {
+        message.as_str()
+    } else if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else {
+        "unknown panic payload"
+    };
+    tracing::error!("Handler panicked: {}", details);
+
+    let body = Json(ErrorMessage {
+        message: "Internal server error".to_string(),
+        max_length: None,
+        beatmap_ids: None,
+    });
+    (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
+}
@@ -1,23 +1,33 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
     net::SocketAddr,
-    sync::{Arc, Mutex as StdMutex, MutexGuard},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock, Mutex as StdMutex, MutexGuard,
+    },
 };
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
+        ws::{close_code, CloseFrame, Message, WebSocket},
         ConnectInfo, State, WebSocketUpgrade,
     },
-    response::Response,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
     Json,
 };
-use futures::{SinkExt, StreamExt};
+use futures::{
+    stream::{self, BoxStream},
+    SinkExt, StreamExt,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use surrealdb::{method::QueryStream, sql::Datetime, Action, Notification};
 use tokio::sync::{
-    broadcast::{self, Receiver, Sender},
+    broadcast::{self, error::RecvError, Receiver, Sender},
     Mutex,
 };
 
@@ -113,38 +123,219 @@ impl ActivityType {
             _ => {}
         }
     }
+
+    /// The other user of influence activities
+    pub fn influence_user_id(&self) -> Option<u32> {
+        match self {
+            ActivityType::AddInfluence { influence }
+            | ActivityType::RemoveInfluence { influence }
+            | ActivityType::AddInfluenceBeatmap { influence, .. }
+            | ActivityType::RemoveInfluenceBeatmap { influence, .. }
+            | ActivityType::EditInfluenceDesc { influence, .. }
+            | ActivityType::EditInfluenceType { influence, .. } => Some(influence.id),
+            _ => None,
+        }
+    }
+}
+
+/// How many beatmap additions to influences a user can have in the activity queue
+const MAX_INFLUENCE_BEATMAP_ACTIVITIES: usize = 3;
+
+/// Decides if the new activity should be shown based on the activities that are already in
+/// the queue. Returns false for the activities that would spam the queue.
+///
+/// Only one activity per user is kept for bio edits and user beatmap additions. Additions or
+/// edits of the same influence are shown once. Adding a beatmap to an influence is hidden if the
+/// same beatmap is already shown for that influence, or if the user already has
+/// [`MAX_INFLUENCE_BEATMAP_ACTIVITIES`] of them in the queue, whichever influence they belong to.
+/// Logins and removals are never shown.
+pub fn should_show_activity(queue: &VecDeque<Activity>, new_activity: &Activity) -> bool {
+    match &new_activity.activity_type {
+        ActivityType::EditBio { .. } => !queue.iter().any(|old_activity| {
+            new_activity.user.id == old_activity.user.id
+                && matches!(old_activity.activity_type, ActivityType::EditBio { .. })
+        }),
+        ActivityType::AddUserBeatmap { .. } => {
+            let matched = queue.iter().any(|old_activity| {
+                new_activity.user.id == old_activity.user.id
+                    && matches!(
+                        &old_activity.activity_type,
+                        ActivityType::AddUserBeatmap { .. }
+                    )
+            });
+            !matched
+        }
+
+        ActivityType::AddInfluence {
+            influence: new_influence,
+        } => {
+            let matched = queue.iter().any(|old_activity| {
+                new_activity.user.id == old_activity.user.id
+                    && match &old_activity.activity_type {
+                        ActivityType::AddInfluence {
+                            influence: old_influence,
+                        }
+                        | ActivityType::EditInfluenceDesc {
+                            influence: old_influence,
+                            ..
+                        }
+                        | ActivityType::EditInfluenceType {
+                            influence: old_influence,
+                            ..
+                        } => new_influence.id == old_influence.id,
+                        _ => false,
+                    }
+            });
+            !matched
+        }
+        ActivityType::EditInfluenceDesc {
+            influence: new_influence,
+            ..
+        }
+        | ActivityType::EditInfluenceType {
+            influence: new_influence,
+            ..
+        } => {
+            let matched = queue.iter().any(|old_activity| {
+                new_activity.user.id == old_activity.user.id
+                    && match &old_activity.activity_type {
+                        ActivityType::AddInfluence {
+                            influence: old_influence,
+                        }
+                        | ActivityType::EditInfluenceDesc {
+                            influence: old_influence,
+                            ..
+                        }
+                        | ActivityType::EditInfluenceType {
+                            influence: old_influence,
+                            ..
+                        } => new_influence.id == old_influence.id,
+
+                        _ => false,
+                    }
+            });
+            !matched
+        }
+        ActivityType::AddInfluenceBeatmap {
+            influence: new_influence,
+            ..
+        } => {
+            let new_beatmap_id = new_activity.activity_type.get_beatmap_id();
+            let mut shown_count = 0;
+            for old_activity in queue
+                .iter()
+                .filter(|old_activity| new_activity.user.id == old_activity.user.id)
+            {
+                let ActivityType::AddInfluenceBeatmap {
+                    influence: old_influence,
+                    ..
+                } = &old_activity.activity_type
+                else {
+                    continue;
+                };
+                let is_same_beatmap = new_influence.id == old_influence.id
+                    && new_beatmap_id == old_activity.activity_type.get_beatmap_id();
+                shown_count += 1;
+                // The limit is checked for every influence, not only for other beatmaps of the
+                // same influence
+                if is_same_beatmap || shown_count >= MAX_INFLUENCE_BEATMAP_ACTIVITIES {
+                    return false;
+                }
+            }
+            true
+        }
+        _ => false,
+    }
 }
 
 pub struct ActivityTracker {
     activity_queue: StdMutex<VecDeque<Activity>>,
     queue_size: u8,
+    enabled: bool,
     activity_broadcaster: Sender<String>,
+    lag_stats: Arc<LagStats>,
+    /// Users whose data is being purged, with the end time of the purge once it's done. `None`
+    /// while the purge is running
+    purged_users: StdMutex<HashMap<u32, Option<Datetime>>>,
     cached_combined_requester: Arc<CombinedRequester>,
     credentials_grant_client: Arc<CredentialsGrantClient>,
 }
 
 impl ActivityTracker {
+    /// When `defer_beatmap_hydration` is true, beatmaps of the initial activities are requested in
+    /// the background instead of blocking the startup. Until that's done, those activities only
+    /// have beatmap ids
     pub async fn new(
         db: Arc<DatabaseClient>,
         queue_size: u8,
         cached_combined_requester: Arc<CombinedRequester>,
         credentials_grant_client: Arc<CredentialsGrantClient>,
+        defer_beatmap_hydration: bool,
     ) -> Result<Arc<ActivityTracker>, AppError> {
-        let (broadcast_sender, _broadcast_receiver) = broadcast::channel(50);
-        let activity_tracker = ActivityTracker {
-            activity_queue: StdMutex::new(VecDeque::new()),
+        let activity_tracker = Arc::new(ActivityTracker::empty(
             queue_size,
-            activity_broadcaster: broadcast_sender,
+            true,
             cached_combined_requester,
             credentials_grant_client,
-        };
-        let activity_tracker = Arc::new(activity_tracker);
+        ));
         activity_tracker.set_initial_activities(&db).await?;
-        activity_tracker.swap_beatmaps().await?;
+        if defer_beatmap_hydration {
+            let cloned_tracker = activity_tracker.clone();
+            tokio::spawn(async move {
+                match cloned_tracker.swap_beatmaps().await {
+                    Ok(()) => tracing::info!("Initial activity beatmaps are hydrated"),
+                    Err(error) => {
+                        tracing::error!("Failed to hydrate initial activity beatmaps: {}", error)
+                    }
+                }
+            });
+        } else {
+            activity_tracker.swap_beatmaps().await?;
+        }
         activity_tracker.clone().start_loop(db).await?;
         Ok(activity_tracker)
     }
 
+    /// Creates a tracker without the live query. Its queue always stays empty, so `/activity`
+    /// returns an empty list and `/ws` connections are closed right after the upgrade
+    pub fn disabled(
+        cached_combined_requester: Arc<CombinedRequester>,
+        credentials_grant_client: Arc<CredentialsGrantClient>,
+    ) -> Arc<ActivityTracker> {
+        Arc::new(ActivityTracker::empty(
+            0,
+            false,
+            cached_combined_requester,
+            credentials_grant_client,
+        ))
+    }
+
+    fn empty(
+        queue_size: u8,
+        enabled: bool,
+        cached_combined_requester: Arc<CombinedRequester>,
+        credentials_grant_client: Arc<CredentialsGrantClient>,
+    ) -> ActivityTracker {
+        // The policy is first used by a connection task. Invalid values should fail the startup
+        // instead of that task
+        LazyLock::force(&LAG_POLICY);
+        let (broadcast_sender, _broadcast_receiver) = broadcast::channel(50);
+        ActivityTracker {
+            activity_queue: StdMutex::new(VecDeque::new()),
+            queue_size,
+            enabled,
+            activity_broadcaster: broadcast_sender,
+            lag_stats: Arc::new(LagStats::default()),
+            purged_users: StdMutex::new(HashMap::new()),
+            cached_combined_requester,
+            credentials_grant_client,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
     pub fn lock_activity_queue(&self) -> Result<MutexGuard<VecDeque<Activity>>, AppError> {
         self.activity_queue.lock().map_err(|_| AppError::Mutex)
     }
@@ -158,6 +349,57 @@ impl ActivityTracker {
         Ok(())
     }
 
+    fn lock_purged_users(&self) -> Result<MutexGuard<HashMap<u32, Option<Datetime>>>, AppError> {
+        self.purged_users.lock().map_err(|_| AppError::Mutex)
+    }
+
+    /// Called before the user's data is purged. Events create activities for the removed data,
+    /// the live query skips them from here on
+    pub fn start_purge(&self, user_id: u32) -> Result<(), AppError> {
+        self.lock_purged_users()?.insert(user_id, None);
+        Ok(())
+    }
+
+    /// Called after the purge with its end time, or `None` if it failed. Only the activities
+    /// created before `purged_at` are skipped after this. The activities of the user and the
+    /// ones that mention them are dropped from the queue too, since it would keep showing them
+    /// until they are pushed out
+    pub fn finish_purge(&self, user_id: u32, purged_at: Option<Datetime>) -> Result<(), AppError> {
+        let Some(purged_at) = purged_at else {
+            self.lock_purged_users()?.remove(&user_id);
+            return Ok(());
+        };
+        self.lock_purged_users()?.insert(user_id, Some(purged_at));
+        self.lock_activity_queue()?.retain(|activity| {
+            activity.user.id != user_id
+                && activity.activity_type.influence_user_id() != Some(user_id)
+        });
+        Ok(())
+    }
+
+    /// Activities that the events created for a purge. A purged user's entry is dropped once a
+    /// newer activity of them comes in, the purge's activities are all delivered before it
+    fn is_created_by_purge(&self, activity: &Activity) -> Result<bool, AppError> {
+        let mut purged_users = self.lock_purged_users()?;
+        let user_ids = [
+            Some(activity.user.id),
+            activity.activity_type.influence_user_id(),
+        ];
+        for user_id in user_ids.into_iter().flatten() {
+            match purged_users.get(&user_id) {
+                None => {}
+                Some(None) => return Ok(true),
+                Some(Some(purged_at)) if activity.created_at <= *purged_at => return Ok(true),
+                Some(Some(_)) => {
+                    if user_id == activity.user.id {
+                        purged_users.remove(&user_id);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
     pub fn get_current_queue(&self) -> Result<Vec<Activity>, AppError> {
         let cloned = { self.lock_activity_queue()?.iter().cloned().collect() };
         Ok(cloned)
@@ -170,93 +412,18 @@ impl ActivityTracker {
         ))
     }
 
-    pub fn spam_prevention(&self, new_activity: &Activity) -> Result<bool, AppError> {
-        let locked_queue = self.lock_activity_queue()?;
+    pub fn lag_stats(&self) -> Arc<LagStats> {
+        self.lag_stats.clone()
+    }
 
-        match &new_activity.activity_type {
-            ActivityType::EditBio { .. } => Ok(!locked_queue.iter().any(|old_activity| {
-                new_activity.user.id == old_activity.user.id
-                    && matches!(old_activity.activity_type, ActivityType::EditBio { .. })
-            })),
-            ActivityType::AddUserBeatmap { .. } => {
-                let matched = locked_queue.iter().any(|old_activity| {
-                    new_activity.user.id == old_activity.user.id
-                        && matches!(
-                            &old_activity.activity_type,
-                            ActivityType::AddUserBeatmap { .. }
-                        )
-                });
-                Ok(!matched)
-            }
+    pub fn broadcast_stats(&self) -> BroadcastStats {
+        self.lag_stats
+            .snapshot(self.activity_broadcaster.receiver_count())
+    }
 
-            ActivityType::AddInfluence {
-                influence: new_influence,
-            } => {
-                let matched = locked_queue.iter().any(|old_activity| {
-                    new_activity.user.id == old_activity.user.id
-                        && match &old_activity.activity_type {
-                            ActivityType::AddInfluence {
-                                influence: old_influence,
-                            }
-                            | ActivityType::EditInfluenceDesc {
-                                influence: old_influence,
-                                ..
-                            }
-                            | ActivityType::EditInfluenceType {
-                                influence: old_influence,
-                                ..
-                            } => new_influence.id == old_influence.id,
-                            _ => false,
-                        }
-                });
-                Ok(!matched)
-            }
-            ActivityType::EditInfluenceDesc {
-                influence: new_influence,
-                ..
-            }
-            | ActivityType::EditInfluenceType {
-                influence: new_influence,
-                ..
-            } => {
-                let matched = locked_queue.iter().any(|old_activity| {
-                    new_activity.user.id == old_activity.user.id
-                        && match &old_activity.activity_type {
-                            ActivityType::AddInfluence {
-                                influence: old_influence,
-                            }
-                            | ActivityType::EditInfluenceDesc {
-                                influence: old_influence,
-                                ..
-                            }
-                            | ActivityType::EditInfluenceType {
-                                influence: old_influence,
-                                ..
-                            } => new_influence.id == old_influence.id,
-
-                            _ => false,
-                        }
-                });
-                Ok(!matched)
-            }
-            ActivityType::AddInfluenceBeatmap {
-                influence: new_influence,
-                ..
-            } => {
-                let matched = locked_queue.iter().any(|old_activity| {
-                    new_activity.user.id == old_activity.user.id
-                        && match &old_activity.activity_type {
-                            ActivityType::AddInfluenceBeatmap {
-                                influence: old_influence,
-                                ..
-                            } => new_influence.id == old_influence.id,
-                            _ => false,
-                        }
-                });
-                Ok(!matched)
-            }
-            _ => Ok(false),
-        }
+    pub fn spam_prevention(&self, new_activity: &Activity) -> Result<bool, AppError> {
+        let locked_queue = self.lock_activity_queue()?;
+        Ok(should_show_activity(&locked_queue, new_activity))
     }
 
     pub async fn set_initial_activities(&self, db: &DatabaseClient) -> Result<(), AppError> {
@@ -299,7 +466,7 @@ impl ActivityTracker {
         let beatmaps = self
             .cached_combined_requester
             .clone()
-            .get_beatmaps_with_user(&beatmaps_to_request, &token)
+            .get_beatmaps_with_user_partial(&beatmaps_to_request, &token)
             .await?;
 
         self.lock_activity_queue()?
@@ -384,6 +551,9 @@ impl ActivityTracker {
                     _ => {}
                 }
 
+                let Ok(false) = cloned_self.is_created_by_purge(&new_activity.data) else {
+                    continue;
+                };
                 let Ok(true) = cloned_self.spam_prevention(&new_activity.data) else {
                     continue;
                 };
@@ -461,24 +631,246 @@ pub async fn ws_handler(
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> Result<Response, AppError> {
+    if !state.activity_tracker.is_enabled() {
+        return Ok(ws.on_upgrade(close_disabled_socket));
+    }
     let (initial_message, broadcast_receiver) = state.activity_tracker.new_connection()?;
-    let upgrade_response = ws
-        .on_upgrade(move |socket| handle_socket(socket, addr, initial_message, broadcast_receiver));
+    let lag_stats = state.activity_tracker.lag_stats();
+    let upgrade_response = ws.on_upgrade(move |socket| {
+        handle_socket(socket, addr, initial_message, broadcast_receiver, lag_stats)
+    });
     Ok(upgrade_response)
 }
 
+pub type ActivityEventStream = BoxStream<'static, Result<Event, Infallible>>;
+
+/// Server-sent events alternative to `/ws`. The first event is `initial` with the current queue,
+/// then every new activity is sent as an `activity` event. The stream ends right away when the
+/// activity stream is disabled
+pub async fn sse_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Sse<ActivityEventStream>, AppError> {
+    if !state.activity_tracker.is_enabled() {
+        return Ok(Sse::new(stream::empty().boxed()));
+    }
+    let (initial_message, broadcast_receiver) = state.activity_tracker.new_connection()?;
+    let lag_stats = state.activity_tracker.lag_stats();
+
+    let initial_event =
+        stream::once(async move { Ok(Event::default().event("initial").data(initial_message)) });
+    // The guard is dropped with the stream when the client disconnects
+    let connection_lag = lag_stats.register(None);
+    let activity_events = stream::unfold(
+        (broadcast_receiver, connection_lag),
+        move |(mut receiver, connection_lag)| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(activity_string) => {
+                        let event = Event::default().event("activity").data(activity_string);
+                        return Some((Ok(event), (receiver, connection_lag)));
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        // There is no close code for SSE, connections always skip
+                        connection_lag
+                            .lag_stats
+                            .record(connection_lag.connection_id, skipped);
+                        tracing::warn!("SSE connection skipped {} activities", skipped);
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        },
+    );
+
+    let event_stream = initial_event.chain(activity_events).boxed();
+    Ok(Sse::new(event_stream).keep_alive(KeepAlive::default()))
+}
+
+async fn close_disabled_socket(mut websocket: WebSocket) {
+    let close_frame = CloseFrame {
+        code: close_code::NORMAL,
+        reason: "Activity stream is disabled".into(),
+    };
+    if let Err(error) = websocket.send(Message::Close(Some(close_frame))).await {
+        tracing::info!("Error while closing activity websocket: {}", error);
+    }
+}
+
+/// Application defined close code for connections that fell behind the broadcast. Clients can
+/// reconnect right away to get a fresh initial message
+pub const LAGGED_CLOSE_CODE: u16 = 4000;
+
+/// What a websocket connection does when it falls behind the broadcast. Set with the
+/// `BROADCAST_LAG_POLICY` environment variable, `close` by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LagPolicy {
+    /// Close the connection with [`LAGGED_CLOSE_CODE`]. Client reconnects and gets a fresh initial
+    /// message, so it doesn't miss anything
+    Close,
+    /// Drop the missed messages and keep the connection open
+    Skip,
+}
+
+static LAG_POLICY: LazyLock<LagPolicy> = LazyLock::new(|| {
+    match std::env::var("BROADCAST_LAG_POLICY")
+        .map(|value| value.to_lowercase())
+        .as_deref()
+    {
+        Err(_) | Ok("close") => LagPolicy::Close,
+        Ok("skip") => LagPolicy::Skip,
+        Ok(value) => panic!(
+            "BROADCAST_LAG_POLICY environment variable should be close or skip, got {}",
+            value
+        ),
+    }
+});
+
+/// Lag counters of a broadcast, shared by all of its connections. Totals count since the server
+/// started, counters of a single connection are only kept while it's open
+#[derive(Default)]
+pub struct LagStats {
+    lag_events: AtomicU64,
+    dropped_messages: AtomicU64,
+    next_connection_id: AtomicU64,
+    connections: StdMutex<HashMap<u64, ConnectionLag>>,
+}
+
+impl LagStats {
+    /// The connection is tracked until the returned guard is dropped
+    fn register(self: &Arc<Self>, address: Option<SocketAddr>) -> ConnectionLagGuard {
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.insert(
+                connection_id,
+                ConnectionLag {
+                    address: address.map(|address| address.to_string()),
+                    lag_events: 0,
+                    dropped_messages: 0,
+                },
+            );
+        }
+        ConnectionLagGuard {
+            lag_stats: self.clone(),
+            connection_id,
+        }
+    }
+
+    fn record(&self, connection_id: u64, skipped: u64) {
+        self.lag_events.fetch_add(1, Ordering::Relaxed);
+        self.dropped_messages.fetch_add(skipped, Ordering::Relaxed);
+        if let Ok(mut connections) = self.connections.lock() {
+            if let Some(connection) = connections.get_mut(&connection_id) {
+                connection.lag_events += 1;
+                connection.dropped_messages += skipped;
+            }
+        }
+    }
+
+    pub fn snapshot(&self, connections: usize) -> BroadcastStats {
+        let lagged_connections = self
+            .connections
+            .lock()
+            .map(|connections| {
+                connections
+                    .values()
+                    .filter(|connection| connection.lag_events > 0)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        BroadcastStats {
+            connections,
+            lag_events: self.lag_events.load(Ordering::Relaxed),
+            dropped_messages: self.dropped_messages.load(Ordering::Relaxed),
+            lagged_connections,
+            lag_policy: *LAG_POLICY,
+        }
+    }
+}
+
+/// Removes the connection from [`LagStats`] once it's closed
+struct ConnectionLagGuard {
+    lag_stats: Arc<LagStats>,
+    connection_id: u64,
+}
+
+impl Drop for ConnectionLagGuard {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.lag_stats.connections.lock() {
+            connections.remove(&self.connection_id);
+        }
+    }
+}
+
+/// `ConnectionLag` type. Lag counters of a single open connection
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ConnectionLag {
+    /// Client address. Missing for SSE connections
+    pub address: Option<String>,
+    pub lag_events: u64,
+    pub dropped_messages: u64,
+}
+
+/// `BroadcastStats` type. Shows whether the clients keep up with the broadcast
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct BroadcastStats {
+    /// Currently open connections
+    pub connections: usize,
+    /// How many times a connection fell behind
+    pub lag_events: u64,
+    /// Messages that were never sent to the connections that fell behind
+    pub dropped_messages: u64,
+    /// Open connections that fell behind at least once, with their own counters
+    pub lagged_connections: Vec<ConnectionLag>,
+    pub lag_policy: LagPolicy,
+}
+
+/// Why the server ended a websocket connection. Sent to the client in the close frame so it can
+/// decide between reconnecting right away and backing off
+#[derive(Debug, Clone, Copy)]
+enum CloseReason {
+    /// Client closed the connection or it's already broken. There is no one to send a frame to
+    ConnectionGone,
+    /// Client couldn't keep up with the broadcast and missed messages
+    Lagged,
+    /// Broadcast channel is closed. Only happens when the server is shutting down or restarting
+    ServerRestarting,
+    /// Reading from the client failed or a connection task panicked
+    ServerError,
+}
+
+impl CloseReason {
+    fn close_frame(self) -> Option<CloseFrame<'static>> {
+        let (code, reason) = match self {
+            CloseReason::ConnectionGone => return None,
+            CloseReason::Lagged => (LAGGED_CLOSE_CODE, "lagged"),
+            CloseReason::ServerRestarting => (close_code::RESTART, "server restarting"),
+            CloseReason::ServerError => (close_code::ERROR, "server error"),
+        };
+        Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })
+    }
+}
+
 // I hope we don't have to manually handle pings. Axum documentation claims that it's done
 // automatically in background. But in my latest project, I had to do it manually since client
 // library was sending ping messages in text format instead of its dedicated message type
 // maybe that's how it's supposed to be? I don't think so but whatever
-async fn handle_socket(
+pub(crate) async fn handle_socket(
     websocket: WebSocket,
     address: SocketAddr,
     initial_data: String,
     mut broadcast_receiver: Receiver<String>,
+    lag_stats: Arc<LagStats>,
 ) {
     let (ws_sender, mut ws_receiver) = websocket.split();
     let ws_sender = Arc::new(Mutex::new(ws_sender));
+    // Kept until this function returns, the broadcast task might outlive the connection
+    let connection_lag = lag_stats.register(Some(address));
+    let connection_id = connection_lag.connection_id;
 
     {
         let mut locked_ws_sender = ws_sender.lock().await;
@@ -507,17 +899,19 @@ async fn handle_socket(
                         address,
                         error
                     );
-                    break;
+                    return CloseReason::ServerError;
                 }
                 None => {
                     tracing::info!("WebSocket connection closed for {}", address);
-                    break;
+                    return CloseReason::ConnectionGone;
                 }
             }
         }
     });
 
     let broadcast_task = tokio::spawn(async move {
+        // Only for the logs, counters are also kept in `lag_stats`
+        let mut dropped_messages = 0;
         loop {
             match broadcast_receiver.recv().await {
                 Ok(new_activity_string) => {
@@ -527,20 +921,44 @@ async fn handle_socket(
                         .await
                     {
                         tracing::error!("Error while sending message to {}: {}", address, error);
-                        break;
+                        return CloseReason::ConnectionGone;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    lag_stats.record(connection_id, skipped);
+                    dropped_messages += skipped;
+                    tracing::warn!(
+                        "Connection {} skipped {} messages, {} in total",
+                        address,
+                        skipped,
+                        dropped_messages
+                    );
+                    if *LAG_POLICY == LagPolicy::Close {
+                        return CloseReason::Lagged;
                     }
                 }
-                Err(error) => {
-                    tracing::error!("Error receiving broadcast message: {}", error);
-                    break;
+                Err(RecvError::Closed) => {
+                    tracing::error!("Broadcast channel closed for {}", address);
+                    return CloseReason::ServerRestarting;
                 }
             }
         }
     });
 
-    tokio::select! {
-        _ = websocket_task => {},
-        _ = broadcast_task => {},
+    let close_reason = tokio::select! {
+        reason = websocket_task => reason,
+        reason = broadcast_task => reason,
+    }
+    .unwrap_or(CloseReason::ServerError);
+
+    if let Some(close_frame) = close_reason.close_frame() {
+        let mut locked_ws_sender = ws_sender.lock().await;
+        if let Err(error) = locked_ws_sender
+            .send(Message::Close(Some(close_frame)))
+            .await
+        {
+            tracing::info!("Error while closing websocket for {}: {}", address, error);
+        }
     }
 }
 
@@ -0,0 +1,303 @@
+use std::{future::Future, sync::Arc, time::Instant};
+
+use axum::{
+    extract::{OriginalUri, Path, Query, State},
+    Extension, Json,
+};
+use futures::try_join;
+use http::HeaderMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{
+        audit::{AuditAction, AuditLog},
+        migration::MigrationStatus,
+        report::Report,
+        user::User,
+    },
+    error::AppError,
+    jwt::AuthData,
+    AppState,
+};
+
+use super::{
+    activity::BroadcastStats,
+    graph_vizualizer::refresh_graph_cache,
+    leaderboard::{refresh_beatmap_leaderboard_cache, refresh_user_leaderboard_cache},
+    pagination_headers, write_audit, PaginationQuery,
+};
+
+/// `WarmedCaches` type. Graph fields are the sizes of the cached graph. Leaderboard fields are the
+/// totals of the leaderboards, only the top entries up to the prefetch size are cached
+#[derive(Serialize, JsonSchema)]
+pub struct WarmedCaches {
+    pub graph_nodes: usize,
+    pub graph_links: usize,
+    pub user_leaderboard: u32,
+    pub ranked_user_leaderboard: u32,
+    pub beatmap_leaderboard: u32,
+    pub ranked_beatmap_leaderboard: u32,
+}
+
+/// `RecomputedLeaderboard` type. `cached_entries` can be less than the prefetch size if the
+/// leaderboard is shorter or osu! API didn't return some of the beatmaps
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct RecomputedLeaderboard {
+    pub total: u32,
+    pub cached_entries: usize,
+    pub duration_ms: u64,
+}
+
+/// `RecomputedLeaderboards` type. Global leaderboards that are computed again without cache
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct RecomputedLeaderboards {
+    pub user_leaderboard: RecomputedLeaderboard,
+    pub ranked_user_leaderboard: RecomputedLeaderboard,
+    pub beatmap_leaderboard: RecomputedLeaderboard,
+    pub ranked_beatmap_leaderboard: RecomputedLeaderboard,
+}
+
+/// `Broadcasts` type. Connection and lag counters of the websocket broadcasts
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Broadcasts {
+    pub activity: BroadcastStats,
+    pub user_leaderboard: BroadcastStats,
+}
+
+/// `AuditLogFilterQuery` type. Filters for the audit log
+#[derive(Deserialize, JsonSchema)]
+pub struct AuditLogFilterQuery {
+    /// Only return the actions of this user
+    actor: Option<u32>,
+    /// Only return the actions on this user
+    target: Option<u32>,
+}
+
+/// `ReportFilterQuery` type. Filters for the report list
+#[derive(Deserialize, JsonSchema)]
+pub struct ReportFilterQuery {
+    /// Return the resolved reports instead of the open ones
+    #[serde(default)]
+    resolved: bool,
+}
+
+/// `MergeUsers` type. `source` is deleted after everything is moved to `target`
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct MergeUsers {
+    pub source: u32,
+    pub target: u32,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PathReportId {
+    #[serde(rename = "report_id")]
+    value: String,
+}
+
+pub async fn get_migration_status(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<MigrationStatus>, AppError> {
+    let status = state.db.get_migration_status().await?;
+    Ok(Json(status))
+}
+
+pub async fn get_broadcast_stats(State(state): State<Arc<AppState>>) -> Json<Broadcasts> {
+    Json(Broadcasts {
+        activity: state.activity_tracker.broadcast_stats(),
+        user_leaderboard: state.user_leaderboard_broadcaster.broadcast_stats(),
+    })
+}
+
+/// Fills the graph and the global leaderboard caches so that the first requests after a
+/// deploy or an import don't have to run the expensive queries
+pub async fn warm_caches(
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<WarmedCaches>, AppError> {
+    let (
+        graph,
+        (_, user_leaderboard),
+        (_, ranked_user_leaderboard),
+        (_, beatmap_leaderboard),
+        (_, ranked_beatmap_leaderboard),
+    ) = try_join!(
+        refresh_graph_cache(&state),
+        refresh_user_leaderboard_cache(&state, false, None, 0, 0),
+        refresh_user_leaderboard_cache(&state, true, None, 0, 0),
+        refresh_beatmap_leaderboard_cache(&state, false, None, 0, 0),
+        refresh_beatmap_leaderboard_cache(&state, true, None, 0, 0),
+    )?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::WarmCaches,
+        None,
+        None,
+    )
+    .await;
+
+    Ok(Json(WarmedCaches {
+        graph_nodes: graph.nodes.len(),
+        graph_links: graph.links.len(),
+        user_leaderboard,
+        ranked_user_leaderboard,
+        beatmap_leaderboard,
+        ranked_beatmap_leaderboard,
+    }))
+}
+
+async fn timed_recompute<T>(
+    name: &str,
+    recompute: impl Future<Output = Result<(Vec<T>, u32), AppError>>,
+) -> Result<RecomputedLeaderboard, AppError> {
+    let started_at = Instant::now();
+    let (leaderboard, total) = recompute.await?;
+    let duration_ms = started_at.elapsed().as_millis() as u64;
+    tracing::info!(
+        "Recomputed {} in {} ms, {} cached entries out of {}",
+        name,
+        duration_ms,
+        leaderboard.len(),
+        total
+    );
+    Ok(RecomputedLeaderboard {
+        total,
+        cached_entries: leaderboard.len(),
+        duration_ms,
+    })
+}
+
+/// Drops every cached leaderboard and runs the global ones again. Unlike [`warm_caches`], the
+/// queries run one by one so the durations aren't affected by each other. Meant for checking the
+/// data after bulk imports
+pub async fn recompute_leaderboards(
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<RecomputedLeaderboards>, AppError> {
+    state.user_leaderboard_cache.clear()?;
+    state.beatmap_leaderboard_cache.clear()?;
+
+    // `u32::MAX` as the limit returns the whole cached part
+    let recomputed = RecomputedLeaderboards {
+        user_leaderboard: timed_recompute(
+            "user leaderboard",
+            refresh_user_leaderboard_cache(&state, false, None, 0, u32::MAX),
+        )
+        .await?,
+        ranked_user_leaderboard: timed_recompute(
+            "ranked user leaderboard",
+            refresh_user_leaderboard_cache(&state, true, None, 0, u32::MAX),
+        )
+        .await?,
+        beatmap_leaderboard: timed_recompute(
+            "beatmap leaderboard",
+            refresh_beatmap_leaderboard_cache(&state, false, None, 0, u32::MAX),
+        )
+        .await?,
+        ranked_beatmap_leaderboard: timed_recompute(
+            "ranked beatmap leaderboard",
+            refresh_beatmap_leaderboard_cache(&state, true, None, 0, u32::MAX),
+        )
+        .await?,
+    };
+    // Connected clients get the fresh leaderboard without waiting for the next tick
+    state.user_leaderboard_broadcaster.refresh(&state).await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::RecomputeLeaderboards,
+        None,
+        None,
+    )
+    .await;
+
+    Ok(Json(recomputed))
+}
+
+pub async fn get_audit_logs(
+    Query(pagination): Query<PaginationQuery>,
+    Query(filter): Query<AuditLogFilterQuery>,
+    OriginalUri(uri): OriginalUri,
+    State(state): State<Arc<AppState>>,
+) -> Result<(HeaderMap, Json<Vec<AuditLog>>), AppError> {
+    let (logs, total) = try_join!(
+        state.db.get_audit_logs(
+            filter.actor,
+            filter.target,
+            pagination.start,
+            pagination.limit
+        ),
+        state.db.get_audit_log_count(filter.actor, filter.target)
+    )?;
+    let headers = pagination_headers(&uri, pagination.start, pagination.limit, total)?;
+    Ok((headers, Json(logs)))
+}
+
+pub async fn get_reports(
+    Query(pagination): Query<PaginationQuery>,
+    Query(filter): Query<ReportFilterQuery>,
+    OriginalUri(uri): OriginalUri,
+    State(state): State<Arc<AppState>>,
+) -> Result<(HeaderMap, Json<Vec<Report>>), AppError> {
+    let (reports, total) = try_join!(
+        state
+            .db
+            .get_reports(filter.resolved, pagination.start, pagination.limit),
+        state.db.get_report_count(filter.resolved)
+    )?;
+    let headers = pagination_headers(&uri, pagination.start, pagination.limit, total)?;
+    Ok((headers, Json(reports)))
+}
+
+pub async fn resolve_report(
+    Path(report_id): Path<PathReportId>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Report>, AppError> {
+    let report = state
+        .db
+        .resolve_report(report_id.value, auth_data.user_id)
+        .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::ResolveReport,
+        Some(report.target_id),
+        Some(report.id.clone()),
+    )
+    .await;
+    Ok(Json(report))
+}
+
+/// For the duplicate users of the same person, like renamed or restricted then recreated osu!
+/// accounts. Returns the target user with the merged beatmaps
+pub async fn merge_users(
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+    Json(merge): Json<MergeUsers>,
+) -> Result<Json<User>, AppError> {
+    if merge.source == merge.target {
+        return Err(AppError::SelfMerge);
+    }
+    try_join!(
+        state.db.get_user_details(merge.source),
+        state.db.get_user_details(merge.target)
+    )?;
+
+    state.db.merge_users(merge.source, merge.target).await?;
+    state
+        .activity_tracker
+        .remove_user_activities(merge.source)?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::MergeUsers,
+        Some(merge.target),
+        Some(merge.source.to_string()),
+    )
+    .await;
+
+    let user = state.db.get_user_details(merge.target).await?;
+    Ok(Json(user))
+}
@@ -4,21 +4,35 @@ use aide::transform::TransformOperation;
 use axum::{
     extract::{Query, Request, State},
     response::{IntoResponse, Redirect, Response},
-    Json,
+    Extension, Json,
 };
 use axum_extra::extract::CookieJar;
 use futures::try_join;
-use http::HeaderMap;
+use http::{header::LOCATION, HeaderMap, HeaderValue, StatusCode};
 use reqwest::header::SET_COOKIE;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{error::AppError, AppState};
+use crate::{
+    database::audit::AuditAction, error::AppError, jwt::AuthData, osu_api::authorize_url, AppState,
+};
+
+use super::write_audit;
 
 static POST_LOGIN_REDIRECT_URI: LazyLock<String> = LazyLock::new(|| {
     std::env::var("POST_LOGIN_REDIRECT_URI")
         .expect("Missing POST_LOGIN_REDIRECT_URI environment variable")
 });
+/// Comma separated list of extra URIs that are allowed as post login redirects. The default
+/// `POST_LOGIN_REDIRECT_URI` is always allowed
+static POST_LOGIN_REDIRECT_ALLOWLIST: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("POST_LOGIN_REDIRECT_ALLOWLIST")
+        .unwrap_or_default()
+        .split(',')
+        .map(|uri| uri.trim().to_string())
+        .filter(|uri| !uri.is_empty())
+        .collect()
+});
 static ADMIN_PASSWORD: LazyLock<String> = LazyLock::new(|| {
     std::env::var("ADMIN_PASSWORD").expect("Missing ADMIN_PASSWORD environment variable")
 });
@@ -29,9 +43,39 @@ static DEPLOY_COOKIE: LazyLock<bool> = LazyLock::new(|| {
     std::env::var("DEPLOY_COOKIE").is_ok_and(|value| value.to_lowercase() == "true")
 });
 
+/// Holds the nonce of the signed OAuth `state` between login initiation and the callback
+const OAUTH_STATE_COOKIE: &str = "oauth_state";
+
+/// Shared by every caller. Keeps wrong password attempts from flooding the audit log
+const MAX_REJECTED_ADMIN_LOGINS_PER_MINUTE: u32 = 10;
+
 #[derive(Deserialize, JsonSchema)]
 pub struct AuthQuery {
     code: String,
+    /// osu! passes the `state` parameter created in `/oauth/login` back as is
+    #[serde(default)]
+    state: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct LoginQuery {
+    /// Post login redirect URI. It has to be in the allowlist. Defaults to
+    /// `POST_LOGIN_REDIRECT_URI`
+    #[serde(default)]
+    redirect: Option<String>,
+}
+
+/// Only exact matches are allowed to avoid open redirects
+fn post_login_redirect_uri(requested: Option<&str>) -> Result<&str, AppError> {
+    match requested {
+        None => Ok(POST_LOGIN_REDIRECT_URI.as_str()),
+        Some(uri) if uri == POST_LOGIN_REDIRECT_URI.as_str() => Ok(uri),
+        Some(uri) => POST_LOGIN_REDIRECT_ALLOWLIST
+            .iter()
+            .find(|allowed| allowed.as_str() == uri)
+            .map(String::as_str)
+            .ok_or_else(|| AppError::RedirectNotAllowed(uri.to_string())),
+    }
 }
 
 #[derive(Serialize, Deserialize, JsonSchema)]
@@ -47,10 +91,46 @@ impl AdminLogin {
     }
 }
 
+/// Starts the login. The signed `state` carries the redirect URI and its nonce is stored in a
+/// cookie, so the callback can only be completed by the browser that started it
+pub async fn osu_oauth2_login(
+    Query(query): Query<LoginQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, AppError> {
+    let redirect_uri = post_login_redirect_uri(query.redirect.as_deref())?;
+    let (oauth_state, nonce) = state.jwt.create_oauth_state(redirect_uri.to_string())?;
+
+    let location = authorize_url(&oauth_state)?;
+    // 302 instead of `Redirect::to`, which is 303
+    let mut redirect_response = (StatusCode::FOUND, [(LOCATION, location)]).into_response();
+    let mut state_cookie_string = format!(
+        "{}={};HttpOnly;Max-Age=600;Path=/;SameSite=lax",
+        OAUTH_STATE_COOKIE, nonce
+    );
+    if *DEPLOY_COOKIE {
+        state_cookie_string += ";Secure;domain=.mapperinfluences.com";
+    }
+    redirect_response
+        .headers_mut()
+        .append(SET_COOKIE, cookie_header_value(&state_cookie_string)?);
+    Ok(redirect_response)
+}
+
 pub async fn osu_oauth2_redirect(
     Query(query_parameters): Query<AuthQuery>,
     State(state): State<Arc<AppState>>,
+    cookie_jar: CookieJar,
 ) -> Result<Response, AppError> {
+    let (Some(oauth_state), Some(nonce_cookie)) = (
+        query_parameters.state.as_deref(),
+        cookie_jar.get(OAUTH_STATE_COOKIE),
+    ) else {
+        return Err(AppError::InvalidOAuthState);
+    };
+    let verified_state = state
+        .jwt
+        .verify_oauth_state(oauth_state, nonce_cookie.value())?;
+
     let auth_response = state
         .request
         .get_osu_auth_token(query_parameters.code)
@@ -65,8 +145,9 @@ pub async fn osu_oauth2_redirect(
         osu_user.username.clone(),
         auth_response.access_token,
         auth_response.expires_in,
+        false,
     )?;
-    let mut redirect_response = Redirect::to(POST_LOGIN_REDIRECT_URI.as_str()).into_response();
+    let mut redirect_response = Redirect::to(&verified_state.redirect_uri).into_response();
     let headers = redirect_response.headers_mut();
     let mut user_token_cookie_string = format!(
         "user_token={};HttpOnly;Max-Age=86400;Path=/;SameSite=lax",
@@ -74,13 +155,19 @@ pub async fn osu_oauth2_redirect(
     );
     let mut logged_in_cookie_string =
         "logged_in=true;Max-Age=86400;Path=/;SameSite=lax".to_string();
+    let mut state_cookie_string = format!(
+        "{}=deleted;HttpOnly;Max-Age=-1;Path=/;SameSite=lax",
+        OAUTH_STATE_COOKIE
+    );
     if *DEPLOY_COOKIE {
         user_token_cookie_string += ";Secure;domain=.mapperinfluences.com";
         logged_in_cookie_string += ";Secure;domain=.mapperinfluences.com";
+        state_cookie_string += ";Secure;domain=.mapperinfluences.com";
     }
 
-    headers.append(SET_COOKIE, user_token_cookie_string.parse().unwrap());
-    headers.append(SET_COOKIE, logged_in_cookie_string.parse().unwrap());
+    headers.append(SET_COOKIE, cookie_header_value(&user_token_cookie_string)?);
+    headers.append(SET_COOKIE, cookie_header_value(&logged_in_cookie_string)?);
+    headers.append(SET_COOKIE, cookie_header_value(&state_cookie_string)?);
 
     // TODO: maybe fix authorized thing to be in the same query later?
     let osu_user_id = osu_user.id;
@@ -96,7 +183,13 @@ pub fn osu_oauth2_redirect_docs(op: TransformOperation<'_>) -> TransformOperatio
     op.tag("Auth").response::<302, ()>()
 }
 
-pub async fn logout() -> Response {
+/// Cookie strings are built from tokens and env values. An invalid header character in any of
+/// those becomes an error instead of a panic
+pub fn cookie_header_value(cookie: &str) -> Result<HeaderValue, AppError> {
+    Ok(HeaderValue::from_str(cookie)?)
+}
+
+pub(super) fn clear_cookie_headers() -> Result<HeaderMap, AppError> {
     let mut headers = HeaderMap::new();
     let mut user_token_cookie_string =
         "user_token=deleted;HttpOnly;Max-Age=-1;path=/;SameSite=lax".to_string();
@@ -105,9 +198,34 @@ pub async fn logout() -> Response {
         user_token_cookie_string += ";Secure;domain=.mapperinfluences.com";
         logged_in_cookie_string += ";Secure;domain=.mapperinfluences.com";
     }
-    headers.append(SET_COOKIE, user_token_cookie_string.parse().unwrap());
-    headers.append(SET_COOKIE, logged_in_cookie_string.parse().unwrap());
-    headers.into_response()
+    headers.append(SET_COOKIE, cookie_header_value(&user_token_cookie_string)?);
+    headers.append(SET_COOKIE, cookie_header_value(&logged_in_cookie_string)?);
+    Ok(headers)
+}
+
+/// Revokes the current token if there is a valid one. Cookies are cleared regardless
+pub async fn logout(
+    State(state): State<Arc<AppState>>,
+    cookie_jar: CookieJar,
+) -> Result<Response, AppError> {
+    if let Some(token_cookie) = cookie_jar.get("user_token") {
+        if let Ok((_, metadata)) = state.jwt.verify_jwt(token_cookie.value()) {
+            state
+                .db
+                .revoke_token(metadata.jwt_id, metadata.expires_at)
+                .await?;
+        }
+    }
+    Ok(clear_cookie_headers()?.into_response())
+}
+
+/// Revokes every token of the user, including the ones on other devices
+pub async fn logout_all(
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, AppError> {
+    state.db.revoke_all_user_tokens(auth_data.user_id).await?;
+    Ok(clear_cookie_headers()?.into_response())
 }
 
 pub async fn check_jwt_token(
@@ -120,23 +238,57 @@ pub async fn check_jwt_token(
         .get("user_token")
         .ok_or(AppError::MissingTokenCookie)?
         .value();
-    let claims = state
+    let (claims, metadata) = state
         .jwt
         .verify_jwt(token)
         .map_err(|_| AppError::JwtVerification)?;
 
+    if state
+        .db
+        .is_token_revoked(
+            claims.user_id,
+            metadata.jwt_id.clone(),
+            metadata.issued_at_ms,
+        )
+        .await?
+    {
+        return Err(AppError::RevokedToken);
+    }
+
     request.extensions_mut().insert(claims);
+    request.extensions_mut().insert(metadata);
+    Ok(next.run(request).await)
+}
+
+/// Needs to run after [`check_jwt_token`] since it depends on its extension
+pub async fn check_admin(
+    Extension(auth_data): Extension<AuthData>,
+    request: Request,
+    next: axum::middleware::Next,
+) -> Result<Response, AppError> {
+    if !auth_data.admin {
+        return Err(AppError::NotAdmin);
+    }
     Ok(next.run(request).await)
 }
 
 /// Easy way to get a premade jwt with internal client credential grant method in it
 ///
-/// This is to make the API testing easier by skipping oauth2 process
+/// This is to make the API testing easier by skipping oauth2 process. Wrong passwords are
+/// audited without an actor, see
+/// [`crate::database::DatabaseClient::write_rejected_admin_login`]
 pub async fn admin_login(
     State(state): State<Arc<AppState>>,
     Json(admin_login): Json<AdminLogin>,
 ) -> Result<String, AppError> {
     if *ADMIN_PASSWORD != admin_login.password {
+        let written = state
+            .db
+            .write_rejected_admin_login(admin_login.id, MAX_REJECTED_ADMIN_LOGINS_PER_MINUTE)
+            .await?;
+        if !written {
+            return Err(AppError::TooManyAdminLogins);
+        }
         return Err(AppError::WrongAdminPassword);
     }
 
@@ -146,11 +298,14 @@ pub async fn admin_login(
         .get_user_osu(&client_credential_token, admin_login.id)
         .await?;
 
+    write_audit(&state.db, osu_user.id, AuditAction::AdminLogin, None, None).await;
+
     // Token can expire earlier than specified here. If that's the case, get a new one.
     state.jwt.create_jwt(
         osu_user.id,
         osu_user.username.clone(),
         client_credential_token,
         84600,
+        true,
     )
 }
@@ -0,0 +1,67 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::LazyLock,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    response::Response,
+};
+use http::HeaderMap;
+
+/// Set this when the server is behind a reverse proxy or a load balancer, otherwise every client
+/// has the proxy's address. Off by default since clients can send these headers themselves
+static TRUST_PROXY_HEADERS: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("TRUST_PROXY_HEADERS").is_ok_and(|value| value.to_lowercase() == "true")
+});
+
+/// Number of proxies in front of the server. Only used when `TRUST_PROXY_HEADERS` is set
+static TRUSTED_PROXY_COUNT: LazyLock<usize> = LazyLock::new(|| {
+    let count = std::env::var("TRUSTED_PROXY_COUNT").map_or(1, |value| {
+        value
+            .parse()
+            .expect("TRUSTED_PROXY_COUNT environment variable is not a valid number")
+    });
+    assert!(
+        count >= 1,
+        "TRUSTED_PROXY_COUNT environment variable should be at least 1"
+    );
+    count
+});
+
+/// Each proxy appends the address it received the request from to `X-Forwarded-For`, so the
+/// client is `trusted_proxy_count` entries from the end. Entries before that are sent by the client
+/// and can't be trusted. Returns `None` if there are fewer entries than proxies or the entry isn't
+/// an address, so the connection address is used. `X-Real-IP` is only read when there is no
+/// `X-Forwarded-For` at all
+pub fn client_ip_from_headers(headers: &HeaderMap, trusted_proxy_count: usize) -> Option<IpAddr> {
+    let forwarded_for: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    if forwarded_for.is_empty() {
+        return headers.get("x-real-ip")?.to_str().ok()?.trim().parse().ok();
+    }
+    let index = forwarded_for.len().checked_sub(trusted_proxy_count)?;
+    forwarded_for[index].parse().ok()
+}
+
+/// Replaces the address in [`ConnectInfo`] with the client address from proxy headers, so the
+/// handlers and logs downstream don't need to know about proxies. Does nothing unless
+/// `TRUST_PROXY_HEADERS` is set
+pub async fn rewrite_client_ip(mut request: Request, next: axum::middleware::Next) -> Response {
+    if *TRUST_PROXY_HEADERS {
+        if let Some(client_ip) = client_ip_from_headers(request.headers(), *TRUSTED_PROXY_COUNT) {
+            if let Some(ConnectInfo(address)) = request
+                .extensions_mut()
+                .get_mut::<ConnectInfo<SocketAddr>>()
+            {
+                address.set_ip(client_ip);
+            }
+        }
+    }
+    next.run(request).await
+}
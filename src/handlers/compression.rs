@@ -0,0 +1,72 @@
+use std::sync::LazyLock;
+
+use axum::{extract::Request, response::Response};
+use http::{header::ACCEPT_ENCODING, HeaderMap, HeaderValue};
+
+/// Enabled compression algorithms out of `gzip`, `deflate`, `zstd` and `br`, in priority order.
+/// Every algorithm is enabled in this order when `COMPRESSION_ALGORITHMS` is not set
+pub static COMPRESSION_ALGORITHMS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    let algorithms: Vec<String> = std::env::var("COMPRESSION_ALGORITHMS")
+        .unwrap_or_else(|_| "gzip,deflate,zstd,br".to_string())
+        .to_lowercase()
+        .split(',')
+        .map(|algorithm| algorithm.trim().to_string())
+        .collect();
+    for algorithm in &algorithms {
+        assert!(
+            ["gzip", "deflate", "zstd", "br"].contains(&algorithm.as_str()),
+            "Unknown compression algorithm in COMPRESSION_ALGORITHMS: {}",
+            algorithm
+        );
+    }
+    algorithms
+});
+
+/// First algorithm in `priority` that the client accepts. An algorithm is accepted if it's
+/// listed in `Accept-Encoding`, or `*` is listed, with a non zero weight. The client's weights
+/// don't change the order otherwise
+pub fn preferred_encoding<'a>(headers: &HeaderMap, priority: &'a [String]) -> Option<&'a str> {
+    let accepted: Vec<(String, bool)> = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let encoding = params.next()?.trim().to_lowercase();
+            let weight = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|weight| weight.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((encoding, weight > 0.0))
+        })
+        .collect();
+    let is_accepted = |encoding: &str| {
+        accepted
+            .iter()
+            .find(|(accepted_encoding, _)| accepted_encoding == encoding)
+            .map(|(_, is_accepted)| *is_accepted)
+    };
+    priority
+        .iter()
+        .find(|algorithm| {
+            is_accepted(algorithm)
+                .or_else(|| is_accepted("*"))
+                .unwrap_or(false)
+        })
+        .map(String::as_str)
+}
+
+/// Replaces `Accept-Encoding` with the preferred algorithm, so the compression layer picks the
+/// configured priority instead of the client's weights. Should be layered outside of it
+pub async fn prioritize_compression(
+    mut request: Request,
+    next: axum::middleware::Next,
+) -> Response {
+    if let Some(encoding) = preferred_encoding(request.headers(), &COMPRESSION_ALGORITHMS) {
+        request
+            .headers_mut()
+            .insert(ACCEPT_ENCODING, HeaderValue::from_static(encoding));
+    }
+    next.run(request).await
+}
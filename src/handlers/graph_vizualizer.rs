@@ -3,9 +3,26 @@ use std::{
     time::{Duration, Instant},
 };
 
-use axum::{extract::State, Json};
+use axum::{
+    body::Body,
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::header::CONTENT_TYPE;
+use serde::Serialize;
+use surrealdb::sql::Thing;
+
+use crate::{
+    database::{
+        graph_vizualizer::{GraphData, GraphInfluence, GraphUser},
+        DatabaseClient,
+    },
+    error::AppError,
+    AppState,
+};
 
-use crate::{database::graph_vizualizer::GraphData, error::AppError, AppState};
+const GRAPH_STREAM_CHUNK_SIZE: u32 = 1000;
 
 pub struct GraphCacheInner {
     pub data: Option<GraphData>,
@@ -52,8 +69,90 @@ pub async fn get_graph_data(
         return Ok(Json(cached_graph));
     }
 
+    let graph_data = refresh_graph_cache(&state).await?;
+    Ok(Json(graph_data))
+}
+
+/// Fetches the whole graph from database and puts it in the cache
+pub async fn refresh_graph_cache(state: &AppState) -> Result<GraphData, AppError> {
     let graph_data = state.db.get_graph_data().await?;
     state.graph_cache.update(graph_data.clone())?;
+    Ok(graph_data)
+}
 
-    Ok(Json(graph_data))
+/// A single line of the graph stream
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum GraphStreamItem {
+    Node(GraphUser),
+    Link(GraphInfluence),
+}
+
+/// Holds the id of the last streamed record, the next chunk starts after it
+enum GraphStreamState {
+    Nodes(Option<Thing>),
+    Links(Option<Thing>),
+    Done,
+}
+
+fn to_json_lines(items: impl Iterator<Item = GraphStreamItem>) -> Result<String, AppError> {
+    let mut lines = String::new();
+    for item in items {
+        lines += &serde_json::to_string(&item)?;
+        lines.push('\n');
+    }
+    Ok(lines)
+}
+
+async fn next_graph_chunk(
+    db: &DatabaseClient,
+    stream_state: GraphStreamState,
+) -> Option<(Result<String, AppError>, GraphStreamState)> {
+    match stream_state {
+        GraphStreamState::Nodes(after) => {
+            let nodes = match db.get_graph_nodes(after, GRAPH_STREAM_CHUNK_SIZE).await {
+                Ok(nodes) => nodes,
+                Err(error) => return Some((Err(error), GraphStreamState::Done)),
+            };
+            let next_state = if nodes.items.len() < GRAPH_STREAM_CHUNK_SIZE as usize {
+                GraphStreamState::Links(None)
+            } else {
+                GraphStreamState::Nodes(nodes.last_id)
+            };
+            let lines = to_json_lines(nodes.items.into_iter().map(GraphStreamItem::Node));
+            Some((lines, next_state))
+        }
+        GraphStreamState::Links(after) => {
+            let links = match db.get_graph_links(after, GRAPH_STREAM_CHUNK_SIZE).await {
+                Ok(links) => links,
+                Err(error) => return Some((Err(error), GraphStreamState::Done)),
+            };
+            let next_state = if links.items.len() < GRAPH_STREAM_CHUNK_SIZE as usize {
+                GraphStreamState::Done
+            } else {
+                GraphStreamState::Links(links.last_id)
+            };
+            let lines = to_json_lines(links.items.into_iter().map(GraphStreamItem::Link));
+            Some((lines, next_state))
+        }
+        GraphStreamState::Done => None,
+    }
+}
+
+/// Streams the graph as newline delimited JSON. All nodes come first, then the links.
+/// Each line has a `type` field that is either `node` or `link`.
+///
+/// Data is requested from the database in chunks and it's not cached, so unlike `/graph`,
+/// nodes and links are not guaranteed to be consistent with each other.
+pub async fn stream_graph_data(State(state): State<Arc<AppState>>) -> Response {
+    let db = state.db.clone();
+    let stream = futures::stream::unfold(GraphStreamState::Nodes(None), move |stream_state| {
+        let db = db.clone();
+        async move { next_graph_chunk(&db, stream_state).await }
+    });
+    (
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(stream),
+    )
+        .into_response()
 }
@@ -0,0 +1,211 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use cached::Cached;
+use http::{header::SET_COOKIE, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+
+use crate::{custom_cache::CustomCache, error::AppError, jwt::AuthData, AppState};
+
+pub static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+/// Set on the responses that are returned from the store instead of running the handler again
+pub static IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+/// Same as the default body limit of axum extractors. Bodies are read here to be hashed
+const MAX_REQUEST_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// User id, method, path and the key. The same key can be used for different endpoints or by
+/// different users without clashing
+type StoreKey = (u32, Method, String, String);
+
+#[derive(Clone)]
+enum StoredResponse {
+    /// The first request with the key is still running
+    InProgress,
+    Completed {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+    },
+}
+
+/// The response, or the in progress marker, with the hash of the request body it belongs to
+#[derive(Clone)]
+struct StoredRequest {
+    body_hash: u64,
+    response: StoredResponse,
+}
+
+fn hash_body(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl StoredResponse {
+    fn replay(self) -> Result<Response, AppError> {
+        match self {
+            StoredResponse::InProgress => Err(AppError::IdempotencyKeyInUse),
+            StoredResponse::Completed {
+                status,
+                mut headers,
+                body,
+            } => {
+                headers.insert(
+                    IDEMPOTENT_REPLAYED.clone(),
+                    HeaderValue::from_static("true"),
+                );
+                Ok((status, headers, body).into_response())
+            }
+        }
+    }
+}
+
+/// Responses of the mutation requests that are sent with an `Idempotency-Key` header. Client
+/// retries of the same request get the stored response instead of adding the same influence or
+/// beatmap again, which would also create duplicate activities
+pub struct IdempotencyStore(Mutex<CustomCache<StoreKey, StoredRequest>>);
+
+impl IdempotencyStore {
+    pub fn new(expire_in: u32) -> Self {
+        IdempotencyStore(Mutex::new(CustomCache::new(expire_in)))
+    }
+
+    /// Marks the key as in progress. Returns the stored response instead if the key is seen
+    /// before, or an error if it's seen with a different body
+    fn start(&self, key: &StoreKey, body_hash: u64) -> Result<Option<StoredResponse>, AppError> {
+        let mut locked_store = self.0.lock().map_err(|_| AppError::Mutex)?;
+        if let Some(stored) = locked_store.cache_get(key) {
+            if stored.body_hash != body_hash {
+                return Err(AppError::IdempotencyKeyReused);
+            }
+            return Ok(Some(stored.response.clone()));
+        }
+        locked_store.cache_set(
+            key.clone(),
+            StoredRequest {
+                body_hash,
+                response: StoredResponse::InProgress,
+            },
+        );
+        Ok(None)
+    }
+
+    fn complete(
+        &self,
+        key: StoreKey,
+        body_hash: u64,
+        response: StoredResponse,
+    ) -> Result<(), AppError> {
+        let mut locked_store = self.0.lock().map_err(|_| AppError::Mutex)?;
+        locked_store.cache_set(
+            key,
+            StoredRequest {
+                body_hash,
+                response,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove(&self, key: &StoreKey) {
+        if let Ok(mut locked_store) = self.0.lock() {
+            locked_store.cache_remove(key);
+        }
+    }
+}
+
+/// Removes the in progress marker if the request doesn't complete. Handler futures are dropped
+/// when the client disconnects, which is exactly when a retry is expected
+struct InProgressGuard<'a> {
+    store: &'a IdempotencyStore,
+    key: Option<StoreKey>,
+    body_hash: u64,
+}
+
+impl InProgressGuard<'_> {
+    fn complete(mut self, response: StoredResponse) -> Result<(), AppError> {
+        let key = self.key.take().expect("key is only taken once");
+        self.store.complete(key, self.body_hash, response)
+    }
+}
+
+impl Drop for InProgressGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.store.remove(&key);
+        }
+    }
+}
+
+/// Needs to run after [`super::auth::check_jwt_token`] since keys are scoped to the user.
+/// Requests without the header and the read-only methods are passed through as is. Only
+/// successful responses are stored, so failed requests can be retried with the same key. Reusing
+/// a key with a different body is rejected instead of replaying the response of the first body.
+/// `Set-Cookie` headers are not stored, replays shouldn't set cookies again
+pub async fn check_idempotency_key(
+    State(state): State<Arc<AppState>>,
+    Extension(auth_data): Extension<AuthData>,
+    request: Request,
+    next: axum::middleware::Next,
+) -> Result<Response, AppError> {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return Ok(next.run(request).await);
+    }
+    let Some(key) = request.headers().get(&IDEMPOTENCY_KEY) else {
+        return Ok(next.run(request).await);
+    };
+    let key = key.to_str().map_err(|_| AppError::InvalidIdempotencyKey)?;
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(AppError::InvalidIdempotencyKey);
+    }
+
+    let store_key = (
+        auth_data.user_id,
+        request.method().clone(),
+        request.uri().path().to_string(),
+        key.to_string(),
+    );
+    let (request_parts, request_body) = request.into_parts();
+    let request_body = to_bytes(request_body, MAX_REQUEST_BODY_SIZE)
+        .await
+        .map_err(|_| AppError::RequestBodyTooLarge(MAX_REQUEST_BODY_SIZE))?;
+    let body_hash = hash_body(&request_body);
+    let request = Request::from_parts(request_parts, Body::from(request_body));
+
+    if let Some(stored) = state.idempotency_store.start(&store_key, body_hash)? {
+        return stored.replay();
+    }
+    let guard = InProgressGuard {
+        store: &state.idempotency_store,
+        key: Some(store_key),
+        body_hash,
+    };
+
+    let response = next.run(request).await;
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await?;
+    let mut stored_headers = parts.headers.clone();
+    stored_headers.remove(SET_COOKIE);
+    guard.complete(StoredResponse::Completed {
+        status: parts.status,
+        headers: stored_headers,
+        body: body.clone(),
+    })?;
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
@@ -1,24 +1,36 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{OriginalUri, Path, Query, State},
     Extension, Json,
 };
-use futures::try_join;
+use futures::{
+    future::{join_all, try_join_all},
+    try_join,
+};
+use http::HeaderMap;
 use itertools::Itertools;
 use schemars::JsonSchema;
-use serde::Deserialize;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, LazyLock},
+};
 
 use crate::{
-    database::influence::Influence,
+    database::{
+        audit::AuditAction,
+        influence::{Influence, InfluenceTree},
+    },
     error::AppError,
     jwt::AuthData,
-    osu_api::{BeatmapEnum, GetID},
+    osu_api::{GameMode, GetID},
+    sanitize::sanitize_user_text,
     AppState,
 };
 
 use super::{
-    check_multiple_maps, swap_beatmaps, BeatmapRequest, PaginationQuery, PathInfluencedTo,
-    PathUserBeatmapIds, PathUserId, PathUserTypeId,
+    check_length, check_multiple_maps, default_limit, filter_new_beatmaps, hydrate_beatmaps,
+    pagination_headers, swap_beatmaps, write_audit, BeatmapRequest, PaginationQuery,
+    PathInfluencedTo, PathUserBeatmapIds, PathUserId, PathUserTypeId, MAX_DESCRIPTION_LENGTH,
 };
 
 #[derive(Deserialize, JsonSchema)]
@@ -26,22 +38,159 @@ pub struct Description {
     description: String,
 }
 
+/// `Tag` type. Null removes the tag
+#[derive(Deserialize, JsonSchema)]
+pub struct Tag {
+    tag: Option<String>,
+}
+
+/// In characters, see [`check_length`]
+const MAX_TAG_LENGTH: usize = 50;
+
+/// Maximum number of influences a single user can add. Protects the graph from bloated users
+static MAX_INFLUENCES_PER_USER: LazyLock<u32> = LazyLock::new(|| {
+    std::env::var("MAX_INFLUENCES_PER_USER").map_or(200, |value| {
+        value
+            .parse()
+            .expect("MAX_INFLUENCES_PER_USER environment variable is not a valid number")
+    })
+});
+
+/// Every user is a separate database query. Keeps a single request from running too many of them
+const MAX_BULK_INFLUENCE_USERS: usize = 50;
+
+/// Status check is a single query, so it allows more users than the bulk influence list
+const MAX_INFLUENCE_STATUS_USERS: usize = 200;
+
+/// Every level of the influence tree is a database query
+const MAX_INFLUENCE_TREE_DEPTH: u8 = 5;
+
+/// Bounds the response size, a few popular users in the first levels can fill the tree quickly
+const MAX_INFLUENCE_TREE_NODES: usize = 500;
+
 /// `InfluenceCreationOptions` type. Optional fields to override defaults
 #[derive(Deserialize, JsonSchema)]
 pub struct InfluenceCreationOptions {
     pub influence_type: Option<u8>,
     pub description: Option<String>,
     pub beatmaps: Option<Vec<u32>>,
+    pub tag: Option<String>,
+    /// Rejects the request if any of `beatmaps` is from another mode
+    pub expected_mode: Option<GameMode>,
     #[serde(alias = "userId")]
     pub user_id: String,
 }
 
+/// `InfluenceTypeUpdate` type. Used in bulk influence type update
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct InfluenceTypeUpdate {
+    pub influenced_to: u32,
+    pub type_id: u8,
+}
+
+/// Influence types are 1: Respect, 2: Fascination, 3: Implementation
+fn validate_influence_type(type_id: u8) -> Result<(), AppError> {
+    if !(1..=3).contains(&type_id) {
+        return Err(AppError::InvalidInfluenceType(type_id));
+    }
+    Ok(())
+}
+
+/// [`swap_beatmaps`] for multiple influences. Requests the beatmaps of every influence at once
+async fn swap_multiple_influence_beatmaps(
+    state: &AppState,
+    osu_token: &str,
+    influences: &mut [Influence],
+) {
+    let beatmaps_to_request: Vec<u32> = influences
+        .iter()
+        .flat_map(|influence| &influence.beatmaps)
+        .map(|maps| maps.get_id())
+        .unique()
+        .collect();
+
+    let beatmaps = state
+        .cached_combined_requester
+        .clone()
+        .get_beatmaps_with_user_partial(&beatmaps_to_request, osu_token)
+        .await
+        .unwrap_or_else(|error| {
+            tracing::warn!(
+                "Failed to request beatmaps, returning ids instead: {}",
+                error
+            );
+            HashMap::new()
+        });
+
+    // Influences converted with beatmap data
+    influences.iter_mut().for_each(|influence| {
+        influence.beatmaps = hydrate_beatmaps(&influence.beatmaps, &beatmaps);
+    });
+}
+
+/// `InfluenceFilterQuery` type. Filters for influence and mention lists
+#[derive(Deserialize, JsonSchema)]
+pub struct InfluenceFilterQuery {
+    /// Only return the users with ranked, loved or guest difficulties
+    #[serde(default)]
+    ranked_only: bool,
+}
+
+/// `InfluenceBeatmapQuery` type. Filters influences by their credited beatmaps
+#[derive(Deserialize, JsonSchema)]
+pub struct InfluenceBeatmapQuery {
+    /// Only return the influences that include this beatmap id
+    #[serde(default)]
+    has_beatmap: Option<u32>,
+}
+
+/// `ConnectionsQuery` type. Influences and mentions are paginated separately
+#[derive(Deserialize, JsonSchema)]
+pub struct ConnectionsQuery {
+    #[serde(default = "default_limit")]
+    influences_limit: u32,
+    #[serde(default)]
+    influences_start: u32,
+    #[serde(default = "default_limit")]
+    mentions_limit: u32,
+    #[serde(default)]
+    mentions_start: u32,
+}
+
+/// `InfluenceTreeQuery` type. `depth` is capped at 5. Depth of 0 returns only the user
+#[derive(Deserialize, JsonSchema)]
+pub struct InfluenceTreeQuery {
+    #[serde(default = "default_tree_depth")]
+    depth: u8,
+}
+fn default_tree_depth() -> u8 {
+    2
+}
+
+/// `UserConnections` type. Totals are in the body since the lists can't share pagination headers
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct UserConnections {
+    pub influences: Vec<Influence>,
+    pub influence_count: u32,
+    pub mentions: Vec<Influence>,
+    pub mention_count: u32,
+}
+
 pub async fn add_influence(
     Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
-    Json(options): Json<InfluenceCreationOptions>,
+    Json(mut options): Json<InfluenceCreationOptions>,
 ) -> Result<Json<Influence>, AppError> {
     let influenced_to = options.user_id.parse::<u32>()?;
+    if let Some(tag) = &options.tag {
+        check_length(tag, MAX_TAG_LENGTH)?;
+    }
+    if let Some(description) = &options.description {
+        check_length(description, *MAX_DESCRIPTION_LENGTH)?;
+    }
+    options.description = options
+        .description
+        .map(|description| sanitize_user_text(&description));
 
     let target_user = state
         .request
@@ -53,27 +202,58 @@ pub async fn add_influence(
             state.cached_combined_requester.clone(),
             &auth_data.osu_token,
             influence_beatmaps,
+            options.expected_mode,
         )
         .await?;
     }
 
-    let (_, mut influence) = try_join!(
-        state.db.upsert_user(target_user),
-        state
-            .db
-            .add_influence_relation(auth_data.user_id, influenced_to, options)
-    )?;
+    let mut influence = state
+        .db
+        .add_influence_relation(
+            auth_data.user_id,
+            target_user,
+            options,
+            *MAX_INFLUENCES_PER_USER,
+        )
+        .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::AddInfluence,
+        Some(influenced_to),
+        None,
+    )
+    .await;
 
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut influence.beatmaps,
     )
-    .await?;
+    .await;
 
     Ok(Json(influence))
 }
 
+/// Influence of the logged in user to `influenced_to`
+pub async fn get_influence(
+    Path(influenced_to): Path<PathInfluencedTo>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Influence>, AppError> {
+    let mut influence = state
+        .db
+        .get_influence(auth_data.user_id, influenced_to.value)
+        .await?;
+    swap_beatmaps(
+        state.cached_combined_requester.clone(),
+        &auth_data.osu_token,
+        &mut influence.beatmaps,
+    )
+    .await;
+    Ok(Json(influence))
+}
+
 pub async fn delete_influence(
     Path(influenced_to): Path<PathInfluencedTo>,
     Extension(auth_data): Extension<AuthData>,
@@ -83,12 +263,20 @@ pub async fn delete_influence(
         .db
         .remove_influence_relation(auth_data.user_id, influenced_to.value)
         .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::RemoveInfluence,
+        Some(influenced_to.value),
+        None,
+    )
+    .await;
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut influence.beatmaps,
     )
-    .await?;
+    .await;
 
     Ok(Json(influence))
 }
@@ -99,25 +287,48 @@ pub async fn add_influence_beatmap(
     State(state): State<Arc<AppState>>,
     Json(beatmaps): Json<BeatmapRequest>,
 ) -> Result<Json<Influence>, AppError> {
-    let beatmaps: Vec<u32> = beatmaps.ids.into_iter().collect();
-    check_multiple_maps(
-        state.cached_combined_requester.clone(),
-        &auth_data.osu_token,
-        &beatmaps,
-    )
-    .await?;
+    let expected_mode = beatmaps.expected_mode;
+    let beatmaps = beatmaps
+        .into_beatmap_ids(state.request.as_ref(), &auth_data.osu_token)
+        .await?;
 
-    let mut influence = state
+    let influence = state
         .db
-        .add_beatmap_to_influence(auth_data.user_id, path.value, beatmaps)
+        .get_influence(auth_data.user_id, path.value)
+        .await?;
+    let new_beatmaps = filter_new_beatmaps(&influence.beatmaps, beatmaps);
+    let mut influence = if new_beatmaps.is_empty() {
+        influence
+    } else {
+        check_multiple_maps(
+            state.cached_combined_requester.clone(),
+            &auth_data.osu_token,
+            &new_beatmaps,
+            expected_mode,
+        )
         .await?;
+        let details = new_beatmaps.iter().join(",");
+        let influence = state
+            .db
+            .add_beatmap_to_influence(auth_data.user_id, path.value, new_beatmaps)
+            .await?;
+        write_audit(
+            &state.db,
+            auth_data.user_id,
+            AuditAction::AddInfluenceBeatmap,
+            Some(path.value),
+            Some(details),
+        )
+        .await;
+        influence
+    };
 
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut influence.beatmaps,
     )
-    .await?;
+    .await;
 
     Ok(Json(influence))
 }
@@ -131,13 +342,21 @@ pub async fn remove_influence_beatmap(
         .db
         .remove_beatmap_from_influence(auth_data.user_id, path.influenced_to, path.beatmap_id)
         .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::RemoveInfluenceBeatmap,
+        Some(path.influenced_to),
+        Some(path.beatmap_id.to_string()),
+    )
+    .await;
 
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut influence.beatmaps,
     )
-    .await?;
+    .await;
 
     Ok(Json(influence))
 }
@@ -148,25 +367,113 @@ pub async fn update_influence_description(
     State(state): State<Arc<AppState>>,
     Json(description): Json<Description>,
 ) -> Result<Json<Influence>, AppError> {
-    const MAX_DESC_LENGTH: usize = 5000;
-    if description.description.len() > MAX_DESC_LENGTH {
-        return Err(AppError::StringTooLong);
+    check_length(&description.description, *MAX_DESCRIPTION_LENGTH)?;
+    let description = sanitize_user_text(&description.description);
+    let mut influence = state
+        .db
+        .update_influence_description(auth_data.user_id, influenced_to.value, description.clone())
+        .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::EditInfluenceDesc,
+        Some(influenced_to.value),
+        Some(description),
+    )
+    .await;
+
+    swap_beatmaps(
+        state.cached_combined_requester.clone(),
+        &auth_data.osu_token,
+        &mut influence.beatmaps,
+    )
+    .await;
+    Ok(Json(influence))
+}
+
+/// Pinned influence is shown separately on the profile. Pinning another influence unpins this one
+pub async fn pin_influence(
+    Path(influenced_to): Path<PathInfluencedTo>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Influence>, AppError> {
+    let mut influence = state
+        .db
+        .pin_influence(auth_data.user_id, influenced_to.value)
+        .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::PinInfluence,
+        Some(influenced_to.value),
+        None,
+    )
+    .await;
+
+    swap_beatmaps(
+        state.cached_combined_requester.clone(),
+        &auth_data.osu_token,
+        &mut influence.beatmaps,
+    )
+    .await;
+    Ok(Json(influence))
+}
+
+pub async fn unpin_influence(
+    Path(influenced_to): Path<PathInfluencedTo>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Influence>, AppError> {
+    let mut influence = state
+        .db
+        .unpin_influence(auth_data.user_id, influenced_to.value)
+        .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::UnpinInfluence,
+        Some(influenced_to.value),
+        None,
+    )
+    .await;
+
+    swap_beatmaps(
+        state.cached_combined_requester.clone(),
+        &auth_data.osu_token,
+        &mut influence.beatmaps,
+    )
+    .await;
+    Ok(Json(influence))
+}
+
+pub async fn update_influence_tag(
+    Path(influenced_to): Path<PathInfluencedTo>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+    Json(tag): Json<Tag>,
+) -> Result<Json<Influence>, AppError> {
+    if let Some(tag) = &tag.tag {
+        check_length(tag, MAX_TAG_LENGTH)?;
     }
     let mut influence = state
         .db
-        .update_influence_description(
-            auth_data.user_id,
-            influenced_to.value,
-            description.description,
-        )
+        .update_influence_tag(auth_data.user_id, influenced_to.value, tag.tag.clone())
         .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::EditInfluenceTag,
+        Some(influenced_to.value),
+        tag.tag,
+    )
+    .await;
 
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut influence.beatmaps,
     )
-    .await?;
+    .await;
     Ok(Json(influence))
 }
 
@@ -175,70 +482,222 @@ pub async fn update_influence_type(
     Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<Influence>, AppError> {
+    validate_influence_type(path.type_id)?;
     let mut influence = state
         .db
         .update_influence_type(auth_data.user_id, path.influenced_to, path.type_id)
         .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::EditInfluenceType,
+        Some(path.influenced_to),
+        Some(path.type_id.to_string()),
+    )
+    .await;
 
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut influence.beatmaps,
     )
-    .await?;
+    .await;
     Ok(Json(influence))
 }
 
-pub async fn get_user_mentions(
-    Query(pagination): Query<PaginationQuery>,
-    Path(user_id): Path<PathUserId>,
+pub async fn update_influence_types(
+    Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
+    Json(updates): Json<Vec<InfluenceTypeUpdate>>,
 ) -> Result<Json<Vec<Influence>>, AppError> {
-    let mentions = state
+    for update in &updates {
+        validate_influence_type(update.type_id)?;
+    }
+
+    let mut influences = state
         .db
-        .get_mentions(user_id.value, pagination.start, pagination.limit)
+        .update_influence_types(auth_data.user_id, &updates)
         .await?;
-    Ok(Json(mentions))
+    join_all(updates.iter().map(|update| {
+        write_audit(
+            &state.db,
+            auth_data.user_id,
+            AuditAction::EditInfluenceType,
+            Some(update.influenced_to),
+            Some(update.type_id.to_string()),
+        )
+    }))
+    .await;
+    swap_multiple_influence_beatmaps(&state, &auth_data.osu_token, &mut influences).await;
+    Ok(Json(influences))
+}
+
+pub async fn get_user_mentions(
+    Query(pagination): Query<PaginationQuery>,
+    Query(filter): Query<InfluenceFilterQuery>,
+    Path(user_id): Path<PathUserId>,
+    OriginalUri(uri): OriginalUri,
+    State(state): State<Arc<AppState>>,
+) -> Result<(HeaderMap, Json<Vec<Influence>>), AppError> {
+    let (mentions, total) = try_join!(
+        state.db.get_mentions(
+            user_id.value,
+            filter.ranked_only,
+            pagination.start,
+            pagination.limit
+        ),
+        state
+            .db
+            .get_mention_count(user_id.value, filter.ranked_only)
+    )?;
+    let headers = pagination_headers(&uri, pagination.start, pagination.limit, total)?;
+    Ok((headers, Json(mentions)))
 }
 
 pub async fn get_user_influences(
     Query(pagination): Query<PaginationQuery>,
+    Query(filter): Query<InfluenceFilterQuery>,
+    Query(beatmap_filter): Query<InfluenceBeatmapQuery>,
     Path(user_id): Path<PathUserId>,
+    OriginalUri(uri): OriginalUri,
     Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<Influence>>, AppError> {
-    let mut influences = state
+) -> Result<(HeaderMap, Json<Vec<Influence>>), AppError> {
+    let (mut influences, total) = try_join!(
+        state.db.get_influences(
+            user_id.value,
+            filter.ranked_only,
+            beatmap_filter.has_beatmap,
+            pagination.start,
+            pagination.limit
+        ),
+        state.db.get_influence_count(
+            user_id.value,
+            filter.ranked_only,
+            beatmap_filter.has_beatmap
+        )
+    )?;
+
+    swap_multiple_influence_beatmaps(&state, &auth_data.osu_token, &mut influences).await;
+
+    let headers = pagination_headers(&uri, pagination.start, pagination.limit, total)?;
+    Ok((headers, Json(influences)))
+}
+
+/// Influences and mentions of a user in one request, for the profile page. Mentions don't have
+/// beatmaps, so only the influences are hydrated
+pub async fn get_user_connections(
+    Query(query): Query<ConnectionsQuery>,
+    Query(filter): Query<InfluenceFilterQuery>,
+    Path(user_id): Path<PathUserId>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<UserConnections>, AppError> {
+    let (mut influences, influence_count, mentions, mention_count) = try_join!(
+        state.db.get_influences(
+            user_id.value,
+            filter.ranked_only,
+            None,
+            query.influences_start,
+            query.influences_limit
+        ),
+        state
+            .db
+            .get_influence_count(user_id.value, filter.ranked_only, None),
+        state.db.get_mentions(
+            user_id.value,
+            filter.ranked_only,
+            query.mentions_start,
+            query.mentions_limit
+        ),
+        state
+            .db
+            .get_mention_count(user_id.value, filter.ranked_only)
+    )?;
+
+    swap_multiple_influence_beatmaps(&state, &auth_data.osu_token, &mut influences).await;
+
+    Ok(Json(UserConnections {
+        influences,
+        influence_count,
+        mentions,
+        mention_count,
+    }))
+}
+
+/// Influences of the user and their influences recursively, for visualizations. Unlike
+/// `/graph`, only the part of the graph that is reachable from the user is returned
+pub async fn get_influence_tree(
+    Query(query): Query<InfluenceTreeQuery>,
+    Path(user_id): Path<PathUserId>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<InfluenceTree>, AppError> {
+    let tree = state
         .db
-        .get_influences(user_id.value, pagination.start, pagination.limit)
+        .get_influence_tree(
+            user_id.value,
+            query.depth.min(MAX_INFLUENCE_TREE_DEPTH),
+            MAX_INFLUENCE_TREE_NODES,
+        )
         .await?;
+    Ok(Json(tree))
+}
 
-    let beatmaps_to_request: Vec<u32> = influences
-        .iter()
-        .flat_map(|influence| &influence.beatmaps)
-        .map(|maps| maps.get_id())
-        .unique()
-        .collect();
+/// Influences of multiple users in one request. Beatmaps of every user are requested from osu!
+/// at once. Duplicate user ids are ignored
+pub async fn get_bulk_user_influences(
+    Query(filter): Query<InfluenceFilterQuery>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+    Json(user_ids): Json<Vec<u32>>,
+) -> Result<Json<HashMap<u32, Vec<Influence>>>, AppError> {
+    let user_ids: Vec<u32> = user_ids.into_iter().unique().collect();
+    if user_ids.len() > MAX_BULK_INFLUENCE_USERS {
+        return Err(AppError::TooManyUsers(MAX_BULK_INFLUENCE_USERS));
+    }
 
-    let beatmaps = state
-        .cached_combined_requester
-        .clone()
-        .get_beatmaps_with_user(&beatmaps_to_request, &auth_data.osu_token)
-        .await?;
+    let influence_lists = try_join_all(user_ids.iter().map(|user_id| {
+        state
+            .db
+            .get_influences(*user_id, filter.ranked_only, None, 0, u32::MAX)
+    }))
+    .await?;
 
-    // Influences converted with beatmap data
-    influences.iter_mut().for_each(|influence| {
-        let new_beatmaps = influence
-            .beatmaps
-            .iter()
-            .filter_map(|beatmap| {
-                // it's not ok to use remove here
-                // there could be beatmaps used more than once
-                let beatmap = beatmaps.get(&beatmap.get_id())?;
-                Some(BeatmapEnum::All(beatmap.clone()))
-            })
-            .collect();
-        influence.beatmaps = new_beatmaps;
-    });
+    // Flattened to hydrate every beatmap with a single request, then split back by user
+    let list_lengths: Vec<usize> = influence_lists.iter().map(Vec::len).collect();
+    let mut influences: Vec<Influence> = influence_lists.into_iter().flatten().collect();
+    swap_multiple_influence_beatmaps(&state, &auth_data.osu_token, &mut influences).await;
 
-    Ok(Json(influences))
+    let mut influences = influences.into_iter();
+    let user_influences = user_ids
+        .into_iter()
+        .zip(list_lengths)
+        .map(|(user_id, length)| (user_id, influences.by_ref().take(length).collect()))
+        .collect();
+    Ok(Json(user_influences))
+}
+
+/// Whether the logged in user has an influence to each of the given users. Meant for rendering
+/// many add influence buttons at once
+pub async fn get_influence_status(
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+    Json(user_ids): Json<Vec<u32>>,
+) -> Result<Json<HashMap<u32, bool>>, AppError> {
+    let user_ids: Vec<u32> = user_ids.into_iter().unique().collect();
+    if user_ids.len() > MAX_INFLUENCE_STATUS_USERS {
+        return Err(AppError::TooManyUsers(MAX_INFLUENCE_STATUS_USERS));
+    }
+
+    let existing: HashSet<u32> = state
+        .db
+        .get_existing_influence_targets(auth_data.user_id, &user_ids)
+        .await?
+        .into_iter()
+        .collect();
+    let status = user_ids
+        .into_iter()
+        .map(|user_id| (user_id, existing.contains(&user_id)))
+        .collect();
+    Ok(Json(status))
 }
@@ -1,15 +1,21 @@
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 
 use axum::{
-    extract::{Query, State},
+    extract::{ConnectInfo, OriginalUri, Query, State, WebSocketUpgrade},
+    response::Response,
     Json,
 };
 use cached::Cached;
+use futures::try_join;
+use http::HeaderMap;
 use schemars::JsonSchema;
 use serde::Deserialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
 
-use crate::osu_api::{BeatmapEnum, GetID};
+use crate::osu_api::{BeatmapEnum, GameMode, GetID};
 use crate::{
     custom_cache::CustomCache,
     database::leaderboard::{LeaderboardBeatmap, LeaderboardUser},
@@ -17,6 +23,14 @@ use crate::{
     AppState,
 };
 
+use super::{
+    activity::{handle_socket, BroadcastStats, LagStats},
+    pagination_headers,
+};
+
+/// How many users are sent to websocket clients in each leaderboard snapshot
+const USER_LEADERBOARD_SNAPSHOT_SIZE: u32 = 100;
+
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct LeaderboardQuery {
     #[serde(default)]
@@ -32,101 +46,281 @@ fn default_limit() -> u32 {
     100
 }
 
+/// `BeatmapLeaderboardModeQuery` type. Mode of a beatmap is only known after it's requested from
+/// osu!, so only the cached part of the leaderboard is filtered. With `mode`, `start` and the
+/// total in the headers are about the filtered list, and there are no pages past it
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct BeatmapLeaderboardModeQuery {
+    mode: Option<GameMode>,
+}
+
 pub struct LeaderboardCache<K: Hash + Eq + Clone, V: Clone> {
     /// In theory, it's better to use RwLock here, but [`CustomCache::cache_get`]
     /// takes &mut self reference, so we can't separate read and write operations
-    cache: Mutex<CustomCache<K, Vec<V>>>,
+    cache: Mutex<CustomCache<K, (Vec<V>, u32)>>,
+    /// How many entries are fetched from database and kept in the cache for each key
+    prefetch_size: u32,
 }
 
 impl<K: Hash + Eq + Clone, V: Clone> LeaderboardCache<K, V> {
-    pub fn new(expire_in: u32) -> Self {
+    pub fn new(expire_in: u32, prefetch_size: u32) -> Self {
         Self {
             cache: Mutex::new(CustomCache::new(expire_in)),
+            prefetch_size,
         }
     }
+
+    /// Uses `env_var` environment variable as the prefetch size. Falls back to
+    /// `default_prefetch_size` if the variable is not set
+    pub fn from_env(expire_in: u32, env_var: &str, default_prefetch_size: u32) -> Self {
+        let prefetch_size = match std::env::var(env_var) {
+            Ok(value) => value.parse::<u32>().unwrap_or_else(|_| {
+                panic!("{} environment variable is not a valid number", env_var)
+            }),
+            Err(_) => default_prefetch_size,
+        };
+        assert!(
+            prefetch_size >= 1,
+            "{} environment variable should be at least 1",
+            env_var
+        );
+        Self::new(expire_in, prefetch_size)
+    }
+
+    pub fn prefetch_size(&self) -> u32 {
+        self.prefetch_size
+    }
+
+    /// Pages can't be larger than the cached part. Pages past it go to database, and beatmap
+    /// pages are also requested from osu!, so a large `limit` would be expensive
+    pub fn clamp_limit(&self, limit: u32) -> u32 {
+        limit.min(self.prefetch_size)
+    }
+
+    /// Whether the requested page is inside the cached part of the leaderboard
+    pub fn is_cached_window(&self, start: u32, limit: u32) -> bool {
+        start.saturating_add(limit) <= self.prefetch_size
+    }
+
+    /// Returns the requested page and the total length of the leaderboard
     pub fn cached_query(
         &self,
         key: &K,
         start: u32,
         limit: u32,
-    ) -> Result<Option<Vec<V>>, AppError> {
+    ) -> Result<Option<(Vec<V>, u32)>, AppError> {
         let mut locked_cache = self.cache.lock().map_err(|_| AppError::Mutex)?;
-        let Some(leaderboard) = locked_cache.cache_get(key) else {
+        let Some((leaderboard, total)) = locked_cache.cache_get(key) else {
             return Ok(None);
         };
-        Ok(Some(
+        Ok(Some((
             leaderboard
                 .iter()
                 .skip(start as usize)
                 .take(limit as usize)
                 .cloned()
                 .collect(),
-        ))
+            *total,
+        )))
+    }
+
+    /// `total` is the length of the whole leaderboard, not only the cached part
+    pub fn add_leaderboard(
+        &self,
+        key: &K,
+        leaderboard: Vec<V>,
+        total: u32,
+    ) -> Result<(), AppError> {
+        let mut locked_cache = self.cache.lock().map_err(|_| AppError::Mutex)?;
+        locked_cache.cache_set(key.clone(), (leaderboard, total));
+        Ok(())
     }
 
-    pub fn add_leaderboard(&self, key: &K, leaderboard: Vec<V>) -> Result<(), AppError> {
+    /// Removes every key, including the country and mode filtered leaderboards that are only
+    /// refilled by the next request
+    pub fn clear(&self) -> Result<(), AppError> {
         let mut locked_cache = self.cache.lock().map_err(|_| AppError::Mutex)?;
-        locked_cache.cache_set(key.clone(), leaderboard);
+        locked_cache.cache_clear();
         Ok(())
     }
 }
 
+/// Pushes the global user leaderboard to websocket clients. The leaderboard is recomputed
+/// on the same interval as the user leaderboard cache expiry and a snapshot is only
+/// broadcasted if it differs from the previous one. It doesn't write to the cache, so a
+/// fresh snapshot never makes the paginated endpoint stale
+pub struct LeaderboardBroadcaster {
+    latest_snapshot: Mutex<String>,
+    leaderboard_broadcaster: Sender<String>,
+    lag_stats: Arc<LagStats>,
+    refresh_interval: Duration,
+}
+
+impl LeaderboardBroadcaster {
+    pub fn new(refresh_interval: Duration) -> Arc<Self> {
+        let (broadcast_sender, _broadcast_receiver) = broadcast::channel(10);
+        Arc::new(LeaderboardBroadcaster {
+            latest_snapshot: Mutex::new("[]".to_string()),
+            leaderboard_broadcaster: broadcast_sender,
+            lag_stats: Arc::new(LagStats::default()),
+            refresh_interval,
+        })
+    }
+
+    pub fn new_connection(&self) -> Result<(String, Receiver<String>), AppError> {
+        let latest_snapshot = self.latest_snapshot.lock().map_err(|_| AppError::Mutex)?;
+        Ok((
+            latest_snapshot.clone(),
+            self.leaderboard_broadcaster.subscribe(),
+        ))
+    }
+
+    pub fn broadcast_stats(&self) -> BroadcastStats {
+        self.lag_stats
+            .snapshot(self.leaderboard_broadcaster.receiver_count())
+    }
+
+    /// Takes a weak reference to not keep [`AppState`] alive forever. The loop stops once the
+    /// state is dropped
+    pub fn start_loop(self: Arc<Self>, state: Weak<AppState>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.refresh_interval);
+            loop {
+                interval.tick().await;
+                let Some(state) = state.upgrade() else {
+                    break;
+                };
+                if let Err(error) = self.refresh(&state).await {
+                    tracing::error!("Failed to refresh user leaderboard snapshot: {}", error);
+                }
+            }
+        });
+    }
+
+    /// Recomputes the snapshot and broadcasts it if it changed. Called by the loop and when the
+    /// leaderboards are recomputed by an admin
+    pub async fn refresh(&self, state: &AppState) -> Result<(), AppError> {
+        let leaderboard = state
+            .db
+            .user_leaderboard(None, false, USER_LEADERBOARD_SNAPSHOT_SIZE, 0)
+            .await?;
+        let snapshot = serde_json::to_string(&leaderboard)?;
+
+        {
+            let mut latest_snapshot = self.latest_snapshot.lock().map_err(|_| AppError::Mutex)?;
+            if *latest_snapshot == snapshot {
+                return Ok(());
+            }
+            latest_snapshot.clone_from(&snapshot);
+        }
+
+        if let Ok(receiver_count) = self.leaderboard_broadcaster.send(snapshot) {
+            tracing::info!("Sending new leaderboard to {} connections", receiver_count);
+        }
+        Ok(())
+    }
+}
+
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Response, AppError> {
+    let (initial_message, broadcast_receiver) =
+        state.user_leaderboard_broadcaster.new_connection()?;
+    let lag_stats = state.user_leaderboard_broadcaster.lag_stats.clone();
+    let upgrade_response = ws.on_upgrade(move |socket| {
+        handle_socket(socket, addr, initial_message, broadcast_receiver, lag_stats)
+    });
+    Ok(upgrade_response)
+}
+
 pub async fn get_user_leaderboard(
-    Query(query): Query<LeaderboardQuery>,
+    Query(mut query): Query<LeaderboardQuery>,
+    OriginalUri(uri): OriginalUri,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<LeaderboardUser>>, AppError> {
-    let leaderboard_cache_limit = 500;
+) -> Result<(HeaderMap, Json<Vec<LeaderboardUser>>), AppError> {
+    query.limit = state.user_leaderboard_cache.clamp_limit(query.limit);
+    if !state
+        .user_leaderboard_cache
+        .is_cached_window(query.start, query.limit)
+    {
+        // Pages past the cached part are rarely requested. They go to database directly
+        let (leaderboard, total) = try_join!(
+            state.db.user_leaderboard(
+                query.country.clone(),
+                query.ranked,
+                query.limit,
+                query.start
+            ),
+            state
+                .db
+                .user_leaderboard_count(query.country.clone(), query.ranked)
+        )?;
+        let headers = pagination_headers(&uri, query.start, query.limit, total)?;
+        return Ok((headers, Json(leaderboard)));
+    }
 
-    if let Some(leaderboard) = state.user_leaderboard_cache.cached_query(
+    if let Some((leaderboard, total)) = state.user_leaderboard_cache.cached_query(
         &(query.ranked, query.country.clone()),
         query.start,
         query.limit,
     )? {
-        return Ok(Json(leaderboard));
-    }
-    let mut leaderboard = state
-        .db
-        .user_leaderboard(
-            query.country.clone(),
-            query.ranked,
-            leaderboard_cache_limit,
+        let headers = pagination_headers(&uri, query.start, query.limit, total)?;
+        return Ok((headers, Json(leaderboard)));
+    }
+    let (leaderboard, total) = refresh_user_leaderboard_cache(
+        &state,
+        query.ranked,
+        query.country,
+        query.start,
+        query.limit,
+    )
+    .await?;
+    let headers = pagination_headers(&uri, query.start, query.limit, total)?;
+    Ok((headers, Json(leaderboard)))
+}
+
+/// Fetches the cached part of the user leaderboard from database and puts it in the cache.
+/// Returns the requested page of the fresh leaderboard and the total length
+pub async fn refresh_user_leaderboard_cache(
+    state: &AppState,
+    ranked: bool,
+    country: Option<String>,
+    start: u32,
+    limit: u32,
+) -> Result<(Vec<LeaderboardUser>, u32), AppError> {
+    let (mut leaderboard, total) = try_join!(
+        state.db.user_leaderboard(
+            country.clone(),
+            ranked,
+            state.user_leaderboard_cache.prefetch_size(),
             0,
-        )
-        .await?;
+        ),
+        state.db.user_leaderboard_count(country.clone(), ranked)
+    )?;
     leaderboard.shrink_to_fit();
 
     let limited_leaderboard = leaderboard
         .iter()
-        .skip(query.start as usize)
-        .take(query.limit as usize)
+        .skip(start as usize)
+        .take(limit as usize)
         .cloned()
         .collect();
 
     state
         .user_leaderboard_cache
-        .add_leaderboard(&(query.ranked, query.country), leaderboard)?;
-    Ok(Json(limited_leaderboard))
+        .add_leaderboard(&(ranked, country), leaderboard, total)?;
+    Ok((limited_leaderboard, total))
 }
 
-pub async fn get_beatmap_leaderboard(
-    Query(query): Query<LeaderboardQuery>,
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<Vec<LeaderboardBeatmap>>, AppError> {
-    let leaderboard_cache_limit = 200;
-
-    if let Some(leaderboard) =
-        state
-            .beatmap_leaderboard_cache
-            .cached_query(&query.ranked, query.start, query.limit)?
-    {
-        return Ok(Json(leaderboard));
-    }
-
-    let leaderboard = state
-        .db
-        .beatmap_leaderboard(query.ranked, leaderboard_cache_limit, 0)
-        .await?;
-
+/// Swaps database beatmap ids with osu! API data. Beatmaps that osu! API doesn't return are
+/// dropped, so are the ones from other modes if `mode` is set
+async fn hydrate_beatmap_leaderboard(
+    state: &AppState,
+    leaderboard: Vec<LeaderboardBeatmap>,
+    mode: Option<GameMode>,
+) -> Result<Vec<LeaderboardBeatmap>, AppError> {
     let beatmaps_to_request: Vec<u32> = leaderboard
         .iter()
         .map(|entry| entry.beatmap.get_id())
@@ -136,30 +330,107 @@ pub async fn get_beatmap_leaderboard(
     let mut beatmaps = state
         .cached_combined_requester
         .clone()
-        .get_beatmaps_with_user(&beatmaps_to_request, &access_token)
+        .get_beatmaps_with_user_partial(&beatmaps_to_request, &access_token)
         .await?;
-    let mut leaderboard: Vec<LeaderboardBeatmap> = leaderboard
+    Ok(leaderboard
         .into_iter()
         .filter_map(|entry| {
             // we can use remove here since all of the maps should be unique
             let new_beatmap = beatmaps.remove(&entry.beatmap.get_id())?;
+            if mode.is_some_and(|mode| {
+                !new_beatmap
+                    .beatmaps
+                    .iter()
+                    .any(|beatmap| beatmap.mode == mode)
+            }) {
+                return None;
+            }
             Some(LeaderboardBeatmap {
                 beatmap: BeatmapEnum::All(new_beatmap),
                 count: entry.count,
             })
         })
-        .collect();
+        .collect())
+}
+
+pub async fn get_beatmap_leaderboard(
+    Query(mut query): Query<LeaderboardQuery>,
+    Query(mode_query): Query<BeatmapLeaderboardModeQuery>,
+    OriginalUri(uri): OriginalUri,
+    State(state): State<Arc<AppState>>,
+) -> Result<(HeaderMap, Json<Vec<LeaderboardBeatmap>>), AppError> {
+    query.limit = state.beatmap_leaderboard_cache.clamp_limit(query.limit);
+    // Mode filtered leaderboards only have the cached part
+    if mode_query.mode.is_none()
+        && !state
+            .beatmap_leaderboard_cache
+            .is_cached_window(query.start, query.limit)
+    {
+        // Pages past the cached part are rarely requested. They go to database directly
+        let (leaderboard, total) = try_join!(
+            state
+                .db
+                .beatmap_leaderboard(query.ranked, query.limit, query.start),
+            state.db.beatmap_leaderboard_count(query.ranked)
+        )?;
+        let leaderboard = hydrate_beatmap_leaderboard(&state, leaderboard, None).await?;
+        let headers = pagination_headers(&uri, query.start, query.limit, total)?;
+        return Ok((headers, Json(leaderboard)));
+    }
+
+    if let Some((leaderboard, total)) = state.beatmap_leaderboard_cache.cached_query(
+        &(query.ranked, mode_query.mode),
+        query.start,
+        query.limit,
+    )? {
+        let headers = pagination_headers(&uri, query.start, query.limit, total)?;
+        return Ok((headers, Json(leaderboard)));
+    }
+
+    let (leaderboard, total) = refresh_beatmap_leaderboard_cache(
+        &state,
+        query.ranked,
+        mode_query.mode,
+        query.start,
+        query.limit,
+    )
+    .await?;
+    let headers = pagination_headers(&uri, query.start, query.limit, total)?;
+    Ok((headers, Json(leaderboard)))
+}
+
+/// Fetches the cached part of the beatmap leaderboard from database and puts it in the cache.
+/// Returns the requested page of the fresh leaderboard and the total length. With `mode`, the
+/// total is the length of the filtered list
+pub async fn refresh_beatmap_leaderboard_cache(
+    state: &AppState,
+    ranked: bool,
+    mode: Option<GameMode>,
+    start: u32,
+    limit: u32,
+) -> Result<(Vec<LeaderboardBeatmap>, u32), AppError> {
+    let (leaderboard, total) = try_join!(
+        state
+            .db
+            .beatmap_leaderboard(ranked, state.beatmap_leaderboard_cache.prefetch_size(), 0,),
+        state.db.beatmap_leaderboard_count(ranked)
+    )?;
+    let mut leaderboard = hydrate_beatmap_leaderboard(state, leaderboard, mode).await?;
     leaderboard.shrink_to_fit();
+    let total = match mode {
+        Some(_) => leaderboard.len() as u32,
+        None => total,
+    };
 
     let limited_leaderboard = leaderboard
         .iter()
-        .skip(query.start as usize)
-        .take(query.limit as usize)
+        .skip(start as usize)
+        .take(limit as usize)
         .cloned()
         .collect();
 
     state
         .beatmap_leaderboard_cache
-        .add_leaderboard(&query.ranked, leaderboard)?;
-    Ok(Json(limited_leaderboard))
+        .add_leaderboard(&(ranked, mode), leaderboard, total)?;
+    Ok((limited_leaderboard, total))
 }
@@ -1,20 +1,39 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, LazyLock},
+};
 
+use futures::future::try_join_all;
+use http::{
+    header::{HeaderName, LINK},
+    HeaderMap, HeaderValue, Uri,
+};
 use itertools::Itertools;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    database::{audit::AuditAction, DatabaseClient},
+    env::number_from_env,
     error::AppError,
-    osu_api::{cached_requester::CombinedRequester, BeatmapEnum, GetID},
+    osu_api::{
+        cached_requester::CombinedRequester, request::Requester, BeatmapEnum, BeatmapsetSmall,
+        GameMode, GetID,
+    },
 };
 
 pub mod activity;
+pub mod admin;
 pub mod auth;
+pub mod client_ip;
+pub mod compression;
 pub mod graph_vizualizer;
+pub mod idempotency;
 pub mod influence;
 pub mod leaderboard;
 pub mod osu_search;
+pub mod report;
+pub mod schema;
 pub mod user;
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -28,6 +47,78 @@ fn default_limit() -> u32 {
     u32::MAX
 }
 
+/// Bio and influence description share the same default. Both can be tightened with
+/// `MAX_BIO_LENGTH` and `MAX_DESCRIPTION_LENGTH` environment variables. Limits are in characters,
+/// see [`check_length`]
+const DEFAULT_MAX_TEXT_LENGTH: usize = 5000;
+
+pub static MAX_BIO_LENGTH: LazyLock<usize> =
+    LazyLock::new(|| number_from_env("MAX_BIO_LENGTH", DEFAULT_MAX_TEXT_LENGTH));
+pub static MAX_DESCRIPTION_LENGTH: LazyLock<usize> =
+    LazyLock::new(|| number_from_env("MAX_DESCRIPTION_LENGTH", DEFAULT_MAX_TEXT_LENGTH));
+
+/// Counts unicode scalar values instead of bytes. Otherwise non-ASCII text like Japanese bios hit
+/// the limit at a third of the length
+fn check_length(text: &str, max_length: usize) -> Result<(), AppError> {
+    if text.chars().count() > max_length {
+        return Err(AppError::StringTooLong(max_length));
+    }
+    Ok(())
+}
+
+pub static X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Builds `X-Total-Count` and RFC5988 `Link` headers for paginated list responses.
+/// `start` and `limit` are replaced in the links, the rest of the query parameters are kept as is
+pub fn pagination_headers(
+    uri: &Uri,
+    start: u32,
+    limit: u32,
+    total: u32,
+) -> Result<HeaderMap, AppError> {
+    let other_parameters: Vec<&str> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|parameter| {
+            !parameter.is_empty()
+                && !parameter.starts_with("start=")
+                && !parameter.starts_with("limit=")
+        })
+        .collect();
+    let page_link = |page_start: u32, relation: &str| {
+        let mut parameters: Vec<String> = other_parameters
+            .iter()
+            .map(|parameter| parameter.to_string())
+            .collect();
+        parameters.push(format!("start={}", page_start));
+        parameters.push(format!("limit={}", limit));
+        format!(
+            "<{}?{}>; rel=\"{}\"",
+            uri.path(),
+            parameters.join("&"),
+            relation
+        )
+    };
+
+    let mut links = Vec::new();
+    if start.saturating_add(limit) < total {
+        links.push(page_link(start + limit, "next"));
+    }
+    if start > 0 {
+        links.push(page_link(start.saturating_sub(limit), "prev"));
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(X_TOTAL_COUNT.clone(), HeaderValue::from(total));
+    if !links.is_empty() {
+        let link_value = HeaderValue::from_str(&links.join(", "))
+            .map_err(|_| AppError::BadUri(uri.to_string()))?;
+        headers.insert(LINK, link_value);
+    }
+    Ok(headers)
+}
+
 #[derive(Deserialize, JsonSchema)]
 pub struct PathInfluencedTo {
     #[serde(rename = "influenced_to")]
@@ -64,47 +155,125 @@ pub struct PathUserTypeId {
 }
 
 /// `BeatmapRequest` type
-#[derive(Deserialize, Serialize, JsonSchema)]
+#[derive(Deserialize, Serialize, JsonSchema, Default)]
 pub struct BeatmapRequest {
-    #[serde(rename = "beatmaps")]
-    pub ids: HashSet<u32>,
+    /// Added in this order, duplicates are skipped
+    #[serde(rename = "beatmaps", default)]
+    pub ids: Vec<u32>,
+    /// Every difficulty of these beatmapsets is added along with `beatmaps`
+    #[serde(default)]
+    pub beatmapset_ids: Vec<u32>,
+    /// Rejects the request if any of `beatmaps` is from another mode. Only the difficulties of
+    /// this mode are added from `beatmapset_ids`
+    #[serde(default)]
+    pub expected_mode: Option<GameMode>,
+}
+
+impl BeatmapRequest {
+    /// Expands beatmapsets to their difficulties and merges them with the beatmap ids. Keeps the
+    /// requested order, beatmap ids first, and the first occurrence of duplicates
+    async fn into_beatmap_ids(
+        self,
+        request: &dyn Requester,
+        osu_token: &str,
+    ) -> Result<Vec<u32>, AppError> {
+        let mut seen_beatmapset_ids = HashSet::new();
+        let beatmapset_ids = self
+            .beatmapset_ids
+            .into_iter()
+            .filter(|beatmapset_id| seen_beatmapset_ids.insert(*beatmapset_id));
+        let beatmapsets = try_join_all(
+            beatmapset_ids
+                .map(|beatmapset_id| request.get_beatmapset_osu(osu_token, beatmapset_id)),
+        )
+        .await?;
+
+        let expected_mode = self.expected_mode;
+        let beatmapset_beatmap_ids = beatmapsets
+            .into_iter()
+            .flat_map(|beatmapset| beatmapset.base_beatmapset.beatmaps)
+            .filter(|beatmap| {
+                expected_mode.map_or(true, |expected_mode| beatmap.mode == expected_mode)
+            })
+            .map(|beatmap| beatmap.id);
+        let mut seen_ids = HashSet::new();
+        Ok(self
+            .ids
+            .into_iter()
+            .chain(beatmapset_beatmap_ids)
+            .filter(|beatmap_id| seen_ids.insert(*beatmap_id))
+            .collect())
+    }
+}
+
+/// Drops the requested beatmaps that are already added. Re-adding them is a no-op for the database
+/// but we don't want to send any requests or create activities for them.
+fn filter_new_beatmaps(
+    existing_beatmaps: &[BeatmapEnum],
+    requested_beatmaps: Vec<u32>,
+) -> Vec<u32> {
+    let existing_ids: HashSet<u32> = existing_beatmaps.iter().map(GetID::get_id).collect();
+    requested_beatmaps
+        .into_iter()
+        .filter(|beatmap_id| !existing_ids.contains(beatmap_id))
+        .collect()
+}
+
+/// Replaces the beatmap ids with the requested beatmap data and keeps the order. Beatmaps that
+/// couldn't be requested stay as ids instead of being dropped, so the rest of the response can
+/// still be shown
+fn hydrate_beatmaps(
+    beatmaps: &[BeatmapEnum],
+    requested_beatmaps: &HashMap<u32, BeatmapsetSmall>,
+) -> Vec<BeatmapEnum> {
+    beatmaps
+        .iter()
+        .map(|beatmap_enum| {
+            let id = beatmap_enum.get_id();
+            match requested_beatmaps.get(&id) {
+                Some(beatmap) => BeatmapEnum::All(beatmap.clone()),
+                None => BeatmapEnum::Id(id),
+            }
+        })
+        .collect()
 }
 
 /// A shortcut to use in user and influence endpoints.
 /// This is not usable for multiple influences as this function would send requests for each
 /// influence. They have their own implementation to save requests
 ///
+/// Failing to request the beatmaps doesn't fail the response, see [`hydrate_beatmaps`]
+///
 /// TODO: maybe even do it as middleware? you seem to repeat this. A little ambitious though
 async fn swap_beatmaps(
     cached_combined_requester: Arc<CombinedRequester>,
     osu_token: &str,
     beatmaps: &mut Vec<BeatmapEnum>,
-) -> Result<(), AppError> {
+) {
     let beatmaps_to_request: Vec<u32> = beatmaps.iter().map(|map| map.get_id()).unique().collect();
 
-    let mut requested_beatmaps = cached_combined_requester
+    let requested_beatmaps = cached_combined_requester
         .clone()
-        .get_beatmaps_with_user(&beatmaps_to_request, osu_token)
-        .await?;
-
-    // to keep the order, we iterate user beatmaps
-    let new_beatmaps: Vec<BeatmapEnum> = beatmaps
-        .iter()
-        .filter_map(|beatmap_enum| {
-            // remove should be ok, we keep beatmaps as set in db, so they should be unique
-            let beatmap = requested_beatmaps.remove(&beatmap_enum.get_id())?;
-            Some(BeatmapEnum::All(beatmap))
-        })
-        .collect();
+        .get_beatmaps_with_user_partial(&beatmaps_to_request, osu_token)
+        .await
+        .unwrap_or_else(|error| {
+            tracing::warn!(
+                "Failed to request beatmaps, returning ids instead: {}",
+                error
+            );
+            HashMap::new()
+        });
 
-    *beatmaps = new_beatmaps;
-    Ok(())
+    *beatmaps = hydrate_beatmaps(beatmaps, &requested_beatmaps);
 }
 
+/// `expected_mode` is for the mode specific pages. Every beatmap in another mode is returned in
+/// the error, not just the first one
 async fn check_multiple_maps(
     cached_combined_requester: Arc<CombinedRequester>,
     osu_token: &str,
     beatmaps: &[u32],
+    expected_mode: Option<GameMode>,
 ) -> Result<(), AppError> {
     let requested_beatmaps = cached_combined_requester
         .clone()
@@ -112,13 +281,37 @@ async fn check_multiple_maps(
         .await?;
 
     // efficient but not user friendly missing map warning
-    let first_missing_beatmap = requested_beatmaps
-        .keys()
-        .filter(|requested_map| !beatmaps.contains(requested_map))
-        .copied()
-        .next();
+    let first_missing_beatmap = beatmaps
+        .iter()
+        .find(|requested_map| !requested_beatmaps.contains_key(requested_map))
+        .copied();
     if let Some(first_missing_map) = first_missing_beatmap {
         return Err(AppError::NonExistingMap(first_missing_map));
     }
+
+    if let Some(expected_mode) = expected_mode {
+        let wrong_mode_beatmaps: Vec<u32> = beatmaps
+            .iter()
+            .filter(|beatmap_id| requested_beatmaps[beatmap_id].mode != expected_mode)
+            .copied()
+            .collect();
+        if !wrong_mode_beatmaps.is_empty() {
+            return Err(AppError::WrongBeatmapMode(wrong_mode_beatmaps));
+        }
+    }
     Ok(())
 }
+
+/// The mutation is already done when this is called, so failing to write the audit log only gets
+/// logged instead of failing the response
+async fn write_audit(
+    db: &DatabaseClient,
+    actor: u32,
+    action: AuditAction,
+    target: Option<u32>,
+    details: Option<String>,
+) {
+    if let Err(error) = db.write_audit(actor, action, target, details).await {
+        tracing::error!("Failed to write audit log for {:?}: {}", action, error);
+    }
+}
@@ -1,56 +1,173 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
 
 use axum::{
-    extract::{Path, Request, State},
+    extract::{Path, Query, State},
     Extension, Json,
 };
 use cached::proc_macro::cached;
 use itertools::Itertools;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     custom_cache::CustomCache,
     database::user::UserSmall,
     error::AppError,
     jwt::AuthData,
-    osu_api::{cached_requester::cached_osu_user_request, BeatmapsetSmall},
+    osu_api::{cached_requester::cached_osu_user_request, BeatmapsetSmall, GameMode},
     AppState,
 };
 
-use super::{PathBeatmapId, PathQuery};
+use super::{check_length, BeatmapRequest, PathBeatmapId, PathQuery};
 
-#[cached(
-    ty = "CustomCache<String, Json<Vec<UserSmall>>>",
-    create = "{CustomCache::new(600)}",
-    convert = r#"{path_query.value.clone()}"#,
-    result = true
-)]
+/// In characters. osu! usernames are at most 15 characters. Leaves room for searching with user
+/// ids and typos
+const MAX_USER_QUERY_LENGTH: usize = 100;
+/// Applies to the encoded query string forwarded to osu!. Cursor strings take some of it
+const MAX_BEATMAP_QUERY_LENGTH: usize = 500;
+
+/// Limits both the requested beatmaps and beatmapsets, and the beatmaps after beatmapsets are
+/// expanded to their difficulties
+const MAX_BULK_BEATMAPS: usize = 100;
+
+/// How many osu! user search results are ranked. Only the returned ones are requested from osu!
+/// Also the maximum `limit` for user search
+const USER_SEARCH_CANDIDATES: usize = 10;
+
+/// osu! user search is abandoned after this and database search results are returned instead
+const USER_SEARCH_OSU_TIMEOUT: Duration = Duration::from_secs(3);
+
+static USER_SEARCH_RESULT_COUNT: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("USER_SEARCH_RESULT_COUNT").map_or(3, |value| {
+        value
+            .parse()
+            .expect("USER_SEARCH_RESULT_COUNT environment variable is not a valid number")
+    })
+});
+
+/// `UserSearchQuery` type
+#[derive(Deserialize, JsonSchema)]
+pub struct UserSearchQuery {
+    /// Number of users to return. Defaults to 3 and it's capped at 10
+    limit: Option<usize>,
+}
+
+fn user_search_limit(limit: Option<usize>) -> usize {
+    limit
+        .unwrap_or(*USER_SEARCH_RESULT_COUNT)
+        .min(USER_SEARCH_CANDIDATES)
+}
+
+/// Orders osu! user search results for this app and returns at most `count` ids. osu! order is
+/// the base score, users that are in our database and users with more mentions are moved up.
+/// Users with equal scores keep the osu! order
+pub fn rank_user_search(osu_order: &[u32], db_users: &[UserSmall], count: usize) -> Vec<u32> {
+    // Every position is worth 2 points. Being in the database is worth 3 and mentions give
+    // diminishing points, so a user needs to be close to the top to be moved past the best match
+    let mut scored_ids: Vec<(u32, f64)> = osu_order
+        .iter()
+        .enumerate()
+        .map(|(position, id)| {
+            let mut score = 2.0 * (osu_order.len() - position) as f64;
+            if let Some(user) = db_users.iter().find(|user| user.id == *id) {
+                score += 3.0 + f64::from(user.mentions.unwrap_or(0)).ln_1p();
+            }
+            (*id, score)
+        })
+        .collect();
+    scored_ids.sort_by(|(_, first), (_, second)| second.total_cmp(first));
+    scored_ids
+        .into_iter()
+        .take(count)
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Falls back to searching the usernames in the database when osu! is slow or down. Fallback
+/// results are not cached
 pub async fn osu_user_search(
     Path(path_query): Path<PathQuery>,
+    Query(search_query): Query<UserSearchQuery>,
     Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<Vec<UserSmall>>, AppError> {
-    let user_search_osu = state
-        .request
-        .search_user_osu(&auth_data.osu_token, &path_query.value)
-        .await?
-        .user
-        .data;
+    check_length(&path_query.value, MAX_USER_QUERY_LENGTH)?;
+    let limit = user_search_limit(search_query.limit);
+    let search_result = ranked_user_search(
+        state.clone(),
+        auth_data.osu_token.clone(),
+        path_query.value.clone(),
+        limit,
+    )
+    .await;
 
-    let mut users_to_get: Vec<u32> = user_search_osu
+    match search_result {
+        Ok(users) => Ok(Json(users)),
+        Err(
+            error @ (AppError::UpstreamTimeout
+            | AppError::UpstreamStatus(_)
+            | AppError::Reqwest(_)
+            | AppError::SerdeJson(_)
+            | AppError::OsuUserNotFound(_)),
+        ) => {
+            tracing::warn!(
+                "osu! user search failed, returning database results instead: {}",
+                error
+            );
+            let users = state
+                .db
+                .search_users_by_name(&path_query.value, limit)
+                .await?;
+            Ok(Json(users))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+#[cached(
+    ty = "CustomCache<(String, usize), Vec<UserSmall>>",
+    create = "{CustomCache::new(600)}",
+    convert = r#"{(query.clone(), limit)}"#,
+    result = true
+)]
+async fn ranked_user_search(
+    state: Arc<AppState>,
+    osu_token: String,
+    query: String,
+    limit: usize,
+) -> Result<Vec<UserSmall>, AppError> {
+    let user_search_osu = tokio::time::timeout(
+        USER_SEARCH_OSU_TIMEOUT,
+        state.request.search_user_osu(&osu_token, &query),
+    )
+    .await
+    .map_err(|_| AppError::UpstreamTimeout)??
+    .user
+    .data;
+
+    let candidate_ids: Vec<u32> = user_search_osu
         .into_iter()
-        .take(3)
+        .take(USER_SEARCH_CANDIDATES)
         .map(|user_id| user_id.id)
         .collect();
 
-    let mut users = state.db.get_multiple_user_details(&users_to_get).await?;
-
-    let db_user_ids: Vec<u32> = users.iter().map(|user| user.id).collect();
-    users_to_get.retain(|id| !db_user_ids.contains(id));
+    let db_users = state.db.get_multiple_user_details(&candidate_ids).await?;
+    let ranked_ids = rank_user_search(&candidate_ids, &db_users, limit);
+    let mut users_by_id: HashMap<u32, UserSmall> =
+        db_users.into_iter().map(|user| (user.id, user)).collect();
 
     let mut handles = Vec::new();
-    for id in users_to_get {
+    for id in ranked_ids
+        .iter()
+        .filter(|id| !users_by_id.contains_key(id))
+        .copied()
+    {
         let client = state.request.clone();
-        let osu_token = auth_data.osu_token.to_string();
+        let osu_token = osu_token.clone();
         let handle =
             tokio::spawn(async move { cached_osu_user_request(client, &osu_token, id).await });
         handles.push(handle);
@@ -58,31 +175,94 @@ pub async fn osu_user_search(
 
     for handle in handles {
         if let Ok(request_result) = handle.await {
-            users.push(request_result?.into())
+            let user: UserSmall = request_result?.into();
+            users_by_id.insert(user.id, user);
         }
     }
 
-    Ok(Json(users))
+    let users = ranked_ids
+        .iter()
+        .filter_map(|id| users_by_id.remove(id))
+        .collect();
+    Ok(users)
+}
+
+/// `BeatmapSearchQuery` type. Same parameters as the official beatmap search. Unknown parameters
+/// are ignored
+#[derive(Deserialize, JsonSchema, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BeatmapSearchQuery {
+    /// Search text
+    q: Option<String>,
+    /// Game mode
+    m: Option<String>,
+    /// Category like `ranked`, `loved` or `any`
+    s: Option<String>,
+    /// Genre
+    g: Option<String>,
+    /// Language
+    l: Option<String>,
+    /// Sort order like `plays_desc`
+    sort: Option<String>,
+    /// Extra filters like `video` or `storyboard`
+    e: Option<String>,
+    /// General filters like `recommended` or `featured_artists`
+    c: Option<String>,
+    /// Rank achieved
+    r: Option<String>,
+    /// `true` to include explicit content
+    nsfw: Option<String>,
+    /// Played filter
+    played: Option<String>,
+    /// Cursor from the previous page
+    cursor_string: Option<String>,
+}
+
+impl BeatmapSearchQuery {
+    /// Encodes the set parameters again to forward them to osu!
+    fn to_query_string(&self) -> Result<String, AppError> {
+        let parameters = [
+            ("q", &self.q),
+            ("m", &self.m),
+            ("s", &self.s),
+            ("g", &self.g),
+            ("l", &self.l),
+            ("sort", &self.sort),
+            ("e", &self.e),
+            ("c", &self.c),
+            ("r", &self.r),
+            ("nsfw", &self.nsfw),
+            ("played", &self.played),
+            ("cursor_string", &self.cursor_string),
+        ];
+        let url = reqwest::Url::parse_with_params(
+            "https://osu.ppy.sh/api/v2/beatmapsets/search",
+            parameters
+                .iter()
+                .filter_map(|(name, value)| Some((*name, value.as_deref()?))),
+        )
+        .map_err(|error| AppError::BadUri(error.to_string()))?;
+        Ok(url.query().unwrap_or_default().to_string())
+    }
 }
 
 #[cached(
-    ty = "CustomCache<String, Json<Vec<BeatmapsetSmall>>>",
+    ty = "CustomCache<BeatmapSearchQuery, Json<Vec<BeatmapsetSmall>>>",
     create = "{CustomCache::new(300)}",
-    convert = r#"{request.uri().to_string()}"#,
+    convert = r#"{search_query.clone()}"#,
     result = true
 )]
 pub async fn osu_beatmap_search(
     Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
-    request: Request,
+    Query(search_query): Query<BeatmapSearchQuery>,
 ) -> Result<Json<Vec<BeatmapsetSmall>>, AppError> {
-    let uri = request.uri().to_string();
-    let query = uri
-        .strip_prefix("/search/map?")
-        .ok_or(AppError::BadUri(uri.clone()))?;
+    let query = search_query.to_query_string()?;
+    if query.len() > MAX_BEATMAP_QUERY_LENGTH {
+        return Err(AppError::StringTooLong(MAX_BEATMAP_QUERY_LENGTH));
+    }
     let beatmap_search_osu = state
         .request
-        .search_map_osu(&auth_data.osu_token, query)
+        .search_map_osu(&auth_data.osu_token, &query)
         .await?;
 
     let users_to_request: Vec<u32> = beatmap_search_osu
@@ -109,20 +289,129 @@ pub async fn osu_beatmap_search(
     Ok(Json(beatmap_search))
 }
 
+/// `SingularBeatmapQuery` type
+#[derive(Deserialize, JsonSchema)]
+pub struct SingularBeatmapQuery {
+    /// Also return the other difficulties of the beatmapset with this text in their names, case
+    /// insensitive. Empty string returns every difficulty. Only the requested difficulty is
+    /// returned when this is not set
+    version: Option<String>,
+}
+
+/// `SingularBeatmapset` type. `BeatmapsetSmall` with the requested difficulty marked
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct SingularBeatmapset {
+    #[serde(flatten)]
+    pub beatmapset: BeatmapsetSmall,
+    /// Id of the difficulty in the path. `beatmaps` can have other difficulties if `version`
+    /// is set
+    pub requested_id: u32,
+}
+
 pub async fn osu_singular_beatmap_serch(
     Path(beatmap_path): Path<PathBeatmapId>,
+    Query(query): Query<SingularBeatmapQuery>,
     Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
-) -> Result<Json<BeatmapsetSmall>, AppError> {
+) -> Result<Json<SingularBeatmapset>, AppError> {
     let beatmap_map = state
         .cached_combined_requester
         .clone()
         .get_beatmaps_with_user(&[beatmap_path.value], &auth_data.osu_token)
         .await?;
+    let mut beatmapset = beatmap_map
+        .into_values()
+        .next()
+        .ok_or(AppError::NonExistingMap(beatmap_path.value))?;
 
-    beatmap_map
+    // Multiple beatmap endpoint doesn't return the other difficulties, they come from the set
+    if let Some(version) = query.version {
+        let version = version.to_lowercase();
+        let full_set = state
+            .request
+            .get_beatmapset_osu(&auth_data.osu_token, beatmapset.id)
+            .await?;
+        beatmapset.beatmaps = full_set
+            .base_beatmapset
+            .beatmaps
+            .into_iter()
+            .filter(|beatmap| {
+                beatmap.id == beatmap_path.value
+                    || beatmap.version.to_lowercase().contains(&version)
+            })
+            .collect();
+    }
+
+    Ok(Json(SingularBeatmapset {
+        beatmapset,
+        requested_id: beatmap_path.value,
+    }))
+}
+
+/// `BeatmapExistence` type. `beatmap` is only set when the beatmap exists
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct BeatmapExistence {
+    pub exists: bool,
+    pub beatmap: Option<BeatmapMetadata>,
+}
+
+/// `BeatmapMetadata` type. Enough to show what is going to be added, without the mapper
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct BeatmapMetadata {
+    pub beatmapset_id: u32,
+    pub title: String,
+    pub artist: String,
+    pub version: String,
+    pub mode: GameMode,
+}
+
+/// Cheaper than [`osu_singular_beatmap_serch`] since the mapper isn't requested. Missing beatmaps
+/// are not an error, so the frontend can tell them apart from failed requests
+pub async fn osu_beatmap_exists(
+    Path(beatmap_path): Path<PathBeatmapId>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<BeatmapExistence>, AppError> {
+    let beatmap_map = state
+        .cached_combined_requester
+        .get_beatmaps_only(&[beatmap_path.value], &auth_data.osu_token)
+        .await?;
+    let beatmap = beatmap_map
         .into_values()
-        .map(Json)
         .next()
-        .ok_or(AppError::NonExistingMap(beatmap_path.value))
+        .map(|beatmap| BeatmapMetadata {
+            beatmapset_id: beatmap.beatmapset_id,
+            title: beatmap.beatmapset.title,
+            artist: beatmap.beatmapset.artist,
+            version: beatmap.version,
+            mode: beatmap.mode,
+        });
+    Ok(Json(BeatmapExistence {
+        exists: beatmap.is_some(),
+        beatmap,
+    }))
+}
+
+/// Beatmap cards for multiple beatmaps, keyed by beatmap id. Beatmaps that couldn't be found are
+/// left out
+pub async fn osu_bulk_beatmap_search(
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+    Json(beatmap_request): Json<BeatmapRequest>,
+) -> Result<Json<HashMap<u32, BeatmapsetSmall>>, AppError> {
+    if beatmap_request.ids.len() + beatmap_request.beatmapset_ids.len() > MAX_BULK_BEATMAPS {
+        return Err(AppError::TooManyBeatmaps(MAX_BULK_BEATMAPS));
+    }
+    let beatmap_ids = beatmap_request
+        .into_beatmap_ids(state.request.as_ref(), &auth_data.osu_token)
+        .await?;
+    if beatmap_ids.len() > MAX_BULK_BEATMAPS {
+        return Err(AppError::TooManyBeatmaps(MAX_BULK_BEATMAPS));
+    }
+
+    let beatmaps = state
+        .cached_combined_requester
+        .get_beatmaps_with_user_partial(&beatmap_ids, &auth_data.osu_token)
+        .await?;
+    Ok(Json(beatmaps))
 }
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Extension, Json};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::{
+    database::{
+        audit::AuditAction,
+        report::{Report, ReportTargetType},
+    },
+    error::AppError,
+    jwt::AuthData,
+    AppState,
+};
+
+use super::{check_length, write_audit};
+
+/// In characters, see [`check_length`]
+const MAX_REPORT_REASON_LENGTH: usize = 1000;
+
+/// Reports are only read by admins, this is enough to stop a single user from flooding them
+const MAX_REPORTS_PER_HOUR: u32 = 5;
+
+/// `ReportRequest` type
+#[derive(Deserialize, JsonSchema)]
+pub struct ReportRequest {
+    pub target_type: ReportTargetType,
+    /// Reported user. For influences, this is the user that added the influence
+    pub target_id: u32,
+    /// Influenced user. Required for `INFLUENCE` reports
+    pub influenced_to: Option<u32>,
+    pub reason: String,
+}
+
+pub async fn create_report(
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+    Json(report): Json<ReportRequest>,
+) -> Result<Json<Report>, AppError> {
+    let reason = report.reason.trim().to_string();
+    if reason.is_empty() {
+        return Err(AppError::EmptyReportReason);
+    }
+    check_length(&reason, MAX_REPORT_REASON_LENGTH)?;
+
+    // Only the existing users and influences can be reported
+    let influenced_to = match report.target_type {
+        ReportTargetType::User => {
+            state.db.get_user_details(report.target_id).await?;
+            None
+        }
+        ReportTargetType::Influence => {
+            let influenced_to = report.influenced_to.ok_or(AppError::InvalidReportTarget)?;
+            state
+                .db
+                .get_influence(report.target_id, influenced_to)
+                .await?;
+            Some(influenced_to)
+        }
+    };
+
+    let report = state
+        .db
+        .create_report(
+            auth_data.user_id,
+            report.target_type,
+            report.target_id,
+            influenced_to,
+            reason,
+            MAX_REPORTS_PER_HOUR,
+        )
+        .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::AddReport,
+        Some(report.target_id),
+        Some(report.id.clone()),
+    )
+    .await;
+    Ok(Json(report))
+}
@@ -0,0 +1,47 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use axum::{extract::Path, Json};
+use schemars::{gen::SchemaGenerator, JsonSchema};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    database::{
+        influence::Influence,
+        user::{User, UserSmall},
+    },
+    error::AppError,
+    osu_api::BeatmapsetSmall,
+};
+
+use super::activity::Activity;
+
+/// Response types that can be requested one by one for frontend codegen. Schemas are generated
+/// once, with the same `schemars` derives that the OpenAPI document uses
+static SCHEMAS: LazyLock<BTreeMap<&'static str, Value>> = LazyLock::new(|| {
+    BTreeMap::from([
+        ("User", schema_value::<User>()),
+        ("UserSmall", schema_value::<UserSmall>()),
+        ("Influence", schema_value::<Influence>()),
+        ("Activity", schema_value::<Activity>()),
+        ("BeatmapsetSmall", schema_value::<BeatmapsetSmall>()),
+    ])
+});
+
+fn schema_value<T: JsonSchema>() -> Value {
+    let schema = SchemaGenerator::default().into_root_schema_for::<T>();
+    serde_json::to_value(schema).expect("JSON schemas are always serializable")
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PathTypeName {
+    #[serde(rename = "type_name")]
+    value: String,
+}
+
+pub async fn get_type_schema(Path(type_name): Path<PathTypeName>) -> Result<Json<Value>, AppError> {
+    let schema = SCHEMAS
+        .get(type_name.value.as_str())
+        .ok_or(AppError::UnknownSchemaType(type_name.value))?;
+    Ok(Json(schema.clone()))
+}
@@ -1,18 +1,36 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use axum::{
-    extract::{Path, State},
+    extract::{OriginalUri, Path, Query, State},
+    response::{IntoResponse, Response},
     Extension, Json,
 };
+use futures::try_join;
+use http::HeaderMap;
+use itertools::Itertools;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    database::user::User, error::AppError, jwt::AuthData,
-    osu_api::cached_requester::cached_osu_user_request, AppState,
+    database::{
+        audit::AuditAction,
+        user::{User, UserSmall},
+    },
+    error::AppError,
+    jwt::{AuthData, TokenMetadata},
+    osu_api::{cached_requester::cached_osu_user_request, BeatmapsetSmall},
+    sanitize::sanitize_user_text,
+    AppState,
 };
 
-use super::{check_multiple_maps, swap_beatmaps, BeatmapRequest, PathBeatmapId, PathUserId};
+use super::{
+    auth::clear_cookie_headers, check_length, check_multiple_maps, filter_new_beatmaps,
+    pagination_headers, swap_beatmaps, write_audit, BeatmapRequest, PaginationQuery, PathBeatmapId,
+    PathUserId, MAX_BIO_LENGTH,
+};
 
 #[derive(Serialize, Deserialize, JsonSchema)]
 pub struct Bio {
@@ -24,6 +42,22 @@ pub struct Order {
     pub influence_user_ids: Vec<u32>,
 }
 
+/// `PurgeQuery` type
+#[derive(Deserialize, JsonSchema)]
+pub struct PurgeQuery {
+    /// Also replace the username and the avatar, and hide the user from the leaderboards
+    #[serde(default)]
+    anonymize: bool,
+}
+
+/// `TokenStatus` type. Expiry of the session token, which is the same as the embedded osu! token
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct TokenStatus {
+    /// Unix timestamp in seconds
+    pub expires_at: u64,
+    pub seconds_remaining: u64,
+}
+
 pub async fn get_me(
     Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
@@ -34,10 +68,49 @@ pub async fn get_me(
         &auth_data.osu_token,
         &mut user.beatmaps,
     )
-    .await?;
+    .await;
     Ok(Json(user))
 }
 
+/// Every token of the user is revoked after the purge, like [`super::auth::logout_all`]
+pub async fn purge_me(
+    Query(query): Query<PurgeQuery>,
+    Extension(auth_data): Extension<AuthData>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, AppError> {
+    state.activity_tracker.start_purge(auth_data.user_id)?;
+    let purge_result = state
+        .db
+        .purge_user(auth_data.user_id, query.anonymize)
+        .await;
+    state
+        .activity_tracker
+        .finish_purge(auth_data.user_id, purge_result.as_ref().ok().cloned())?;
+    purge_result?;
+    state.db.revoke_all_user_tokens(auth_data.user_id).await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::PurgeUser,
+        None,
+        query.anonymize.then(|| "anonymized".to_string()),
+    )
+    .await;
+    Ok(clear_cookie_headers()?.into_response())
+}
+
+/// Only the expiry is returned. The token itself never leaves the cookie
+pub async fn get_token_status(Extension(metadata): Extension<TokenMetadata>) -> Json<TokenStatus> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Json(TokenStatus {
+        expires_at: metadata.expires_at,
+        seconds_remaining: metadata.expires_at.saturating_sub(now),
+    })
+}
+
 /// Returns a database user, If the user is not in database, then returns an osu! API response
 pub async fn get_user(
     Extension(auth_data): Extension<AuthData>,
@@ -63,26 +136,75 @@ pub async fn get_user(
         &auth_data.osu_token,
         &mut user.beatmaps,
     )
-    .await?;
+    .await;
     Ok(Json(user))
 }
 
+/// Newest members for the "new members" list. Only the users that logged in are listed
+pub async fn get_recent_users(
+    Query(pagination): Query<PaginationQuery>,
+    OriginalUri(uri): OriginalUri,
+    State(state): State<Arc<AppState>>,
+) -> Result<(HeaderMap, Json<Vec<UserSmall>>), AppError> {
+    let (users, total) = try_join!(
+        state
+            .db
+            .get_recent_users(pagination.start, pagination.limit),
+        state.db.get_recent_user_count()
+    )?;
+    let headers = pagination_headers(&uri, pagination.start, pagination.limit, total)?;
+    Ok((headers, Json(users)))
+}
+
+/// Every beatmap the user credited on their profile and in their influences. Beatmaps from the
+/// same beatmapset are merged into a single entry
+pub async fn get_user_all_beatmaps(
+    Extension(auth_data): Extension<AuthData>,
+    Path(user_id): Path<PathUserId>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<BeatmapsetSmall>>, AppError> {
+    let beatmap_ids = state.db.get_all_credited_beatmaps(user_id.value).await?;
+    let mut beatmap_map = state
+        .cached_combined_requester
+        .get_beatmaps_with_user_partial(&beatmap_ids, &auth_data.osu_token)
+        .await?;
+
+    let mut beatmapsets: Vec<BeatmapsetSmall> = Vec::new();
+    for beatmap_id in beatmap_ids {
+        let Some(beatmapset) = beatmap_map.remove(&beatmap_id) else {
+            continue;
+        };
+        match beatmapsets.iter_mut().find(|set| set.id == beatmapset.id) {
+            Some(existing_set) => existing_set.beatmaps.extend(beatmapset.beatmaps),
+            None => beatmapsets.push(beatmapset),
+        }
+    }
+    Ok(Json(beatmapsets))
+}
+
 pub async fn update_user_bio(
     Extension(auth_data): Extension<AuthData>,
     State(state): State<Arc<AppState>>,
     Json(bio): Json<Bio>,
 ) -> Result<Json<User>, AppError> {
-    const MAX_BIO_LENGTH: usize = 5000;
-    if bio.bio.len() > MAX_BIO_LENGTH {
-        return Err(AppError::StringTooLong);
-    }
-    let mut user = state.db.update_bio(auth_data.user_id, bio.bio).await?;
+    // Sanitized text is never longer, so the raw text is checked to bound the sanitization work
+    check_length(&bio.bio, *MAX_BIO_LENGTH)?;
+    let bio = sanitize_user_text(&bio.bio);
+    let mut user = state.db.update_bio(auth_data.user_id, bio.clone()).await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::EditBio,
+        None,
+        Some(bio),
+    )
+    .await;
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut user.beatmaps,
     )
-    .await?;
+    .await;
     Ok(Json(user))
 }
 
@@ -91,24 +213,44 @@ pub async fn add_user_beatmap(
     State(state): State<Arc<AppState>>,
     Json(beatmaps): Json<BeatmapRequest>,
 ) -> Result<Json<User>, AppError> {
-    let beatmaps: Vec<u32> = beatmaps.ids.into_iter().collect();
-    check_multiple_maps(
-        state.cached_combined_requester.clone(),
-        &auth_data.osu_token,
-        &beatmaps,
-    )
-    .await?;
+    let expected_mode = beatmaps.expected_mode;
+    let beatmaps = beatmaps
+        .into_beatmap_ids(state.request.as_ref(), &auth_data.osu_token)
+        .await?;
 
-    let mut user = state
-        .db
-        .add_beatmap_to_user(auth_data.user_id, beatmaps)
+    let user = state.db.get_user_details(auth_data.user_id).await?;
+    let new_beatmaps = filter_new_beatmaps(&user.beatmaps, beatmaps);
+    let mut user = if new_beatmaps.is_empty() {
+        user
+    } else {
+        check_multiple_maps(
+            state.cached_combined_requester.clone(),
+            &auth_data.osu_token,
+            &new_beatmaps,
+            expected_mode,
+        )
         .await?;
+        let details = new_beatmaps.iter().join(",");
+        let user = state
+            .db
+            .add_beatmap_to_user(auth_data.user_id, new_beatmaps)
+            .await?;
+        write_audit(
+            &state.db,
+            auth_data.user_id,
+            AuditAction::AddUserBeatmap,
+            None,
+            Some(details),
+        )
+        .await;
+        user
+    };
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut user.beatmaps,
     )
-    .await?;
+    .await;
     Ok(Json(user))
 }
 
@@ -121,12 +263,20 @@ pub async fn delete_user_beatmap(
         .db
         .remove_beatmap_from_user(auth_data.user_id, beatmap_id.value)
         .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::RemoveUserBeatmap,
+        None,
+        Some(beatmap_id.value.to_string()),
+    )
+    .await;
     swap_beatmaps(
         state.cached_combined_requester.clone(),
         &auth_data.osu_token,
         &mut user.beatmaps,
     )
-    .await?;
+    .await;
     Ok(Json(user))
 }
 
@@ -139,5 +289,13 @@ pub async fn set_influence_order(
         .db
         .set_influence_order(auth_data.user_id, &order_request.influence_user_ids)
         .await?;
+    write_audit(
+        &state.db,
+        auth_data.user_id,
+        AuditAction::EditInfluenceOrder,
+        None,
+        Some(order_request.influence_user_ids.iter().join(",")),
+    )
+    .await;
     Ok(())
 }
@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+use crate::{env::seconds_from_env, error::AppError};
+
+/// Builds the HTTP client for outbound requests. This should be constructed once and cloned into
+/// the clients that need it. Clones share the same connection pool.
+///
+/// Timeouts can be configured with `HTTP_CONNECT_TIMEOUT` and `HTTP_TIMEOUT` environment
+/// variables in seconds. Without a timeout, a hung connection would hold a semaphore permit
+/// forever.
+pub fn build_http_client() -> Result<reqwest::Client, AppError> {
+    let client = reqwest::Client::builder()
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(32)
+        .connect_timeout(seconds_from_env("HTTP_CONNECT_TIMEOUT", 5))
+        .timeout(seconds_from_env("HTTP_TIMEOUT", 30))
+        .build()?;
+    Ok(client)
+}
+
+/// Separates timeouts from the rest of the reqwest errors
+pub fn map_reqwest_error(error: reqwest::Error) -> AppError {
+    if error.is_timeout() {
+        AppError::UpstreamTimeout
+    } else {
+        AppError::Reqwest(error)
+    }
+}
@@ -1,6 +1,9 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use jwt_simple::{
     algorithms::{HS256Key, MACLike},
     claims::Claims,
+    common::VerificationOptions,
     reexports::coarsetime::Duration,
 };
 use serde::{Deserialize, Serialize};
@@ -12,8 +15,49 @@ pub struct AuthData {
     pub osu_token: String,
     pub user_id: u32,
     pub username: String,
+    /// Only tokens created with admin login have this
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// Signed custom claims of the session token. `iat` only has second precision, which can't tell
+/// a token issued right before a logout-all from one issued right after it
+#[derive(Serialize, Deserialize)]
+struct SessionClaims {
+    #[serde(flatten)]
+    auth_data: AuthData,
+    /// Tokens created before this claim only have `iat`
+    #[serde(default)]
+    issued_at_ms: Option<u64>,
+}
+
+/// Registered claims of a verified token. Used for revocation checks
+#[derive(Clone, Debug)]
+pub struct TokenMetadata {
+    pub jwt_id: String,
+    /// Milliseconds since the Unix epoch
+    pub issued_at_ms: u64,
+    pub expires_at: u64,
+}
+
+/// Milliseconds since the Unix epoch
+pub fn unix_millis_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Custom claims of the signed OAuth `state` parameter
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OAuthState {
+    /// Where the user is sent after a successful login. Already checked against the allowlist
+    pub redirect_uri: String,
 }
 
+/// OAuth `state` tokens are only valid for this many seconds
+const OAUTH_STATE_DURATION: u64 = 600;
+
 pub struct JwtUtil {
     pub key: HS256Key,
 }
@@ -32,20 +76,68 @@ impl JwtUtil {
         username: String,
         osu_token: String,
         duration: u32,
+        admin: bool,
     ) -> Result<String, AppError> {
-        let additional_data = AuthData {
-            osu_token,
-            user_id: id,
-            username,
+        let additional_data = SessionClaims {
+            auth_data: AuthData {
+                osu_token,
+                user_id: id,
+                username,
+                admin,
+            },
+            issued_at_ms: Some(unix_millis_now()),
         };
-        let claims =
+        let mut claims =
             Claims::with_custom_claims(additional_data, Duration::from_secs(duration.into()));
+        // jwt-simple doesn't have a jti generator, nonce is random enough to be used as one
+        let jwt_id = claims.create_nonce();
+        let claims = claims.with_jwt_id(jwt_id);
         let token = self.key.authenticate(claims)?;
         Ok(token)
     }
 
-    pub fn verify_jwt(&self, token: &str) -> Result<AuthData, AppError> {
-        let claims = self.key.verify_token::<AuthData>(token, None)?;
+    pub fn verify_jwt(&self, token: &str) -> Result<(AuthData, TokenMetadata), AppError> {
+        let claims = self.key.verify_token::<SessionClaims>(token, None)?;
+        // Tokens without these claims are created before revocation support. Treat them as invalid
+        let (Some(jwt_id), Some(issued_at), Some(expires_at)) =
+            (claims.jwt_id, claims.issued_at, claims.expires_at)
+        else {
+            return Err(AppError::JwtVerification);
+        };
+        let metadata = TokenMetadata {
+            jwt_id,
+            // Start of the second is the earliest the old tokens could be issued, so they are
+            // revoked even if they are issued in the same second
+            issued_at_ms: claims
+                .custom
+                .issued_at_ms
+                .unwrap_or(issued_at.as_secs() * 1000),
+            expires_at: expires_at.as_secs(),
+        };
+        Ok((claims.custom.auth_data, metadata))
+    }
+
+    /// Returns the signed `state` and its nonce. The nonce is kept in a cookie on the client to
+    /// bind the state to the browser that started the login
+    pub fn create_oauth_state(&self, redirect_uri: String) -> Result<(String, String), AppError> {
+        let mut claims = Claims::with_custom_claims(
+            OAuthState { redirect_uri },
+            Duration::from_secs(OAUTH_STATE_DURATION),
+        );
+        let nonce = claims.create_nonce();
+        let state = self.key.authenticate(claims)?;
+        Ok((state, nonce))
+    }
+
+    pub fn verify_oauth_state(&self, state: &str, nonce: &str) -> Result<OAuthState, AppError> {
+        let options = VerificationOptions {
+            required_nonce: Some(nonce.to_string()),
+            ..Default::default()
+        };
+        let claims = self
+            .key
+            .verify_token::<OAuthState>(state, Some(options))
+            .map_err(|_| AppError::InvalidOAuthState)?;
         Ok(claims.custom)
     }
 }
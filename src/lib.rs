@@ -1,28 +1,35 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use aide::axum::routing::{delete_with, get_with, patch_with, post_with};
 use aide::axum::ApiRouter;
 use axum::middleware;
-use axum::routing::any;
+use axum::routing::{any, get};
 use database::leaderboard::{LeaderboardBeatmap, LeaderboardUser};
 use database::DatabaseClient;
+use documentation::{admin_only, authenticated};
 use handlers::activity::ActivityTracker;
 use handlers::graph_vizualizer::GraphCache;
-use handlers::leaderboard::LeaderboardCache;
+use handlers::idempotency::IdempotencyStore;
+use handlers::leaderboard::{LeaderboardBroadcaster, LeaderboardCache};
 use jwt::JwtUtil;
 use osu_api::cached_requester::CombinedRequester;
 use osu_api::credentials_grant::CredentialsGrantClient;
 use osu_api::request::Requester;
+use osu_api::GameMode;
 
 pub mod custom_cache;
 pub mod daily_update;
 pub mod database;
 pub mod documentation;
+pub mod env;
 pub mod error;
 pub mod handlers;
+pub mod http_client;
 pub mod jwt;
 pub mod osu_api;
 pub mod retry;
+pub mod sanitize;
 
 pub struct AppState {
     pub db: Arc<DatabaseClient>,
@@ -32,8 +39,10 @@ pub struct AppState {
     pub activity_tracker: Arc<ActivityTracker>,
     pub credentials_grant_client: Arc<CredentialsGrantClient>,
     pub user_leaderboard_cache: LeaderboardCache<(bool, Option<String>), LeaderboardUser>,
-    pub beatmap_leaderboard_cache: LeaderboardCache<bool, LeaderboardBeatmap>,
+    pub beatmap_leaderboard_cache: LeaderboardCache<(bool, Option<GameMode>), LeaderboardBeatmap>,
+    pub user_leaderboard_broadcaster: Arc<LeaderboardBroadcaster>,
     pub graph_cache: GraphCache,
+    pub idempotency_store: IdempotencyStore,
 }
 
 impl AppState {
@@ -45,125 +54,379 @@ impl AppState {
         let cached_combined_requester =
             CombinedRequester::new(request.clone(), "https://osu.ppy.sh");
 
-        let activity_tracker = ActivityTracker::new(
-            db.clone(),
-            50,
-            cached_combined_requester.clone(),
-            credentials_grant_client.clone(),
-        )
-        .await
-        // TODO: better handle errors
-        .expect("failed to initialize activity tracker");
+        // Activity stream is on unless it's explicitly disabled
+        let activity_stream_enabled = std::env::var("ENABLE_ACTIVITY_STREAM")
+            .map_or(true, |value| value.to_lowercase() != "false");
+        let defer_beatmap_hydration = std::env::var("DEFER_ACTIVITY_BEATMAPS")
+            .is_ok_and(|value| value.to_lowercase() == "true");
+        let activity_tracker = if activity_stream_enabled {
+            ActivityTracker::new(
+                db.clone(),
+                50,
+                cached_combined_requester.clone(),
+                credentials_grant_client.clone(),
+                defer_beatmap_hydration,
+            )
+            .await
+            // TODO: better handle errors
+            .expect("failed to initialize activity tracker")
+        } else {
+            tracing::info!("Activity stream is disabled");
+            ActivityTracker::disabled(
+                cached_combined_requester.clone(),
+                credentials_grant_client.clone(),
+            )
+        };
 
-        Arc::new(AppState {
+        let state = Arc::new(AppState {
             db,
             request: request.clone(),
             jwt: JwtUtil::new_jwt(),
             cached_combined_requester,
             activity_tracker,
             credentials_grant_client,
-            user_leaderboard_cache: LeaderboardCache::new(300),
-            beatmap_leaderboard_cache: LeaderboardCache::new(300),
+            user_leaderboard_cache: LeaderboardCache::from_env(
+                300,
+                "USER_LEADERBOARD_CACHE_SIZE",
+                500,
+            ),
+            beatmap_leaderboard_cache: LeaderboardCache::from_env(
+                300,
+                "BEATMAP_LEADERBOARD_CACHE_SIZE",
+                200,
+            ),
+            user_leaderboard_broadcaster: LeaderboardBroadcaster::new(Duration::from_secs(300)),
             graph_cache: GraphCache::new(600),
-        })
+            idempotency_store: IdempotencyStore::new(3600),
+        });
+        state
+            .user_leaderboard_broadcaster
+            .clone()
+            .start_loop(Arc::downgrade(&state));
+        state
     }
 }
 
 pub fn routes(state: Arc<AppState>) -> ApiRouter<Arc<AppState>> {
     ApiRouter::new()
+        .api_route(
+            "/admin/migrations",
+            get_with(handlers::admin::get_migration_status, |op| {
+                admin_only(op)
+                    .tag("Admin")
+                    .description("Applied and pending migration scripts. Admin only")
+            }),
+        )
+        .api_route(
+            "/admin/audit",
+            get_with(handlers::admin::get_audit_logs, |op| {
+                admin_only(op)
+                    .tag("Admin")
+                    .description("Audit log of mutations, newest first. Admin only")
+            }),
+        )
+        .api_route(
+            "/admin/reports",
+            get_with(handlers::admin::get_reports, |op| {
+                admin_only(op)
+                    .tag("Admin")
+                    .description("Open reports, oldest first. Admin only")
+            }),
+        )
+        .api_route(
+            "/admin/reports/:report_id/resolve",
+            post_with(handlers::admin::resolve_report, |op| {
+                admin_only(op)
+                    .tag("Admin")
+                    .description("Resolves a report. Admin only")
+            }),
+        )
+        .api_route(
+            "/admin/users/merge",
+            post_with(handlers::admin::merge_users, |op| {
+                admin_only(op).tag("Admin").description(
+                    "Moves influences, mentions and beatmaps of `source` user to `target` user 
+                    and deletes `source`. Admin only",
+                )
+            }),
+        )
+        .api_route(
+            "/admin/cache/warm",
+            post_with(handlers::admin::warm_caches, |op| {
+                admin_only(op)
+                    .tag("Admin")
+                    .description("Fills graph and leaderboard caches. Admin only")
+            }),
+        )
+        .api_route(
+            "/admin/leaderboard/recompute",
+            post_with(handlers::admin::recompute_leaderboards, |op| {
+                admin_only(op).tag("Admin").description(
+                    "Drops cached leaderboards and computes the global ones again, with the time 
+                    each query took. `/ws/leaderboard` clients get the new user leaderboard. 
+                    Admin only",
+                )
+            }),
+        )
+        .api_route(
+            "/admin/broadcasts",
+            get_with(handlers::admin::get_broadcast_stats, |op| {
+                admin_only(op).tag("Admin").description(
+                    "Open websocket connections and how many messages they missed by falling 
+                    behind, in total and for each open connection that fell behind. Admin only",
+                )
+            }),
+        )
+        .route_layer(middleware::from_fn(handlers::auth::check_admin))
         .api_route(
             "/search/map",
             get_with(handlers::osu_search::osu_beatmap_search, |op| {
-                op.tag("Search").description(
+                authenticated(op).tag("Search").description(
                     "osu! beatmap search. 
                     Use the same query parameters in official beatmap search",
                 )
             }),
         )
+        .api_route(
+            "/search/map/bulk",
+            post_with(handlers::osu_search::osu_bulk_beatmap_search, |op| {
+                authenticated(op).tag("Search").description(
+                    "Beatmap cards for multiple beatmaps, keyed by beatmap id. 
+                    Accepts up to 100 beatmaps and beatmapsets",
+                )
+            }),
+        )
         .api_route(
             "/search/map/:beatmap_id",
             get_with(handlers::osu_search::osu_singular_beatmap_serch, |op| {
-                op.tag("Search").description(
+                authenticated(op).tag("Search").description(
                     "Returns a single map for manual beatmap id field. 
                     Don't confuse it with `/search/map` endpoint which doesn't 
-                    have path parameter",
+                    have path parameter. Other difficulties of the set can be included 
+                    with `version` filter",
+                )
+            }),
+        )
+        .api_route(
+            "/search/map/:beatmap_id/exists",
+            get_with(handlers::osu_search::osu_beatmap_exists, |op| {
+                authenticated(op).tag("Search").description(
+                    "Checks a beatmap id before adding it. Missing beatmaps return 
+                    `exists: false` instead of 404",
                 )
             }),
         )
         .api_route(
             "/search/user/:query",
-            get_with(handlers::osu_search::osu_user_search, |op| op.tag("Search")),
+            get_with(handlers::osu_search::osu_user_search, |op| {
+                authenticated(op).tag("Search")
+            }),
         )
         .api_route(
             "/influence",
-            post_with(handlers::influence::add_influence, |op| op.tag("Influence")),
+            post_with(handlers::influence::add_influence, |op| {
+                authenticated(op).tag("Influence")
+            }),
         )
         .api_route(
             "/influence/influences/:user_id",
             get_with(handlers::influence::get_user_influences, |op| {
-                op.tag("Influence")
+                authenticated(op).tag("Influence")
+            }),
+        )
+        .api_route(
+            "/influence/influences/bulk",
+            post_with(handlers::influence::get_bulk_user_influences, |op| {
+                authenticated(op).tag("Influence").description(
+                    "Influences of multiple users, keyed by user id. Accepts up to 50 user ids",
+                )
+            }),
+        )
+        .api_route(
+            "/influence/status",
+            post_with(handlers::influence::get_influence_status, |op| {
+                authenticated(op).tag("Influence").description(
+                    "Whether the logged in user has an influence to each given user, 
+                    keyed by user id. Accepts up to 200 user ids",
+                )
             }),
         )
         .api_route(
             "/influence/mentions/:user_id",
             get_with(handlers::influence::get_user_mentions, |op| {
-                op.tag("Influence")
+                authenticated(op).tag("Influence")
             }),
         )
         .api_route(
             "/influence/:influenced_to",
-            delete_with(handlers::influence::delete_influence, |op| {
-                op.tag("Influence")
+            get_with(handlers::influence::get_influence, |op| {
+                authenticated(op)
+                    .tag("Influence")
+                    .description("Influence of the logged in user to the given user")
+            })
+            .delete_with(handlers::influence::delete_influence, |op| {
+                authenticated(op).tag("Influence")
             }),
         )
         .api_route(
             "/influence/:influenced_to/map",
             patch_with(handlers::influence::add_influence_beatmap, |op| {
-                op.tag("Influence")
+                authenticated(op).tag("Influence")
             }),
         )
         .api_route(
             "/influence/:influenced_to/map/:beatmap_id",
             delete_with(handlers::influence::remove_influence_beatmap, |op| {
-                op.tag("Influence")
+                authenticated(op).tag("Influence")
             }),
         )
         .api_route(
             "/influence/:influenced_to/description",
             patch_with(handlers::influence::update_influence_description, |op| {
-                op.tag("Influence")
+                authenticated(op).tag("Influence")
+            }),
+        )
+        .api_route(
+            "/influence/:influenced_to/tag",
+            patch_with(handlers::influence::update_influence_tag, |op| {
+                authenticated(op).tag("Influence")
+            }),
+        )
+        .api_route(
+            "/influence/:influenced_to/pin",
+            post_with(handlers::influence::pin_influence, |op| {
+                authenticated(op)
+                    .tag("Influence")
+                    .description("Pins the influence. Previously pinned influence is unpinned")
+            })
+            .delete_with(handlers::influence::unpin_influence, |op| {
+                authenticated(op).tag("Influence")
+            }),
+        )
+        .api_route(
+            "/influence/types",
+            patch_with(handlers::influence::update_influence_types, |op| {
+                authenticated(op)
+                    .tag("Influence")
+                    .description("Updates the types of multiple influences at once")
             }),
         )
         .api_route(
             "/influence/:influenced_to/type/:type_id",
             patch_with(handlers::influence::update_influence_type, |op| {
-                op.tag("Influence")
+                authenticated(op).tag("Influence")
             }),
         )
         .api_route(
             "/users/me",
-            get_with(handlers::user::get_me, |op| op.tag("User")),
+            get_with(handlers::user::get_me, |op| authenticated(op).tag("User")).delete_with(
+                handlers::user::purge_me,
+                |op| {
+                    authenticated(op)
+                        .tag("User")
+                        .description(
+                            "Deletes influences and mentions of the logged in user, clears the bio 
+                            and beatmaps, then logs out of every session. Details of the user's 
+                            audit logs and reasons of their reports are cleared too. No activity 
+                            is created",
+                        )
+                        .response::<200, ()>()
+                },
+            ),
+        )
+        .api_route(
+            "/users/me/token",
+            get_with(handlers::user::get_token_status, |op| {
+                authenticated(op)
+                    .tag("User")
+                    .description("Expiry of the current session and its osu! token")
+            }),
+        )
+        .api_route(
+            "/users/recent",
+            get_with(handlers::user::get_recent_users, |op| {
+                authenticated(op)
+                    .tag("User")
+                    .description("Users that logged in, newest first")
+            }),
         )
         .api_route(
             "/users/:user_id",
-            get_with(handlers::user::get_user, |op| op.tag("User")),
+            get_with(handlers::user::get_user, |op| authenticated(op).tag("User")),
+        )
+        .api_route(
+            "/users/:user_id/all-beatmaps",
+            get_with(handlers::user::get_user_all_beatmaps, |op| {
+                authenticated(op).tag("User").description(
+                    "Beatmaps credited by the user in their profile and all of their influences. 
+                    Returned as beatmapsets like the rest of the beatmap fields, difficulties of 
+                    the same set are merged into one entry. Beatmaps osu! doesn't return are left 
+                    out",
+                )
+            }),
+        )
+        .api_route(
+            "/users/:user_id/connections",
+            get_with(handlers::influence::get_user_connections, |op| {
+                authenticated(op).tag("User").description(
+                    "Influences and mentions of the user together. Both lists are paginated 
+                    separately, totals are in the body instead of the headers",
+                )
+            }),
+        )
+        .api_route(
+            "/users/:user_id/influence-tree",
+            get_with(handlers::influence::get_influence_tree, |op| {
+                authenticated(op).tag("User").description(
+                    "Influences of the user as a nested tree, up to `depth` levels. Users that are 
+                    already in the tree are marked as repeated and not expanded again",
+                )
+            }),
         )
         .api_route(
             "/users/bio",
-            patch_with(handlers::user::update_user_bio, |op| op.tag("User")),
+            patch_with(handlers::user::update_user_bio, |op| {
+                authenticated(op).tag("User")
+            }),
         )
         .api_route(
             "/users/map",
-            patch_with(handlers::user::add_user_beatmap, |op| op.tag("User")),
+            patch_with(handlers::user::add_user_beatmap, |op| {
+                authenticated(op).tag("User")
+            }),
         )
         .api_route(
             "/users/map/:beatmap_id",
-            delete_with(handlers::user::delete_user_beatmap, |op| op.tag("User")),
+            delete_with(handlers::user::delete_user_beatmap, |op| {
+                authenticated(op).tag("User")
+            }),
         )
         .api_route(
             "/users/influence-order",
-            post_with(handlers::user::set_influence_order, |op| op.tag("User")),
+            post_with(handlers::user::set_influence_order, |op| {
+                authenticated(op).tag("User")
+            }),
+        )
+        .api_route(
+            "/report",
+            post_with(handlers::report::create_report, |op| {
+                authenticated(op).tag("Report").description(
+                    "Reports a user profile or an influence to admins. 
+                    Limited to 5 reports per hour",
+                )
+            }),
+        )
+        .api_route(
+            "/oauth/logout-all",
+            post_with(handlers::auth::logout_all, |op| {
+                authenticated(op).tag("Auth").response::<200, ()>()
+            }),
         )
+        // Layers run in reverse order, idempotency check needs the auth data from the jwt check
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::idempotency::check_idempotency_key,
+        ))
         .route_layer(middleware::from_fn_with_state(
             state,
             handlers::auth::check_jwt_token,
@@ -171,10 +434,28 @@ pub fn routes(state: Arc<AppState>) -> ApiRouter<Arc<AppState>> {
         .api_route(
             "/activity",
             get_with(handlers::activity::get_latest_activities, |op| {
-                op.tag("Activity")
+                op.tag("Activity").description(
+                    "Latest activities. Always empty when `ENABLE_ACTIVITY_STREAM` is false. 
+                    `/ws` connections are closed right after the upgrade and `/activity/sse` 
+                    ends without events in that case",
+                )
             }),
         )
         .route("/ws", any(handlers::activity::ws_handler))
+        .route("/activity/sse", get(handlers::activity::sse_handler))
+        .route("/ws/leaderboard", any(handlers::leaderboard::ws_handler))
+        .api_route(
+            "/oauth/login",
+            get_with(handlers::auth::osu_oauth2_login, |op| {
+                op.tag("Auth")
+                    .description(
+                        "Redirects to osu! authorization page. Requests `public` and `identify` 
+                        scopes unless configured otherwise.
+                        Optional `redirect` parameter has to be in the redirect allowlist",
+                    )
+                    .response::<302, ()>()
+            }),
+        )
         .api_route(
             "/oauth/osu-redirect",
             get_with(handlers::auth::osu_oauth2_redirect, |op| {
@@ -200,7 +481,19 @@ pub fn routes(state: Arc<AppState>) -> ApiRouter<Arc<AppState>> {
         .api_route(
             "/leaderboard/beatmap",
             get_with(handlers::leaderboard::get_beatmap_leaderboard, |op| {
-                op.tag("Leaderboard")
+                op.tag("Leaderboard").description(
+                    "Most credited beatmaps. With `mode`, the cached top beatmaps are filtered 
+                    by mode. Pagination and the total count are then about the filtered list",
+                )
+            }),
+        )
+        .api_route(
+            "/schema/:type_name",
+            get_with(handlers::schema::get_type_schema, |op| {
+                op.tag("Schema").description(
+                    "JSON schema of a single response type for codegen. Available types are 
+                    `User`, `UserSmall`, `Influence`, `Activity` and `BeatmapsetSmall`",
+                )
             }),
         )
         .api_route(
@@ -209,4 +502,13 @@ pub fn routes(state: Arc<AppState>) -> ApiRouter<Arc<AppState>> {
                 op.tag("Graph")
             }),
         )
+        .api_route(
+            "/graph/stream",
+            get_with(handlers::graph_vizualizer::stream_graph_data, |op| {
+                op.tag("Graph").description(
+                    "Streams the graph as newline delimited JSON. Nodes come first, then links. 
+                    Each line has a `type` field that is either `node` or `link`",
+                )
+            }),
+        )
 }
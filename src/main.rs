@@ -1,20 +1,46 @@
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use aide::{axum::ApiRouter, openapi::OpenApi};
+use aide::{
+    axum::ApiRouter,
+    openapi::{OpenApi, Server},
+};
 use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    middleware,
     response::{Html, IntoResponse},
     routing::get,
     Extension, Json,
 };
 use axum_swagger_ui::swagger_ui;
+use http::{header::LINK, Request};
 use mapper_influences_backend_rs::{
-    daily_update::update_routine,
+    daily_update::{update_routine, UpdateSchedule},
     database::DatabaseClient,
+    documentation::security_schemes,
+    error::panic_response,
+    handlers::{
+        client_ip::rewrite_client_ip,
+        compression::{prioritize_compression, COMPRESSION_ALGORITHMS},
+        idempotency::IDEMPOTENT_REPLAYED,
+        X_TOTAL_COUNT,
+    },
+    http_client::build_http_client,
     osu_api::{credentials_grant::CredentialsGrantClient, request::OsuApiRequestClient},
     routes, AppState,
 };
-use tower_http::{compression::CompressionLayer, cors::CorsLayer, trace::TraceLayer};
-use tracing::info;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    compression::{
+        predicate::{And, SizeAbove},
+        CompressionLayer, DefaultPredicate, Predicate,
+    },
+    cors::CorsLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+    CompressionLevel,
+};
+use tracing::{info, warn};
 use tracing_subscriber::fmt::format::FmtSpan;
 
 #[tokio::main]
@@ -30,7 +56,30 @@ async fn main() {
     let db = DatabaseClient::new(&url)
         .await
         .expect("failed to initialize db connection");
-    let request = Arc::new(OsuApiRequestClient::new(10));
+
+    // Off by default. Migrations are applied manually in production
+    let run_migrations = std::env::var("RUN_MIGRATIONS");
+    if run_migrations.is_ok_and(|value| value.to_lowercase() == "true") {
+        info!("Running database migrations");
+        db.run_migrations()
+            .await
+            .expect("Failed to apply migrations");
+    }
+
+    match db.get_migration_status().await {
+        Ok(status) if !status.pending.is_empty() => {
+            warn!(
+                "There are {} pending migrations: {}",
+                status.pending.len(),
+                status.pending.join(", ")
+            );
+        }
+        Ok(_) => info!("Database migrations are up to date"),
+        Err(error) => warn!("Failed to check migration status: {}", error),
+    }
+
+    let http_client = build_http_client().expect("Failed to build HTTP client");
+    let request = Arc::new(OsuApiRequestClient::from_env(http_client, 10));
     let credentials_grant_client = CredentialsGrantClient::new(request.clone())
         .await
         .expect("Failed to initialize credentials grant client");
@@ -39,14 +88,20 @@ async fn main() {
     let start_var = std::env::var("DAILY_UPDATE");
     if start_var.is_ok_and(|value| value.to_lowercase() == "true") {
         let initial_delay = 10;
+        let schedule = UpdateSchedule::from_env();
         info!(
-            "starting daily updates after initial delay of {} seconds",
+            "starting daily updates after initial delay of {} seconds. Updates run every {} seconds \
+            with {} seconds between users, up to {} users per run",
             initial_delay,
+            schedule.interval.as_secs(),
+            schedule.user_spacing.as_secs(),
+            schedule.users_per_run(),
         );
         tokio::spawn(update_routine(
             credentials_grant_client,
             db.clone(),
             Duration::from_secs(initial_delay),
+            schedule,
         ));
     }
 
@@ -54,15 +109,18 @@ async fn main() {
         println!("{error}");
     });
     aide::gen::extract_schemas(true);
-    let mut api = OpenApi::default();
+    let mut api = OpenApi {
+        servers: openapi_servers_from_env(),
+        ..OpenApi::default()
+    };
 
     // TODO: restrict this after full deployment
-    let cors = CorsLayer::very_permissive();
-    let compression = CompressionLayer::new()
-        .gzip(true)
-        .deflate(true)
-        .zstd(true)
-        .br(true);
+    let cors = CorsLayer::very_permissive().expose_headers([
+        X_TOTAL_COUNT.clone(),
+        LINK,
+        IDEMPOTENT_REPLAYED.clone(),
+    ]);
+    let compression = compression_layer_from_env();
 
     let app = ApiRouter::new()
         .route(
@@ -86,10 +144,36 @@ async fn main() {
             get(|Extension(api): Extension<Arc<OpenApi>>| async { Json(api).into_response() }),
         )
         .nest("/", routes(state.clone()))
-        .finish_api(&mut api)
+        .finish_api_with(&mut api, security_schemes)
+        .layer(CatchPanicLayer::custom(panic_response))
         .layer(cors)
         .layer(compression)
-        .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn(prioritize_compression))
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
+                let request_id = request
+                    .headers()
+                    .get("x-request-id")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default();
+                let client_ip = request
+                    .extensions()
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(address)| address.ip().to_string())
+                    .unwrap_or_default();
+                tracing::debug_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    request_id,
+                    client_ip,
+                )
+            }),
+        )
+        // Outside of the trace layer so that the request span has the rewritten address
+        .layer(middleware::from_fn(rewrite_client_ip))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         .layer(Extension(Arc::new(api)))
         .with_state(state)
         .into_make_service_with_connect_info::<SocketAddr>();
@@ -101,3 +185,66 @@ async fn main() {
     info!("listening on {}", listener.local_addr().unwrap());
     axum::serve(listener, app).await.unwrap();
 }
+
+/// `OPENAPI_SERVERS` is a comma separated list of base URLs for the `servers` field of the
+/// OpenAPI spec, so generated clients know where to send the requests. Clients fall back to the
+/// host that serves the spec when this is not set
+fn openapi_servers_from_env() -> Vec<Server> {
+    let Ok(servers) = std::env::var("OPENAPI_SERVERS") else {
+        return Vec::new();
+    };
+    servers
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| Server {
+            url: url.trim_end_matches('/').to_string(),
+            ..Server::default()
+        })
+        .collect()
+}
+
+/// `COMPRESSION_ALGORITHMS` is a comma separated list of enabled algorithms out of `gzip`,
+/// `deflate`, `zstd` and `br`, in priority order. The first one the client accepts is used, see
+/// [`prioritize_compression`].
+/// `COMPRESSION_LEVEL` is one of `fastest`, `default`, `best` or an algorithm specific number.
+/// `COMPRESSION_MIN_SIZE` is the body size in bytes below which responses are sent uncompressed,
+/// 1024 by default.
+/// Every algorithm is enabled with default level when these are not set
+fn compression_layer_from_env() -> CompressionLayer<And<DefaultPredicate, SizeAbove>> {
+    let level = match std::env::var("COMPRESSION_LEVEL") {
+        Ok(value) => match value.to_lowercase().as_str() {
+            "fastest" => CompressionLevel::Fastest,
+            "default" => CompressionLevel::Default,
+            "best" => CompressionLevel::Best,
+            level => CompressionLevel::Precise(
+                level
+                    .parse::<i32>()
+                    .expect("COMPRESSION_LEVEL environment variable is not valid"),
+            ),
+        },
+        Err(_) => CompressionLevel::Default,
+    };
+
+    // Error bodies from `AppError` are tiny json objects. Compressing them is a waste of CPU
+    // and it can even make them larger
+    let min_size = match std::env::var("COMPRESSION_MIN_SIZE") {
+        Ok(value) => value
+            .parse::<u16>()
+            .expect("COMPRESSION_MIN_SIZE environment variable is not a valid number"),
+        Err(_) => 1024,
+    };
+
+    let is_enabled = |algorithm: &str| {
+        COMPRESSION_ALGORITHMS
+            .iter()
+            .any(|enabled| enabled == algorithm)
+    };
+    CompressionLayer::new()
+        .gzip(is_enabled("gzip"))
+        .deflate(is_enabled("deflate"))
+        .zstd(is_enabled("zstd"))
+        .br(is_enabled("br"))
+        .quality(level)
+        .compress_when(DefaultPredicate::new().and(SizeAbove::new(min_size)))
+}
@@ -0,0 +1,52 @@
+use mapper_influences_backend_rs::database::DatabaseClient;
+
+/// Compares the stored mention counters with the actual mention counts and prints the
+/// differences. Run with `--fix` to also recompute the counters of the mismatched users
+#[tokio::main]
+async fn main() {
+    dotenvy::dotenv().ok();
+
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let fix = std::env::args().any(|argument| argument == "--fix");
+
+    let url = std::env::var("SURREAL_URL").expect("Missing SURREAL_URL environment variable");
+    let db = DatabaseClient::new(&url)
+        .await
+        .expect("failed to initialize db connection");
+
+    let discrepancies = db
+        .get_mention_discrepancies()
+        .await
+        .expect("failed to query mention counts");
+    if discrepancies.is_empty() {
+        tracing::info!("All mention counters are correct");
+        return;
+    }
+
+    for discrepancy in &discrepancies {
+        tracing::warn!(
+            "User {} has {:?} stored mentions but actually has {}",
+            discrepancy.id,
+            discrepancy.stored,
+            discrepancy.actual
+        );
+    }
+    tracing::info!("{} users have wrong mention counters", discrepancies.len());
+
+    if !fix {
+        tracing::info!("Run with --fix to correct them");
+        return;
+    }
+
+    let user_ids: Vec<u32> = discrepancies
+        .iter()
+        .map(|discrepancy| discrepancy.id)
+        .collect();
+    db.reconcile_mentions(&user_ids)
+        .await
+        .expect("failed to reconcile mention counters");
+    tracing::info!("Fixed mention counters of {} users", user_ids.len());
+}
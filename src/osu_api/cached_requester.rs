@@ -37,6 +37,15 @@ impl<T: DeserializeOwned + GetID + Clone + Send + 'static> CachedRequester<T> {
         self: Arc<Self>,
         ids: &[u32],
         access_token: &str,
+    ) -> Result<HashMap<u32, T>, AppError> {
+        self.fetch_multiple(ids, access_token, false).await
+    }
+
+    async fn fetch_multiple(
+        &self,
+        ids: &[u32],
+        access_token: &str,
+        allow_partial: bool,
     ) -> Result<HashMap<u32, T>, AppError> {
         // try to get the results from cache
         let mut cache_result = {
@@ -44,11 +53,17 @@ impl<T: DeserializeOwned + GetID + Clone + Send + 'static> CachedRequester<T> {
             cache.get_multiple(ids)
         };
         // Request the missing items
-        let misses_requested = self
-            .client
-            .clone()
-            .request_multiple(&self.base_url, &cache_result.misses, access_token)
-            .await?;
+        let client = self.client.clone();
+        let misses_requested = if allow_partial {
+            client
+                .request_multiple_partial(&self.base_url, &cache_result.misses, access_token)
+                .await
+                .values
+        } else {
+            client
+                .request_multiple(&self.base_url, &cache_result.misses, access_token)
+                .await?
+        };
 
         let misses_requested: Vec<T> = serde_json::from_value(Value::Array(misses_requested))?;
 
@@ -97,11 +112,32 @@ impl CombinedRequester {
         &self,
         ids: &[u32],
         access_token: &str,
+    ) -> Result<HashMap<u32, BeatmapsetSmall>, AppError> {
+        self.combine_beatmaps_with_user(ids, access_token, false)
+            .await
+    }
+
+    /// Same as [`CombinedRequester::get_beatmaps_with_user`] but keeps the successfully requested
+    /// beatmaps when some of the osu! API requests fail. Failed beatmaps are missing from the map
+    /// and beatmaps with failed mappers have no user data
+    pub async fn get_beatmaps_with_user_partial(
+        &self,
+        ids: &[u32],
+        access_token: &str,
+    ) -> Result<HashMap<u32, BeatmapsetSmall>, AppError> {
+        self.combine_beatmaps_with_user(ids, access_token, true)
+            .await
+    }
+
+    async fn combine_beatmaps_with_user(
+        &self,
+        ids: &[u32],
+        access_token: &str,
+        allow_partial: bool,
     ) -> Result<HashMap<u32, BeatmapsetSmall>, AppError> {
         let beatmap_map = self
             .beatmap_requester
-            .clone()
-            .get_multiple_osu(ids, access_token)
+            .fetch_multiple(ids, access_token, allow_partial)
             .await?;
         let users_to_request: Vec<u32> = beatmap_map
             .values()
@@ -110,8 +146,7 @@ impl CombinedRequester {
             .collect();
         let user_map = self
             .user_requester
-            .clone()
-            .get_multiple_osu(&users_to_request, access_token)
+            .fetch_multiple(&users_to_request, access_token, allow_partial)
             .await?;
         let combined = beatmap_map
             .into_iter()
@@ -1,6 +1,6 @@
 use std::{
     ops::DerefMut,
-    sync::{Arc, RwLock},
+    sync::{Arc, LazyLock, RwLock},
     time::Duration,
 };
 
@@ -8,7 +8,17 @@ use tokio::{sync::oneshot, sync::Mutex as AsyncMutex, time::sleep};
 
 use crate::{error::AppError, retry::Retryable};
 
-use super::{request::Requester, UserOsu};
+use super::{request::Requester, UserOsu, CLIENT_CREDENTIALS_SCOPES};
+
+/// Tokens are refreshed this long before they expire
+const REFRESH_BUFFER_SECONDS: u32 = 120;
+
+/// Short lived tokens, like the ones that tests use, are refreshed halfway through instead of
+/// subtracting the whole buffer
+fn refresh_delay(expires_in: u32) -> Duration {
+    let buffer = REFRESH_BUFFER_SECONDS.min(expires_in / 2);
+    Duration::from_secs(u64::from(expires_in - buffer))
+}
 
 /// A wrapper to [`RequestClient`] to store and update credentials grant client auth method token
 ///
@@ -23,6 +33,9 @@ pub struct CredentialsGrantClient {
 
 impl CredentialsGrantClient {
     pub async fn new(client: Arc<dyn Requester>) -> Result<Arc<CredentialsGrantClient>, AppError> {
+        // Token is requested lazily in a background task. Invalid scopes should fail the startup
+        // instead of that task
+        LazyLock::force(&CLIENT_CREDENTIALS_SCOPES);
         let (start_sender, start_receiver) = oneshot::channel();
         let (end_sender, end_receiver) = oneshot::channel();
         let client = Arc::new(CredentialsGrantClient {
@@ -42,29 +55,30 @@ impl CredentialsGrantClient {
     }
 
     // I could refactor the retry and update functions but whatever.
+    // Only tokio's sleep is used for timing, so tests can pause and advance the tokio clock to
+    // check the refresh
     fn start_loop(
         self: Arc<Self>,
         start_receiver: oneshot::Receiver<()>,
         end_sender: oneshot::Sender<()>,
     ) {
-        let buffer_time = 120;
         let mut client_clone = self.client.clone();
 
         // we can't fail this task, best we can do is to retry. If this doesn't work,
         // then there is a good chance that the rest of the requests won't work either
         tokio::spawn(async move {
             let _ = start_receiver.await;
-            let token = client_clone
+            let mut token = client_clone
                 .retry_until_success(60, "Failed to get client credentials grant token")
                 .await;
-            let _ = self.update_token(token.access_token);
+            let _ = self.update_token(token.access_token.clone());
             let _ = end_sender.send(());
             loop {
-                sleep(Duration::from_secs(token.expires_in as u64 - buffer_time)).await;
-                let token = client_clone
+                sleep(refresh_delay(token.expires_in)).await;
+                token = client_clone
                     .retry_until_success(60, "Failed to get client credentials grant token")
                     .await;
-                let _ = self.update_token(token.access_token);
+                let _ = self.update_token(token.access_token.clone());
             }
         });
     }
@@ -3,6 +3,8 @@ use std::sync::LazyLock;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::error::AppError;
+
 pub mod cached_requester;
 pub mod credentials_grant;
 pub mod request;
@@ -51,6 +53,7 @@ impl AuthRequest {
             redirect_uri: &REDIRECT_URI,
             grant_type: "authorization_code",
             code: Some(code),
+            // Scopes of this grant are decided in the authorization page. See `authorize_url`
             scope: None,
         }
     }
@@ -62,11 +65,84 @@ impl AuthRequest {
             redirect_uri: &REDIRECT_URI,
             grant_type: "client_credentials",
             code: None,
-            scope: Some("public"),
+            scope: Some(CLIENT_CREDENTIALS_SCOPES.as_str()),
         }
     }
 }
 
+/// Scopes that osu! API accepts
+const KNOWN_SCOPES: [&str; 8] = [
+    "chat.read",
+    "chat.write",
+    "chat.write_manage",
+    "delegate",
+    "forum.write",
+    "friends.read",
+    "identify",
+    "public",
+];
+
+/// osu! only allows these for the client credentials grant
+const CLIENT_CREDENTIALS_ALLOWED_SCOPES: [&str; 2] = ["delegate", "public"];
+
+/// Reads space or comma separated scopes from `env_var` and returns them space separated, the
+/// way osu! expects them. Panics on scopes that are not in `allowed_scopes`
+fn scopes_from_env(env_var: &str, default_scopes: &str, allowed_scopes: &[&str]) -> String {
+    let scopes = std::env::var(env_var).unwrap_or_else(|_| default_scopes.to_string());
+    let scopes: Vec<&str> = scopes
+        .split([' ', ','])
+        .filter(|scope| !scope.is_empty())
+        .collect();
+    assert!(
+        !scopes.is_empty(),
+        "{} environment variable should have at least one scope",
+        env_var
+    );
+    for scope in &scopes {
+        assert!(
+            allowed_scopes.contains(scope),
+            "Scope {} in {} environment variable is not allowed. Allowed scopes are: {}",
+            scope,
+            env_var,
+            allowed_scopes.join(", ")
+        );
+    }
+    scopes.join(" ")
+}
+
+/// Scopes requested from users when they log in. Defaults to:
+/// - `public`: reading users and beatmaps on behalf of the user
+/// - `identify`: reading the user's own profile through `/me`
+static AUTHORIZATION_SCOPES: LazyLock<String> =
+    LazyLock::new(|| scopes_from_env("OSU_AUTHORIZATION_SCOPES", "public identify", &KNOWN_SCOPES));
+
+/// Scopes of the client credentials token that is used for requests without a user. Checked
+/// when [`credentials_grant::CredentialsGrantClient`] is created
+static CLIENT_CREDENTIALS_SCOPES: LazyLock<String> = LazyLock::new(|| {
+    scopes_from_env(
+        "OSU_CLIENT_CREDENTIALS_SCOPES",
+        "public",
+        &CLIENT_CREDENTIALS_ALLOWED_SCOPES,
+    )
+});
+
+/// osu! authorization page URL for the authorization code grant. `state` is passed back to the
+/// redirect URI as is
+pub fn authorize_url(state: &str) -> Result<String, AppError> {
+    let url = reqwest::Url::parse_with_params(
+        "https://osu.ppy.sh/oauth/authorize",
+        &[
+            ("client_id", CLIENT_ID.as_str()),
+            ("redirect_uri", REDIRECT_URI.as_str()),
+            ("response_type", "code"),
+            ("scope", AUTHORIZATION_SCOPES.as_str()),
+            ("state", state),
+        ],
+    )
+    .map_err(|error| AppError::BadUri(error.to_string()))?;
+    Ok(url.to_string())
+}
+
 pub trait GetID {
     fn get_id(&self) -> u32;
 }
@@ -134,12 +210,25 @@ pub struct OsuSearchUserResponse {
     pub user: OsuSearchUserData,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, JsonSchema, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+/// `GameMode` type. Modes that are not known yet are deserialized as `unknown` instead of failing
+/// the whole response
+pub enum GameMode {
+    Osu,
+    Taiko,
+    Fruits,
+    Mania,
+    #[serde(other)]
+    Unknown,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq)]
 /// `BeatmapOsu` type. Used in `BeatmapsetSmall` type
 pub struct BeatmapOsu {
     pub difficulty_rating: f32,
     pub id: u32,
-    pub mode: String,
+    pub mode: GameMode,
     pub version: String,
 }
 
@@ -149,9 +238,49 @@ pub struct BeatmapsetRelatedUser {
     pub avatar_url: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, Default)]
+#[serde(default)]
+/// `covers` object of osu! beatmapsets. Every variant is optional, so sets that are missing some
+/// of them still deserialize
 pub struct Cover {
     pub cover: String,
+    #[serde(rename = "cover@2x")]
+    pub cover_2x: Option<String>,
+    pub card: Option<String>,
+    #[serde(rename = "card@2x")]
+    pub card_2x: Option<String>,
+    pub list: Option<String>,
+    #[serde(rename = "list@2x")]
+    pub list_2x: Option<String>,
+    pub slimcover: Option<String>,
+    #[serde(rename = "slimcover@2x")]
+    pub slimcover_2x: Option<String>,
+}
+
+impl Cover {
+    fn variants(&self) -> CoverVariants {
+        CoverVariants {
+            card: self.card.clone(),
+            card_2x: self.card_2x.clone(),
+            list: self.list.clone(),
+            list_2x: self.list_2x.clone(),
+            slimcover: self.slimcover.clone(),
+            slimcover_2x: self.slimcover_2x.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema, PartialEq)]
+/// `CoverVariants` type. Smaller cover images than `cover`, so cards and lists don't need to load
+/// the full size one. `_2x` ones are for high DPI screens. Variants that osu! doesn't return are
+/// null
+pub struct CoverVariants {
+    pub card: Option<String>,
+    pub card_2x: Option<String>,
+    pub list: Option<String>,
+    pub list_2x: Option<String>,
+    pub slimcover: Option<String>,
+    pub slimcover_2x: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
@@ -181,7 +310,7 @@ pub struct OsuSearchMapResponse {
 pub struct OsuMultipleBeatmap {
     pub id: u32,
     pub difficulty_rating: f32,
-    pub mode: String,
+    pub mode: GameMode,
     pub beatmapset_id: u32,
     pub version: String,
     pub user_id: u32,
@@ -211,6 +340,7 @@ pub struct BeatmapsetSmall {
     pub title: String,
     pub artist: String,
     pub cover: String,
+    pub covers: CoverVariants,
     pub user_name: String,
     pub user_avatar_url: String,
     pub user_id: u32,
@@ -253,6 +383,7 @@ impl BeatmapsetSmall {
             user_avatar_url,
             title: osu_multiple.beatmapset.title,
             artist: osu_multiple.beatmapset.artist,
+            covers: osu_multiple.beatmapset.covers.variants(),
             cover: osu_multiple.beatmapset.covers.cover,
         }
     }
@@ -284,6 +415,7 @@ impl BeatmapsetSmall {
             beatmaps: api_set.beatmaps,
             title: api_set.title,
             artist: api_set.artist,
+            covers: api_set.covers.variants(),
             cover: api_set.covers.cover,
             user_id: api_set.user_id,
             user_name,
@@ -292,8 +424,8 @@ impl BeatmapsetSmall {
     }
 }
 
-/// Despite having two variants for beatmaps, the API will always return the full beatmap
-/// objects instead of integer id's.
+/// Despite having two variants for beatmaps, the API returns the full beatmap objects instead of
+/// integer id's. Beatmaps that couldn't be requested from osu! are returned as id's.
 #[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
 #[serde(untagged)]
 pub enum BeatmapEnum {
@@ -2,18 +2,30 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::future::try_join_all;
-use http::{header::AUTHORIZATION, HeaderMap};
+use futures::{stream, FutureExt, StreamExt, TryStreamExt};
+use http::{header::AUTHORIZATION, HeaderMap, StatusCode};
 use serde_json::Value;
 use tokio::sync::Semaphore;
 
-use crate::{error::AppError, retry::Retryable};
+use crate::{error::AppError, http_client::map_reqwest_error, retry::Retryable};
 
 use super::{
     AuthRequest, BeatmapOsu, BeatmapsetOsu, OsuAuthToken, OsuSearchMapResponse,
     OsuSearchUserResponse, UserOsu,
 };
 
+/// How many chunks of [`Requester::request_multiple`] are requested at the same time. Keeps large
+/// hydrations from taking every permit of the request semaphore at once
+const REQUEST_MULTIPLE_CONCURRENCY: usize = 4;
+
+/// Result of [`Requester::request_multiple_partial`]. Values of the chunks that were requested
+/// successfully and the ids of the chunks that failed
+#[derive(Default, Debug)]
+pub struct PartialResponse {
+    pub values: Vec<Value>,
+    pub failed_ids: Vec<u32>,
+}
+
 /// The reason that the requests retun bytes and then they get decoded, is that it's exaclty the
 /// same implementation in `res.json().await`. this allows us to deserialize bodies into any
 /// type we want in spesific implementation while keeping the return types non generic.
@@ -58,13 +70,25 @@ where
     ) -> Result<BeatmapsetOsu, AppError> {
         let beatmapset_url = format!("https://osu.ppy.sh/api/v2/beatmapsets/{}", beatmapset_id);
         let res_body_bytes = self.get_request(&beatmapset_url, access_token).await?;
-        Ok(serde_json::from_slice(&res_body_bytes)?)
+        serde_json::from_slice(&res_body_bytes).map_err(|error| {
+            if is_osu_error_response(&res_body_bytes) {
+                AppError::NonExistingMapset(beatmapset_id)
+            } else {
+                error.into()
+            }
+        })
     }
 
     async fn get_user_osu(&self, access_token: &str, user_id: u32) -> Result<UserOsu, AppError> {
         let user_url = format!("https://osu.ppy.sh/api/v2/users/{}", user_id);
         let res_body_bytes = self.get_request(&user_url, access_token).await?;
-        Ok(serde_json::from_slice(&res_body_bytes)?)
+        serde_json::from_slice(&res_body_bytes).map_err(|error| {
+            if is_osu_error_response(&res_body_bytes) {
+                AppError::OsuUserNotFound(user_id)
+            } else {
+                error.into()
+            }
+        })
     }
 
     async fn search_user_osu(
@@ -115,54 +139,128 @@ where
         keys: &[u32],
         access_token: &str,
     ) -> Result<Vec<Value>, AppError> {
-        let mut handlers = Vec::new();
-        for chunk_ids in keys.chunks(50) {
-            let url = format!(
-                "{}?{}",
-                base_url,
-                chunk_ids
-                    .iter()
-                    .map(|id| format!("ids[]={}", id))
-                    .collect::<Vec<_>>()
-                    .join("&")
-            );
-            let access_token_string = access_token.to_string();
-            let self_clone = Arc::clone(&self);
-
-            let handler = tokio::spawn(async move {
-                let response: Result<Vec<Value>, AppError> = self_clone
-                    .deserialize_without_outer(url, access_token_string)
-                    .await;
-                response
-            });
-            handlers.push(handler);
-        }
+        // Futures are lazy, collecting them doesn't start any requests. Building them inside the
+        // stream instead trips up the `Send` inference of `async_trait`
+        let requests: Vec<_> = keys
+            .chunks(50)
+            .map(|chunk_ids| {
+                self.deserialize_without_outer(
+                    multiple_ids_url(base_url, chunk_ids),
+                    access_token.to_string(),
+                )
+            })
+            .collect();
+
+        let chunks: Vec<Vec<Value>> = stream::iter(requests)
+            .buffer_unordered(REQUEST_MULTIPLE_CONCURRENCY)
+            .try_collect()
+            .await?;
+        Ok(chunks.into_iter().flatten().collect())
+    }
 
-        try_join_all(handlers)
-            .await?
-            .into_iter()
-            .try_fold(vec![], |mut acc, result| {
-                acc.extend(result?);
-                Ok(acc)
+    /// Same as [`Requester::request_multiple`] but a failed chunk doesn't fail the whole request.
+    /// Failed chunks are logged and their ids are returned in [`PartialResponse::failed_ids`]
+    async fn request_multiple_partial(
+        self: Arc<Self>,
+        base_url: &str,
+        keys: &[u32],
+        access_token: &str,
+    ) -> PartialResponse {
+        let requests: Vec<_> = keys
+            .chunks(50)
+            .map(|chunk_ids| {
+                self.deserialize_without_outer(
+                    multiple_ids_url(base_url, chunk_ids),
+                    access_token.to_string(),
+                )
+                .map(move |result| (chunk_ids, result))
             })
+            .collect();
+
+        let chunks: Vec<_> = stream::iter(requests)
+            .buffer_unordered(REQUEST_MULTIPLE_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut response = PartialResponse::default();
+        for (chunk_ids, result) in chunks {
+            match result {
+                Ok(values) => response.values.extend(values),
+                Err(error) => {
+                    tracing::warn!(
+                        "Failed to request {} for ids {:?}: {}",
+                        base_url,
+                        chunk_ids,
+                        error
+                    );
+                    response.failed_ids.extend_from_slice(chunk_ids);
+                }
+            }
+        }
+        response
     }
 }
 
+fn multiple_ids_url(base_url: &str, ids: &[u32]) -> String {
+    format!(
+        "{}?{}",
+        base_url,
+        ids.iter()
+            .map(|id| format!("ids[]={}", id))
+            .collect::<Vec<_>>()
+            .join("&")
+    )
+}
+
+/// osu! API answers missing resources with a 404 and a body like `{"error": null}` instead of the
+/// requested object. Used to tell those apart from actual deserialization failures. Other error
+/// statuses don't reach here, see [`OsuApiRequestClient`]
+fn is_osu_error_response(body: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|value| value.as_object().map(|object| object.contains_key("error")))
+        .unwrap_or(false)
+}
+
 pub struct OsuApiRequestClient {
     client: reqwest::Client,
     semaphore: Semaphore,
 }
 impl OsuApiRequestClient {
-    pub fn new(concurrent_requests: usize) -> OsuApiRequestClient {
+    /// `client` is expected to be a clone of the shared client from
+    /// [`crate::http_client::build_http_client`] to reuse the connection pool
+    pub fn new(client: reqwest::Client, concurrent_requests: usize) -> OsuApiRequestClient {
         OsuApiRequestClient {
-            client: reqwest::Client::new(),
+            client,
             semaphore: Semaphore::new(concurrent_requests),
         }
     }
+
+    /// Uses `OSU_API_CONCURRENCY` environment variable as the concurrent request limit.
+    /// Falls back to `default_concurrent_requests` if the variable is not set
+    pub fn from_env(
+        client: reqwest::Client,
+        default_concurrent_requests: usize,
+    ) -> OsuApiRequestClient {
+        let concurrent_requests = match std::env::var("OSU_API_CONCURRENCY") {
+            Ok(value) => value
+                .parse::<usize>()
+                .expect("OSU_API_CONCURRENCY environment variable is not a valid number"),
+            Err(_) => default_concurrent_requests,
+        };
+        assert!(
+            concurrent_requests >= 1,
+            "OSU_API_CONCURRENCY environment variable should be at least 1"
+        );
+        OsuApiRequestClient::new(client, concurrent_requests)
+    }
 }
 
 #[async_trait]
 impl Requester for OsuApiRequestClient {
+    /// Bodies of 404 responses are returned like the successful ones, osu! API uses them for
+    /// missing resources. Other error statuses like 429 or 5xx are returned as
+    /// [`AppError::UpstreamStatus`], so they aren't mistaken for missing resources
     async fn get_request(&self, url: &str, access_token: &str) -> Result<Bytes, AppError> {
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -171,14 +269,30 @@ impl Requester for OsuApiRequestClient {
         );
 
         let _permit = self.semaphore.acquire().await?;
-        let res = self.client.get(url).headers(headers).send().await?;
-        Ok(res.bytes().await?)
+        let res = self
+            .client
+            .get(url)
+            .headers(headers)
+            .send()
+            .await
+            .map_err(map_reqwest_error)?;
+        let status = res.status();
+        if !status.is_success() && status != StatusCode::NOT_FOUND {
+            return Err(AppError::UpstreamStatus(status.as_u16()));
+        }
+        res.bytes().await.map_err(map_reqwest_error)
     }
 
     async fn post_request(&self, url: &str, body: AuthRequest) -> Result<Bytes, AppError> {
         let _permit = self.semaphore.acquire().await?;
-        let res = self.client.post(url).json(&body).send().await?;
-        Ok(res.bytes().await?)
+        let res = self
+            .client
+            .post(url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(map_reqwest_error)?;
+        res.bytes().await.map_err(map_reqwest_error)
     }
 }
 
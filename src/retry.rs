@@ -5,13 +5,40 @@ use std::{error::Error, time::Duration};
 pub trait Retryable<Value: Send + Sync, Err: Error + Send>: Send {
     async fn retry(&mut self) -> Result<Value, Err>;
     async fn retry_until_success(&mut self, longest_cooldown: u32, message: &str) -> Value {
+        loop {
+            if let Ok(value) = self
+                .retry_with_limit(longest_cooldown, u32::MAX, message)
+                .await
+            {
+                return value;
+            }
+        }
+    }
+
+    /// Same as [`Retryable::retry_until_success`] but gives up after `max_attempts` and returns
+    /// the last error
+    async fn retry_with_limit(
+        &mut self,
+        longest_cooldown: u32,
+        max_attempts: u32,
+        message: &str,
+    ) -> Result<Value, Err> {
         let mut cooldown_fibo_last = 0;
         let mut cooldown = 1;
         let mut attempt = 1;
         loop {
             match self.retry().await {
                 Ok(value) => {
-                    return value;
+                    return Ok(value);
+                }
+                Err(error) if attempt >= max_attempts => {
+                    tracing::error!(
+                        "{}. Giving up after {} attempts. full error: {}",
+                        message,
+                        attempt,
+                        error
+                    );
+                    return Err(error);
                 }
                 Err(error) => {
                     tracing::error!(
@@ -0,0 +1,101 @@
+/// URL schemes that can run code when a markdown link is rendered
+const UNSAFE_LINK_SCHEMES: [&str; 3] = ["javascript:", "vbscript:", "data:"];
+/// Every nesting level of tags takes another pass. Real text needs one or two
+const MAX_SANITIZE_PASSES: usize = 8;
+
+/// Sanitization policy for user written text like bios and influence descriptions. Text is stored
+/// sanitized, so every consumer gets the same safe text:
+/// - HTML tags and comments are removed, their inner text is kept
+/// - `<` that doesn't start a tag, like in `<3` or `a < b`, is kept as is
+/// - Unsafe schemes are removed from markdown link targets, see [`UNSAFE_LINK_SCHEMES`]
+///
+/// Removing a tag can join the text around it into a new tag, like in `<<b>script>`, so the
+/// passes are repeated until nothing changes, up to [`MAX_SANITIZE_PASSES`] times. Text that is
+/// still changing after that is nested on purpose, every `<` is removed from it instead
+///
+/// Nothing is escaped. Frontend is still expected to render the text as text or markdown
+pub fn sanitize_user_text(text: &str) -> String {
+    let mut sanitized = text.to_string();
+    for _ in 0..MAX_SANITIZE_PASSES {
+        let next = remove_unsafe_link_schemes(&strip_html_tags(&sanitized));
+        if next == sanitized {
+            return sanitized;
+        }
+        sanitized = next;
+    }
+    remove_unsafe_link_schemes(&sanitized.replace('<', ""))
+}
+
+/// Only the `<` followed by a letter, `/`, `!` or `?` start a tag. An unclosed tag drops the `<`
+/// only, so the rest of the text isn't lost
+pub fn strip_html_tags(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(tag_start) = rest.find('<') {
+        result.push_str(&rest[..tag_start]);
+        let after_bracket = &rest[tag_start + 1..];
+        let starts_tag = after_bracket
+            .chars()
+            .next()
+            .is_some_and(|next| next.is_ascii_alphabetic() || matches!(next, '/' | '!' | '?'));
+        if !starts_tag {
+            result.push('<');
+            rest = after_bracket;
+            continue;
+        }
+
+        let tag_end = if after_bracket.starts_with("!--") {
+            after_bracket.find("-->").map(|end| end + "-->".len())
+        } else {
+            after_bracket.find('>').map(|end| end + 1)
+        };
+        rest = match tag_end {
+            Some(tag_end) => &after_bracket[tag_end..],
+            None => after_bracket,
+        };
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Browsers ignore whitespace and control characters inside the scheme, `java\tscript:` is the
+/// same as `javascript:`. They are skipped while matching
+pub fn remove_unsafe_link_schemes(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(link_start) = rest.find("](") {
+        let target_start = link_start + "](".len();
+        result.push_str(&rest[..target_start]);
+        rest = &rest[target_start..];
+        while let Some(scheme_length) = unsafe_scheme_length(rest) {
+            rest = &rest[scheme_length..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Length of the unsafe scheme at the start of `target` in bytes, including the skipped
+/// characters
+fn unsafe_scheme_length(target: &str) -> Option<usize> {
+    let trimmed = target.trim_start_matches(|c: char| c.is_whitespace() || c == '<');
+    let leading_length = target.len() - trimmed.len();
+    for scheme in UNSAFE_LINK_SCHEMES {
+        let mut scheme_chars = scheme.chars().peekable();
+        for (index, c) in trimmed.char_indices() {
+            if c.is_whitespace() || c.is_control() {
+                continue;
+            }
+            if scheme_chars
+                .next_if(|expected| c.eq_ignore_ascii_case(expected))
+                .is_none()
+            {
+                break;
+            }
+            if scheme_chars.peek().is_none() {
+                return Some(leading_length + index + c.len_utf8());
+            }
+        }
+    }
+    None
+}
@@ -3,6 +3,7 @@ use std::{sync::Arc, time::Duration};
 use mapper_influences_backend_rs::{
     daily_update::update_once,
     database::DatabaseClient,
+    http_client::build_http_client,
     osu_api::{credentials_grant::CredentialsGrantClient, request::OsuApiRequestClient},
 };
 
@@ -21,10 +22,13 @@ async fn main() {
 
     let users = db.get_users_to_update().await.unwrap();
 
-    let request_client = Arc::new(OsuApiRequestClient::new(100));
+    let request_client = Arc::new(OsuApiRequestClient::from_env(
+        build_http_client().unwrap(),
+        100,
+    ));
     let credentials_grant_client = CredentialsGrantClient::new(request_client).await.unwrap();
 
-    let unsuccessfuls = update_once(
+    let summary = update_once(
         credentials_grant_client,
         db,
         users,
@@ -32,5 +36,5 @@ async fn main() {
     )
     .await;
 
-    dbg!(unsuccessfuls);
+    dbg!(summary);
 }
@@ -0,0 +1,197 @@
+use std::{collections::VecDeque, time::Duration};
+
+use common::{
+    admin_jwt, assert_activity, init_test_env_with_requester,
+    mock_requester::{mock_user, MockRequester},
+};
+use http::header::COOKIE;
+use mapper_influences_backend_rs::handlers::activity::{should_show_activity, Activity};
+use serde_json::{json, Value};
+
+mod common;
+
+fn user(id: u32) -> Value {
+    json!({
+        "id": id,
+        "username": format!("user{}", id),
+        "avatar_url": format!("https://a.ppy.sh/{}?", id),
+        "groups": [],
+        "country_code": "TR",
+        "country_name": "Turkey",
+        "ranked_maps": 1,
+        "ranked_mapper": true,
+        "mentions": 0,
+        "previous_usernames": [],
+    })
+}
+
+fn activity(user_id: u32, event: Value) -> Activity {
+    let mut activity = json!({
+        "id": format!("activity:{}", user_id),
+        "user": user(user_id),
+        "created_at": "2024-11-01T12:00:00Z",
+    });
+    activity
+        .as_object_mut()
+        .unwrap()
+        .extend(event.as_object().unwrap().clone());
+    serde_json::from_value(activity).expect("invalid test activity")
+}
+
+fn queue(activities: Vec<Activity>) -> VecDeque<Activity> {
+    activities.into_iter().collect()
+}
+
+#[test]
+fn test_spam_prevention_empty_queue() {
+    let queue = VecDeque::new();
+    let allowed = [
+        json!({"event_type": "EDIT_BIO", "bio": "bio"}),
+        json!({"event_type": "ADD_USER_BEATMAP", "beatmap": 1}),
+        json!({"event_type": "ADD_INFLUENCE", "influence": user(2)}),
+        json!({"event_type": "EDIT_INFLUENCE_DESC", "influence": user(2), "description": "d"}),
+        json!({"event_type": "EDIT_INFLUENCE_TYPE", "influence": user(2), "influence_type": 1}),
+        json!({"event_type": "ADD_INFLUENCE_BEATMAP", "influence": user(2), "beatmap": 1}),
+    ];
+    for event in allowed {
+        assert!(should_show_activity(&queue, &activity(1, event)));
+    }
+}
+
+#[test]
+fn test_spam_prevention_hidden_activity_types() {
+    let queue = VecDeque::new();
+    let hidden = [
+        json!({"event_type": "LOGIN"}),
+        json!({"event_type": "REMOVE_INFLUENCE", "influence": user(2)}),
+        json!({"event_type": "REMOVE_USER_BEATMAP", "beatmap": 1}),
+        json!({"event_type": "REMOVE_INFLUENCE_BEATMAP", "influence": user(2), "beatmap": 1}),
+    ];
+    for event in hidden {
+        assert!(!should_show_activity(&queue, &activity(1, event)));
+    }
+}
+
+#[test]
+fn test_spam_prevention_edit_bio() {
+    let queue = queue(vec![activity(
+        1,
+        json!({"event_type": "EDIT_BIO", "bio": "old"}),
+    )]);
+    let new_bio = json!({"event_type": "EDIT_BIO", "bio": "new"});
+    assert!(!should_show_activity(&queue, &activity(1, new_bio.clone())));
+    assert!(should_show_activity(&queue, &activity(2, new_bio)));
+}
+
+#[test]
+fn test_spam_prevention_add_user_beatmap() {
+    let queue = queue(vec![activity(
+        1,
+        json!({"event_type": "ADD_USER_BEATMAP", "beatmap": 1}),
+    )]);
+    let new_beatmap = json!({"event_type": "ADD_USER_BEATMAP", "beatmap": 2});
+    assert!(!should_show_activity(
+        &queue,
+        &activity(1, new_beatmap.clone())
+    ));
+    assert!(should_show_activity(&queue, &activity(2, new_beatmap)));
+}
+
+#[test]
+fn test_spam_prevention_influence_edits() {
+    let queue = queue(vec![activity(
+        1,
+        json!({"event_type": "ADD_INFLUENCE", "influence": user(2)}),
+    )]);
+
+    let same_influence = [
+        json!({"event_type": "ADD_INFLUENCE", "influence": user(2)}),
+        json!({"event_type": "EDIT_INFLUENCE_DESC", "influence": user(2), "description": "d"}),
+        json!({"event_type": "EDIT_INFLUENCE_TYPE", "influence": user(2), "influence_type": 1}),
+    ];
+    for event in same_influence {
+        assert!(!should_show_activity(&queue, &activity(1, event.clone())));
+        assert!(should_show_activity(&queue, &activity(3, event)));
+    }
+
+    let other_influence =
+        json!({"event_type": "EDIT_INFLUENCE_DESC", "influence": user(4), "description": "d"});
+    assert!(should_show_activity(&queue, &activity(1, other_influence)));
+}
+
+#[test]
+fn test_spam_prevention_add_influence_beatmap() {
+    let queue = queue(vec![activity(
+        1,
+        json!({"event_type": "ADD_INFLUENCE_BEATMAP", "influence": user(2), "beatmap": 1}),
+    )]);
+
+    // Only the same beatmap of the same influence is hidden
+    let same_beatmap =
+        json!({"event_type": "ADD_INFLUENCE_BEATMAP", "influence": user(2), "beatmap": 1});
+    assert!(!should_show_activity(&queue, &activity(1, same_beatmap)));
+    let other_beatmap =
+        json!({"event_type": "ADD_INFLUENCE_BEATMAP", "influence": user(2), "beatmap": 2});
+    assert!(should_show_activity(&queue, &activity(1, other_beatmap)));
+
+    let other_influence =
+        json!({"event_type": "ADD_INFLUENCE_BEATMAP", "influence": user(3), "beatmap": 1});
+    assert!(should_show_activity(
+        &queue,
+        &activity(1, other_influence.clone())
+    ));
+    assert!(should_show_activity(&queue, &activity(4, other_influence)));
+
+    // Adding the influence itself doesn't throttle beatmap additions
+    let queue = queue_with_influence_added();
+    let event = json!({"event_type": "ADD_INFLUENCE_BEATMAP", "influence": user(2), "beatmap": 1});
+    assert!(should_show_activity(&queue, &activity(1, event)));
+}
+
+#[test]
+fn test_spam_prevention_influence_beatmap_limit() {
+    let queue = queue(
+        (1..=3)
+            .map(|beatmap| {
+                activity(
+                    1,
+                    json!({"event_type": "ADD_INFLUENCE_BEATMAP", "influence": user(2), "beatmap": beatmap}),
+                )
+            })
+            .collect(),
+    );
+
+    // The limit is per user and counts every influence
+    for influence in [2, 3] {
+        let event = json!({"event_type": "ADD_INFLUENCE_BEATMAP", "influence": user(influence), "beatmap": 4});
+        assert!(!should_show_activity(&queue, &activity(1, event.clone())));
+        assert!(should_show_activity(&queue, &activity(4, event)));
+    }
+}
+
+fn queue_with_influence_added() -> VecDeque<Activity> {
+    queue(vec![activity(
+        1,
+        json!({"event_type": "ADD_INFLUENCE", "influence": user(2)}),
+    )])
+}
+
+#[tokio::test]
+async fn test_add_influence_activity() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    let activity = assert_activity(&test_server, 2, "ADD_INFLUENCE", Duration::from_secs(5)).await;
+    assert_eq!(activity["influence"]["id"], 3);
+    assert_eq!(activity["influence"]["username"], "mapper");
+}
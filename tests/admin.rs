@@ -0,0 +1,361 @@
+use std::time::Duration;
+
+use axum::{extract::State, response::IntoResponse};
+use common::{
+    admin_jwt, init_test_env_with_requester, init_test_state,
+    mock_requester::{mock_user, MockRequester},
+    test_db_client,
+};
+use futures::StreamExt;
+use http::{header::COOKIE, StatusCode};
+use mapper_influences_backend_rs::{
+    database::influence::Influence,
+    handlers::{activity::sse_handler, admin::Broadcasts, auth::AdminLogin},
+};
+use serde_json::{json, Value};
+
+mod common;
+
+#[tokio::test]
+async fn test_migration_status() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let status: Value = test_server
+        .get("/admin/migrations")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert_eq!(status["pending"], Value::Array(Vec::new()));
+}
+
+#[tokio::test]
+async fn test_cache_warm() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    let warmed: Value = test_server
+        .post("/admin/cache/warm")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert_eq!(warmed["user_leaderboard"], 1);
+    assert_eq!(warmed["graph_links"], 1);
+
+    let leaderboard: Vec<Value> = test_server.get("/leaderboard/user").await.json();
+    assert_eq!(leaderboard.len(), 1);
+}
+
+#[tokio::test]
+async fn test_leaderboard_recompute() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_user(mock_user(4, "another mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+    let leaderboard: Vec<Value> = test_server.get("/leaderboard/user").await.json();
+    assert_eq!(leaderboard.len(), 1);
+
+    // cached leaderboard doesn't have this one until it's recomputed
+    test_server
+        .post("/influence/4")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "4" }))
+        .await
+        .assert_status_ok();
+    let recomputed: Value = test_server
+        .post("/admin/leaderboard/recompute")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert_eq!(recomputed["user_leaderboard"]["total"], 2);
+    assert_eq!(recomputed["user_leaderboard"]["cached_entries"], 2);
+    assert!(recomputed["user_leaderboard"]["duration_ms"].is_u64());
+
+    let leaderboard: Vec<Value> = test_server.get("/leaderboard/user").await.json();
+    assert_eq!(leaderboard.len(), 2);
+}
+
+#[tokio::test]
+async fn test_broadcast_stats() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let broadcasts: Broadcasts = test_server
+        .get("/admin/broadcasts")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert_eq!(broadcasts.activity.connections, 0);
+    assert_eq!(broadcasts.activity.dropped_messages, 0);
+    assert!(broadcasts.activity.lagged_connections.is_empty());
+    assert_eq!(broadcasts.user_leaderboard.lag_events, 0);
+}
+
+#[tokio::test]
+async fn test_broadcast_lag_stats() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (state, testcontainer_handle) = init_test_state(requester).await;
+
+    // The stream is only read after the activities are broadcasted, so the connection falls
+    // behind the 50 message channel
+    let sse = sse_handler(State(state.clone())).await.unwrap();
+    let user_ids: Vec<u32> = (3..63).collect();
+    let db = test_db_client(&testcontainer_handle).await;
+    db.get_inner_ref()
+        .query(
+            "
+            FOR $id IN $user_ids {
+                LET $user = type::thing('user', $id);
+                CREATE $user CONTENT (SELECT * OMIT id, created_at, updated_at FROM ONLY user:2);
+                CREATE activity
+                SET user = $user, created_at = time::now(), event_type = 'EDIT_BIO', bio = 'hi';
+            };
+            ",
+        )
+        .bind(("user_ids", user_ids))
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    // The last activity is queued right before it's broadcasted
+    let last_user_queued = || {
+        let queue = serde_json::to_value(state.activity_tracker.get_current_queue().unwrap());
+        let queue: Vec<Value> = serde_json::from_value(queue.unwrap()).unwrap();
+        queue.iter().any(|activity| activity["user"]["id"] == 62)
+    };
+    while !last_user_queued() {
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "activities weren't broadcasted"
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let mut body = sse.into_response().into_body().into_data_stream();
+    while let Some(chunk) = body.next().await {
+        if String::from_utf8_lossy(&chunk.unwrap()).contains("event: activity") {
+            break;
+        }
+    }
+
+    let stats = state.activity_tracker.broadcast_stats();
+    assert_eq!(stats.connections, 1);
+    assert_eq!(stats.lag_events, 1);
+    assert_eq!(stats.dropped_messages, 10);
+    assert_eq!(stats.lagged_connections.len(), 1);
+    assert_eq!(stats.lagged_connections[0].lag_events, 1);
+    assert_eq!(stats.lagged_connections[0].dropped_messages, 10);
+    // SSE connections have no address
+    assert!(stats.lagged_connections[0].address.is_none());
+}
+
+#[tokio::test]
+async fn test_audit_log() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    test_server
+        .post("/oauth/admin")
+        .json(&AdminLogin::new("wrong password".to_string(), 3))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+    test_server
+        .delete("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .assert_status_ok();
+
+    let response = test_server
+        .get("/admin/audit")
+        .add_query_param("actor", 2)
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await;
+    assert_eq!(response.header("x-total-count"), "3");
+    let logs: Vec<Value> = response.json();
+    let actions: Vec<&str> = logs
+        .iter()
+        .map(|log| log["action"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        actions,
+        vec!["REMOVE_INFLUENCE", "ADD_INFLUENCE", "ADMIN_LOGIN"]
+    );
+    assert_eq!(logs[0]["target"], 3);
+
+    // Callers of rejected logins aren't authenticated, they aren't recorded as the actor
+    let rejected: Vec<Value> = test_server
+        .get("/admin/audit")
+        .add_query_param("actor", 3)
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert!(rejected.is_empty());
+    let logs: Vec<Value> = test_server
+        .get("/admin/audit")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    let rejected: Vec<&Value> = logs
+        .iter()
+        .filter(|log| log["action"] == "REJECTED_ADMIN_LOGIN")
+        .collect();
+    assert_eq!(rejected.len(), 1);
+    assert!(rejected[0]["actor"].is_null());
+    assert_eq!(rejected[0]["details"], "3");
+}
+
+#[tokio::test]
+async fn test_rejected_admin_login_limit() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    for _ in 0..10 {
+        test_server
+            .post("/oauth/admin")
+            .json(&AdminLogin::new("wrong password".to_string(), 2))
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+    test_server
+        .post("/oauth/admin")
+        .json(&AdminLogin::new("wrong password".to_string(), 2))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    // Right password isn't limited
+    let jwt = admin_jwt(&test_server, 2).await;
+    let logs: Vec<Value> = test_server
+        .get("/admin/audit")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    let rejected_count = logs
+        .iter()
+        .filter(|log| log["action"] == "REJECTED_ADMIN_LOGIN")
+        .count();
+    assert_eq!(rejected_count, 10);
+}
+
+#[tokio::test]
+async fn test_merge_users() {
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let mut cookies = Vec::new();
+    for user_id in 2..=5 {
+        let jwt = admin_jwt(&test_server, user_id).await;
+        cookies.push((user_id, format!("user_token={}", jwt)));
+    }
+    let cookie = |user_id: u32| cookies[user_id as usize - 2].1.clone();
+
+    // 3 is merged into 4. 2 has both of them, 5 only has 3
+    for (user_id, influenced_to) in [(2, 3), (2, 4), (5, 3), (3, 5)] {
+        test_server
+            .post(&format!("/influence/{}", influenced_to))
+            .add_header(COOKIE, cookie(user_id))
+            .json(&json!({ "user_id": influenced_to.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    test_server
+        .post("/admin/users/merge")
+        .add_header(COOKIE, cookie(2))
+        .json(&json!({ "source": 3, "target": 3 }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    test_server
+        .post("/admin/users/merge")
+        .add_header(COOKIE, cookie(2))
+        .json(&json!({ "source": 3, "target": 4 }))
+        .await
+        .assert_status_ok();
+
+    let ids = |influences: Vec<Influence>| -> Vec<u32> {
+        let mut ids: Vec<u32> = influences
+            .iter()
+            .map(|influence| influence.user.id)
+            .collect();
+        ids.sort();
+        ids
+    };
+    let mentions: Vec<Influence> = test_server
+        .get("/influence/mentions/4")
+        .add_header(COOKIE, cookie(2))
+        .await
+        .json();
+    assert_eq!(ids(mentions), vec![2, 5]);
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/4")
+        .add_header(COOKIE, cookie(2))
+        .await
+        .json();
+    assert_eq!(ids(influences), vec![5]);
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, cookie(2))
+        .await
+        .json();
+    assert_eq!(ids(influences), vec![4]);
+
+    // Source is gone
+    test_server
+        .post("/admin/users/merge")
+        .add_header(COOKIE, cookie(2))
+        .json(&json!({ "source": 3, "target": 4 }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    // Activities of the source would fail to read without the user
+    let response = test_server.get("/activity").await;
+    response.assert_status_ok();
+    let activities: Vec<Value> = response.json();
+    assert!(activities
+        .iter()
+        .all(|activity| activity["user"]["id"] != 3 && activity["influence"]["id"] != 3));
+    let db = test_db_client(&testcontainer_handle).await;
+    db.get_activities(100, 0)
+        .await
+        .expect("activities should be readable after merge");
+}
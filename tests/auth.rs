@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use common::{
+    admin_jwt, init_test_env_with_requester,
+    mock_requester::{mock_user, MockRequester},
+};
+use http::{
+    header::{COOKIE, LOCATION},
+    StatusCode,
+};
+use mapper_influences_backend_rs::{error::AppError, handlers::auth::cookie_header_value};
+
+mod common;
+
+#[test]
+fn test_cookie_header_value() {
+    let cookie = "user_token=abc.def-ghi_jkl;HttpOnly;Max-Age=86400;Path=/;SameSite=lax";
+    assert_eq!(cookie_header_value(cookie).unwrap(), cookie);
+}
+
+#[test]
+fn test_cookie_header_value_invalid_character() {
+    let crafted_cookie = "user_token=abc\r\nSet-Cookie: admin=true;Path=/";
+    assert!(matches!(
+        cookie_header_value(crafted_cookie),
+        Err(AppError::HeaderEncode(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_oauth_login_redirect_not_allowed() {
+    let (test_server, _testcontainer_handle) =
+        init_test_env_with_requester(MockRequester::new()).await;
+
+    test_server
+        .get("/oauth/login")
+        .add_query_param("redirect", "https://evil.example.com")
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_oauth_state_mismatch() {
+    let (test_server, _testcontainer_handle) =
+        init_test_env_with_requester(MockRequester::new()).await;
+
+    let login_response = test_server.get("/oauth/login").await;
+    let location = login_response.header(LOCATION);
+    let authorize_url = reqwest::Url::parse(location.to_str().unwrap()).unwrap();
+    assert!(authorize_url
+        .as_str()
+        .starts_with("https://osu.ppy.sh/oauth/authorize"));
+    let (_, oauth_state) = authorize_url
+        .query_pairs()
+        .find(|(key, _)| key == "state")
+        .expect("missing state in authorize url");
+    assert!(!login_response.cookie("oauth_state").value().is_empty());
+
+    // no state cookie
+    test_server
+        .get("/oauth/osu-redirect")
+        .add_query_param("code", "code")
+        .add_query_param("state", &oauth_state)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // state cookie from another login
+    test_server
+        .get("/oauth/osu-redirect")
+        .add_query_param("code", "code")
+        .add_query_param("state", &oauth_state)
+        .add_header(COOKIE, "oauth_state=another_nonce")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_oauth_login_authorize_url() {
+    let (test_server, _testcontainer_handle) =
+        init_test_env_with_requester(MockRequester::new()).await;
+
+    let login_response = test_server.get("/oauth/login").await;
+    login_response.assert_status(StatusCode::FOUND);
+    let location = login_response.header(LOCATION);
+    let authorize_url = reqwest::Url::parse(location.to_str().unwrap()).unwrap();
+    let query = |name: &str| {
+        authorize_url
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    assert_eq!(query("client_id"), std::env::var("CLIENT_ID").ok());
+    assert_eq!(query("redirect_uri"), std::env::var("REDIRECT_URI").ok());
+    assert_eq!(query("response_type").as_deref(), Some("code"));
+    assert_eq!(query("scope").as_deref(), Some("public identify"));
+}
+
+#[tokio::test]
+async fn test_logout_revokes_token() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let cookie = format!("user_token={}", admin_jwt(&test_server, 2).await);
+    let other_cookie = format!("user_token={}", admin_jwt(&test_server, 2).await);
+
+    test_server
+        .get("/oauth/logout")
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status_ok();
+    test_server
+        .get("/users/me")
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    // Only the token that is logged out is revoked
+    test_server
+        .get("/users/me")
+        .add_header(COOKIE, &other_cookie)
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_logout_all_revokes_tokens() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let cookie = format!("user_token={}", admin_jwt(&test_server, 2).await);
+    let other_cookie = format!("user_token={}", admin_jwt(&test_server, 2).await);
+
+    test_server
+        .post("/oauth/logout-all")
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status_ok();
+    for old_cookie in [&cookie, &other_cookie] {
+        test_server
+            .get("/users/me")
+            .add_header(COOKIE, old_cookie)
+            .await
+            .assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    // Tokens issued in the same millisecond as the logout-all are revoked too
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let new_cookie = format!("user_token={}", admin_jwt(&test_server, 2).await);
+    test_server
+        .get("/users/me")
+        .add_header(COOKIE, &new_cookie)
+        .await
+        .assert_status_ok();
+}
@@ -0,0 +1,35 @@
+use std::net::IpAddr;
+
+use http::HeaderMap;
+use mapper_influences_backend_rs::handlers::client_ip::client_ip_from_headers;
+
+fn ip(address: &str) -> Option<IpAddr> {
+    Some(address.parse().unwrap())
+}
+
+#[test]
+fn test_client_ip_from_forwarded_for() {
+    let mut headers = HeaderMap::new();
+    // first entry is sent by the client, the proxy appends the real address
+    headers.append("x-forwarded-for", "10.0.0.1, 1.1.1.1".parse().unwrap());
+    headers.append("x-forwarded-for", "2.2.2.2".parse().unwrap());
+    headers.insert("x-real-ip", "3.3.3.3".parse().unwrap());
+
+    assert_eq!(client_ip_from_headers(&headers, 1), ip("2.2.2.2"));
+    assert_eq!(client_ip_from_headers(&headers, 2), ip("1.1.1.1"));
+    // more proxies than entries, the connection address is used instead of `x-real-ip`
+    assert_eq!(client_ip_from_headers(&headers, 4), None);
+}
+
+#[test]
+fn test_client_ip_fallbacks() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(client_ip_from_headers(&headers, 1), None);
+
+    headers.insert("x-real-ip", " 2001:db8::1 ".parse().unwrap());
+    assert_eq!(client_ip_from_headers(&headers, 1), ip("2001:db8::1"));
+
+    // `x-real-ip` is only used without `x-forwarded-for`
+    headers.insert("x-forwarded-for", "not an ip".parse().unwrap());
+    assert_eq!(client_ip_from_headers(&headers, 1), None);
+}
@@ -0,0 +1,258 @@
+// Not every test binary uses the mock requester
+#![allow(dead_code)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use axum::async_trait;
+use bytes::Bytes;
+use itertools::Itertools;
+use mapper_influences_backend_rs::{
+    error::AppError,
+    osu_api::{request::Requester, AuthRequest, OsuAuthToken},
+};
+use serde_json::{json, Value};
+
+/// In memory [`Requester`] that returns canned responses. Unlike [`super::osu_test_client`], it
+/// doesn't need a recorded cache file. Ids that are not added are treated as missing on osu!
+#[derive(Default)]
+pub struct MockRequester {
+    users: RwLock<HashMap<u32, Value>>,
+    beatmaps: RwLock<HashMap<u32, Value>>,
+    failing_ids: RwLock<HashSet<u32>>,
+    /// Error statuses of single user requests
+    user_statuses: RwLock<HashMap<u32, u16>>,
+    search_queries: RwLock<Vec<String>>,
+    user_search_fails: AtomicBool,
+    token_requests: AtomicU32,
+    /// `expires_in` of the client credentials tokens. Default is the same as [`OsuAuthToken::test`]
+    token_expires_in: RwLock<Option<u32>>,
+}
+
+/// Minimal user object that satisfies both `UserOsu` and `OsuMultipleUser`
+pub fn mock_user(id: u32, username: &str) -> Value {
+    json!({
+        "id": id,
+        "username": username,
+        "avatar_url": format!("https://a.ppy.sh/{}?", id),
+        "country": { "code": "TR", "name": "Turkey" },
+        "groups": [],
+        "previous_usernames": [],
+        "ranked_and_approved_beatmapset_count": 1,
+        "ranked_beatmapset_count": 1,
+        "nominated_beatmapset_count": 0,
+        "guest_beatmapset_count": 0,
+        "loved_beatmapset_count": 0,
+        "graveyard_beatmapset_count": 0,
+        "pending_beatmapset_count": 0,
+    })
+}
+
+/// Minimal beatmap object that satisfies `OsuMultipleBeatmap`
+pub fn mock_beatmap(id: u32, beatmapset_id: u32, user_id: u32) -> Value {
+    json!({
+        "id": id,
+        "difficulty_rating": 5.5,
+        "mode": "osu",
+        "beatmapset_id": beatmapset_id,
+        "version": format!("Difficulty {}", id),
+        "user_id": user_id,
+        "beatmapset": {
+            "title": format!("Title {}", beatmapset_id),
+            "artist": "Artist",
+            "covers": {
+                "cover": format!("https://assets.ppy.sh/beatmaps/{}/covers/cover.jpg", beatmapset_id),
+                "card": format!("https://assets.ppy.sh/beatmaps/{}/covers/card.jpg", beatmapset_id),
+                "card@2x": format!("https://assets.ppy.sh/beatmaps/{}/covers/card@2x.jpg", beatmapset_id),
+                "list": format!("https://assets.ppy.sh/beatmaps/{}/covers/list.jpg", beatmapset_id),
+            },
+            "user_id": user_id,
+            "creator": "creator",
+        },
+    })
+}
+
+fn ids_from_query(url: &str) -> Vec<u32> {
+    let Some((_, query)) = url.split_once('?') else {
+        return Vec::new();
+    };
+    query
+        .split('&')
+        .filter_map(|parameter| parameter.strip_prefix("ids[]="))
+        .filter_map(|id| id.parse().ok())
+        .collect()
+}
+
+fn select_values(store: &RwLock<HashMap<u32, Value>>, ids: &[u32]) -> Result<Vec<Value>, AppError> {
+    let store = store.read().map_err(|_| AppError::RwLock)?;
+    Ok(ids.iter().filter_map(|id| store.get(id).cloned()).collect())
+}
+
+impl MockRequester {
+    pub fn new() -> Arc<Self> {
+        Arc::new(MockRequester::default())
+    }
+
+    /// `user` should have an `id` field. [`mock_user`] can be used to create one
+    pub fn add_user(&self, user: Value) {
+        let id = user["id"].as_u64().expect("user should have an id") as u32;
+        self.users.write().unwrap().insert(id, user);
+    }
+
+    /// Makes the user look deleted on osu!
+    pub fn remove_user(&self, user_id: u32) {
+        self.users.write().unwrap().remove(&user_id);
+    }
+
+    /// `beatmap` should have an `id` field. [`mock_beatmap`] can be used to create one
+    pub fn add_beatmap(&self, beatmap: Value) {
+        let id = beatmap["id"].as_u64().expect("beatmap should have an id") as u32;
+        self.beatmaps.write().unwrap().insert(id, beatmap);
+    }
+
+    /// Multiple id requests that contain `id` fail as if osu! API timed out
+    pub fn fail_requests_with(&self, id: u32) {
+        self.failing_ids.write().unwrap().insert(id);
+    }
+
+    /// Single user requests of `id` fail like `OsuApiRequestClient` does for error statuses other
+    /// than 404
+    pub fn fail_user_with_status(&self, id: u32, status: u16) {
+        self.user_statuses.write().unwrap().insert(id, status);
+    }
+
+    /// User searches fail as if osu! API timed out
+    pub fn fail_user_search(&self) {
+        self.user_search_fails.store(true, Ordering::Relaxed);
+    }
+
+    /// Query strings of the beatmap searches that were forwarded to osu!, in order
+    pub fn search_queries(&self) -> Vec<String> {
+        self.search_queries.read().unwrap().clone()
+    }
+
+    /// Client credentials tokens that are returned after this expire in `expires_in` seconds
+    pub fn set_token_expires_in(&self, expires_in: u32) {
+        *self.token_expires_in.write().unwrap() = Some(expires_in);
+    }
+
+    /// Client credentials tokens are numbered in request order, starting with `mock-token-1`
+    pub fn token_request_count(&self) -> u32 {
+        self.token_requests.load(Ordering::Relaxed)
+    }
+
+    fn check_failing(&self, ids: &[u32]) -> Result<(), AppError> {
+        let failing_ids = self.failing_ids.read().map_err(|_| AppError::RwLock)?;
+        if ids.iter().any(|id| failing_ids.contains(id)) {
+            return Err(AppError::UpstreamTimeout);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Requester for MockRequester {
+    async fn get_request(&self, url: &str, _token: &str) -> Result<Bytes, AppError> {
+        let path = url
+            .strip_prefix("https://osu.ppy.sh/api/v2/")
+            .unwrap_or_else(|| panic!("Unexpected url in mock requester: {}", url));
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+        self.check_failing(&ids_from_query(url))?;
+        let response = match path.split('/').collect::<Vec<_>>().as_slice() {
+            ["users"] => json!({ "users": select_values(&self.users, &ids_from_query(url))? }),
+            ["beatmaps"] => {
+                json!({ "beatmaps": select_values(&self.beatmaps, &ids_from_query(url))? })
+            }
+            ["users", id] | ["users", id, _] => {
+                let id: u32 = id.parse()?;
+                let user_statuses = self.user_statuses.read().map_err(|_| AppError::RwLock)?;
+                if let Some(status) = user_statuses.get(&id) {
+                    return Err(AppError::UpstreamStatus(*status));
+                }
+                select_values(&self.users, &[id])?
+                    .pop()
+                    // this is what osu! returns for missing users
+                    .unwrap_or_else(|| json!({ "error": null }))
+            }
+            ["search", ""] => {
+                if self.user_search_fails.load(Ordering::Relaxed) {
+                    return Err(AppError::UpstreamTimeout);
+                }
+                let search_text = query
+                    .split('&')
+                    .find_map(|parameter| parameter.strip_prefix("query="))
+                    .unwrap_or_default();
+                let users = self.users.read().map_err(|_| AppError::RwLock)?;
+                let matching_ids: Vec<Value> = users
+                    .values()
+                    .filter(|user| {
+                        user["username"]
+                            .as_str()
+                            .is_some_and(|username| username.contains(search_text))
+                    })
+                    .map(|user| json!({ "id": user["id"] }))
+                    .sorted_by_key(|user| user["id"].as_u64())
+                    .collect();
+                json!({ "user": { "data": matching_ids } })
+            }
+            ["beatmapsets", id] if *id != "search" => {
+                let id: u32 = id.parse()?;
+                let beatmaps = self.beatmaps.read().map_err(|_| AppError::RwLock)?;
+                let set_beatmaps: Vec<&Value> = beatmaps
+                    .values()
+                    .filter(|beatmap| beatmap["beatmapset_id"] == id)
+                    .sorted_by_key(|beatmap| beatmap["id"].as_u64())
+                    .collect();
+                match set_beatmaps.first() {
+                    Some(first) => {
+                        let mut beatmapset = first["beatmapset"].clone();
+                        beatmapset["id"] = json!(id);
+                        beatmapset["related_users"] = json!([]);
+                        beatmapset["beatmaps"] = set_beatmaps
+                            .iter()
+                            .map(|beatmap| {
+                                json!({
+                                    "id": beatmap["id"],
+                                    "difficulty_rating": beatmap["difficulty_rating"],
+                                    "mode": beatmap["mode"],
+                                    "version": beatmap["version"],
+                                })
+                            })
+                            .collect();
+                        beatmapset
+                    }
+                    // this is what osu! returns for missing beatmapsets
+                    None => json!({ "error": null }),
+                }
+            }
+            ["beatmapsets", "search"] => {
+                let mut search_queries =
+                    self.search_queries.write().map_err(|_| AppError::RwLock)?;
+                search_queries.push(query.to_string());
+                json!({ "beatmapsets": [] })
+            }
+            _ => panic!("Unexpected url in mock requester: {}", url),
+        };
+        Ok(Bytes::from(serde_json::to_vec(&response)?))
+    }
+
+    async fn post_request(&self, _url: &str, _body: AuthRequest) -> Result<Bytes, AppError> {
+        unreachable!()
+    }
+
+    async fn get_client_credentials_token(&self) -> Result<OsuAuthToken, AppError> {
+        let request_number = self.token_requests.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut token = OsuAuthToken::test();
+        token.access_token = format!("mock-token-{}", request_number);
+        if let Some(expires_in) = *self.token_expires_in.read().map_err(|_| AppError::RwLock)? {
+            token.expires_in = expires_in;
+        }
+        Ok(token)
+    }
+}
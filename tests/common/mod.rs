@@ -1,6 +1,10 @@
-use std::sync::Arc;
+// Not every test binary uses every helper
+#![allow(dead_code)]
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
+    extract::connect_info::MockConnectInfo,
     middleware,
     routing::{any, delete, get, patch, post},
     Router,
@@ -8,28 +12,62 @@ use axum::{
 use axum_test::TestServer;
 use mapper_influences_backend_rs::{
     database::DatabaseClient,
-    handlers,
-    osu_api::{credentials_grant::CredentialsGrantClient, request::OsuApiRequestClient},
+    handlers::{self, auth::AdminLogin},
+    http_client::build_http_client,
+    osu_api::{
+        credentials_grant::CredentialsGrantClient,
+        request::{OsuApiRequestClient, Requester},
+    },
     AppState,
 };
 use osu_test_client::OsuApiTestClient;
-use surrealdb_migrations::MigrationRunner;
+use serde_json::Value;
 use testcontainers_modules::{
     surrealdb::{SurrealDb, SURREALDB_PORT},
     testcontainers::{runners::AsyncRunner, ContainerAsync, ImageExt},
 };
 
+pub mod mock_requester;
 pub mod osu_test_client;
 
 /// TODO: make it different so that we can have one place we have to change.
 /// Redefining routes because aide and axum_test is not compatible
 pub fn test_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
+        .route(
+            "/admin/migrations",
+            get(handlers::admin::get_migration_status),
+        )
+        .route("/admin/audit", get(handlers::admin::get_audit_logs))
+        .route("/admin/reports", get(handlers::admin::get_reports))
+        .route(
+            "/admin/reports/:report_id/resolve",
+            post(handlers::admin::resolve_report),
+        )
+        .route("/admin/users/merge", post(handlers::admin::merge_users))
+        .route("/admin/cache/warm", post(handlers::admin::warm_caches))
+        .route(
+            "/admin/leaderboard/recompute",
+            post(handlers::admin::recompute_leaderboards),
+        )
+        .route(
+            "/admin/broadcasts",
+            get(handlers::admin::get_broadcast_stats),
+        )
+        .route_layer(middleware::from_fn(handlers::auth::check_admin))
         .route("/search/map", get(handlers::osu_search::osu_beatmap_search))
+        .route(
+            "/search/map/bulk",
+            post(handlers::osu_search::osu_bulk_beatmap_search),
+        )
         .route(
             "/search/map/:beatmap_id",
             get(handlers::osu_search::osu_singular_beatmap_serch),
         )
+        .route(
+            "/search/map/:beatmap_id/exists",
+            get(handlers::osu_search::osu_beatmap_exists),
+        )
         .route(
             "/search/user/:query",
             get(handlers::osu_search::osu_user_search),
@@ -42,6 +80,14 @@ pub fn test_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
             "/influence/influences/:user_id",
             get(handlers::influence::get_user_influences),
         )
+        .route(
+            "/influence/influences/bulk",
+            post(handlers::influence::get_bulk_user_influences),
+        )
+        .route(
+            "/influence/status",
+            post(handlers::influence::get_influence_status),
+        )
         .route(
             "/influence/mentions/:user_id",
             get(handlers::influence::get_user_mentions),
@@ -50,6 +96,10 @@ pub fn test_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
             "/influence/:influenced_to",
             delete(handlers::influence::delete_influence),
         )
+        .route(
+            "/influence/:influenced_to",
+            get(handlers::influence::get_influence),
+        )
         .route(
             "/influence/:influenced_to/map/:beatmap_id",
             patch(handlers::influence::add_influence_beatmap),
@@ -62,12 +112,43 @@ pub fn test_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
             "/influence/:influenced_to/description",
             patch(handlers::influence::update_influence_description),
         )
+        .route(
+            "/influence/:influenced_to/tag",
+            patch(handlers::influence::update_influence_tag),
+        )
+        .route(
+            "/influence/:influenced_to/pin",
+            post(handlers::influence::pin_influence),
+        )
+        .route(
+            "/influence/:influenced_to/pin",
+            delete(handlers::influence::unpin_influence),
+        )
+        .route(
+            "/influence/types",
+            patch(handlers::influence::update_influence_types),
+        )
         .route(
             "/influence/:influenced_to/type/:type_id",
             patch(handlers::influence::update_influence_type),
         )
         .route("/users/me", get(handlers::user::get_me))
+        .route("/users/me", delete(handlers::user::purge_me))
+        .route("/users/me/token", get(handlers::user::get_token_status))
+        .route("/users/recent", get(handlers::user::get_recent_users))
         .route("/users/:user_id", get(handlers::user::get_user))
+        .route(
+            "/users/:user_id/all-beatmaps",
+            get(handlers::user::get_user_all_beatmaps),
+        )
+        .route(
+            "/users/:user_id/connections",
+            get(handlers::influence::get_user_connections),
+        )
+        .route(
+            "/users/:user_id/influence-tree",
+            get(handlers::influence::get_influence_tree),
+        )
         .route("/users/bio", patch(handlers::user::update_user_bio))
         .route("/users/map", patch(handlers::user::add_user_beatmap))
         .route(
@@ -78,12 +159,21 @@ pub fn test_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
             "/users/influence-order",
             post(handlers::user::set_influence_order),
         )
+        .route("/report", post(handlers::report::create_report))
+        .route("/oauth/logout-all", post(handlers::auth::logout_all))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::idempotency::check_idempotency_key,
+        ))
         .layer(middleware::from_fn_with_state(
             state,
             handlers::auth::check_jwt_token,
         ))
         .route("/activity", get(handlers::activity::get_latest_activities))
         .route("/ws", any(handlers::activity::ws_handler))
+        .route("/activity/sse", get(handlers::activity::sse_handler))
+        .route("/ws/leaderboard", any(handlers::leaderboard::ws_handler))
+        .route("/oauth/login", get(handlers::auth::osu_oauth2_login))
         .route(
             "/oauth/osu-redirect",
             get(handlers::auth::osu_oauth2_redirect),
@@ -98,7 +188,65 @@ pub fn test_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
             "/leaderboard/beatmap",
             get(handlers::leaderboard::get_beatmap_leaderboard),
         )
+        .route("/schema/:type_name", get(handlers::schema::get_type_schema))
         .route("/graph", get(handlers::graph_vizualizer::get_graph_data))
+        .route(
+            "/graph/stream",
+            get(handlers::graph_vizualizer::stream_graph_data),
+        )
+}
+
+/// A separate connection to the test database. Useful to call database and update functions
+/// that aren't exposed through routes
+pub async fn test_db_client(
+    surrealdb_container: &ContainerAsync<SurrealDb>,
+) -> Arc<DatabaseClient> {
+    let host_port = surrealdb_container
+        .get_host_port_ipv4(SURREALDB_PORT)
+        .await
+        .expect("Failed to start SurrealDB test container");
+    let url = format!("ws://127.0.0.1:{host_port}");
+    DatabaseClient::new_with_names(&url, "test", "test")
+        .await
+        .expect("failed to initialize db connection")
+}
+
+/// Logs in with `/oauth/admin` and returns the token to send in the `user_token` cookie
+pub async fn admin_jwt(test_server: &TestServer, user_id: u32) -> String {
+    let oauth_body = AdminLogin::new(std::env::var("ADMIN_PASSWORD").unwrap(), user_id);
+    test_server
+        .post("/oauth/admin")
+        .json(&oauth_body)
+        .await
+        .text()
+}
+
+/// Waits until an activity of `event_type` from the user shows up in `/activity` and returns it.
+/// Activities reach the queue through the database live query, so they aren't there as soon as
+/// the request that created them returns. Panics if it doesn't show up in `timeout`
+pub async fn assert_activity(
+    test_server: &TestServer,
+    user_id: u32,
+    event_type: &str,
+    timeout: Duration,
+) -> Value {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let activities: Vec<Value> = test_server.get("/activity").await.json();
+        if let Some(activity) = activities.into_iter().find(|activity| {
+            activity["event_type"] == event_type && activity["user"]["id"] == user_id
+        }) {
+            return activity;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "{} activity of user {} didn't show up in {:?}",
+            event_type,
+            user_id,
+            timeout
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
 }
 
 pub async fn init_test_env(
@@ -106,6 +254,23 @@ pub async fn init_test_env(
 ) -> (TestServer, Arc<OsuApiTestClient>, ContainerAsync<SurrealDb>) {
     dotenvy::dotenv().ok();
 
+    let http_client = build_http_client().expect("Failed to build HTTP client");
+    let working_request_client = Arc::new(OsuApiRequestClient::new(http_client, 10));
+    let test_request_client = OsuApiTestClient::new(working_request_client.clone(), label);
+
+    let (test_server, surrealdb_container) =
+        init_test_env_with_requester(test_request_client.clone()).await;
+    (test_server, test_request_client, surrealdb_container)
+}
+
+/// Starts the database with migrations applied and builds the state on top of it. Use this to
+/// call handlers directly when the test server doesn't give enough control, like reading a
+/// stream at a chosen time
+pub async fn init_test_state(
+    requester: Arc<dyn Requester>,
+) -> (Arc<AppState>, ContainerAsync<SurrealDb>) {
+    dotenvy::dotenv().ok();
+
     // Think of this as join handler. we need to keep the reference alive.
     // Db closes when we drop this. Luckly it's enough to return this and forget.
     let surrealdb_container = SurrealDb::default()
@@ -117,27 +282,16 @@ pub async fn init_test_env(
         .await
         .unwrap();
 
-    let host_port = surrealdb_container
-        .get_host_port_ipv4(SURREALDB_PORT)
-        .await
-        .expect("Failed to start SurrealDB test container");
-    let url = format!("ws://127.0.0.1:{host_port}");
-    let db = DatabaseClient::new(&url)
-        .await
-        .expect("failed to initialize db connection");
-
-    MigrationRunner::new(db.get_inner_ref())
-        .up()
+    let db = test_db_client(&surrealdb_container).await;
+    db.run_migrations()
         .await
         .expect("Failed to apply migrations");
 
-    let working_request_client = Arc::new(OsuApiRequestClient::new(10));
-    let test_request_client = OsuApiTestClient::new(working_request_client.clone(), label);
-    let credentials_grant_client = CredentialsGrantClient::new(test_request_client.clone())
+    let credentials_grant_client = CredentialsGrantClient::new(requester.clone())
         .await
         .expect("Failed to initialize credentials grant client");
 
-    let state = AppState::new(test_request_client.clone(), credentials_grant_client, db).await;
+    let state = AppState::new(requester, credentials_grant_client, db).await;
 
     // Requesting peppy to add in our initial database
     let test_initial_user = state
@@ -146,8 +300,33 @@ pub async fn init_test_env(
         .await
         .unwrap();
     state.db.upsert_user(test_initial_user).await.unwrap();
+    (state, surrealdb_container)
+}
 
+/// Same as [`init_test_env`] but osu! requests go to the given requester.
+/// Use this with [`mock_requester::MockRequester`] to avoid recorded cache files.
+/// The requester should be able to return user with id 2.
+pub async fn init_test_env_with_requester(
+    requester: Arc<dyn Requester>,
+) -> (TestServer, ContainerAsync<SurrealDb>) {
+    let (state, surrealdb_container) = init_test_state(requester).await;
     let routes = test_routes(state.clone()).with_state(state);
     let test_server = TestServer::new(routes).expect("failed to initialize test server");
-    (test_server, test_request_client, surrealdb_container)
+    (test_server, surrealdb_container)
+}
+
+/// Same as [`init_test_env_with_requester`] but the server listens on a real port, which
+/// websockets need. Websocket handlers also read the client address, so a fixed one is set
+pub async fn init_ws_test_env_with_requester(
+    requester: Arc<dyn Requester>,
+) -> (TestServer, ContainerAsync<SurrealDb>) {
+    let (state, surrealdb_container) = init_test_state(requester).await;
+    let routes = test_routes(state.clone())
+        .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))))
+        .with_state(state);
+    let test_server = TestServer::builder()
+        .http_transport()
+        .build(routes)
+        .expect("failed to initialize test server");
+    (test_server, surrealdb_container)
 }
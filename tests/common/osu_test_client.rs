@@ -2,7 +2,10 @@ use std::{
     collections::HashMap,
     fs::File,
     io::{BufReader, BufWriter, Read, Write},
-    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
 };
 
 use axum::async_trait;
@@ -21,6 +24,10 @@ use serde_json::Value;
 
 const OSU_CACHE_BASE_PATH: &str = "tests/data";
 
+/// Replay mode panics on a missing entry unless this is set to `true`. With it, the missing
+/// entries are requested from osu! and added to the cache file
+const RECORD_MISSING_RESPONSES: &str = "RECORD_MISSING_OSU_RESPONSES";
+
 #[derive(Debug)]
 pub enum ClientMod {
     Replay,
@@ -32,9 +39,12 @@ pub struct OsuApiTestClient {
     pub request_cache: RwLock<HashMap<String, Bytes>>,
     pub path: String,
     pub client_mod: ClientMod,
+    /// Missing entries are recorded in replay mode with `RECORD_MISSING_OSU_RESPONSES`, the cache
+    /// is saved if any is added
+    pub has_new_entries: AtomicBool,
 }
 
-fn read_osu_request_cache(file_path: &str) -> Option<HashMap<String, Bytes>> {
+pub fn read_osu_request_cache(file_path: &str) -> Option<HashMap<String, Bytes>> {
     let file = File::open(file_path).ok()?;
     let mut decoder = GzDecoder::new(BufReader::new(file));
     let mut decompressed_data = Vec::new();
@@ -50,7 +60,10 @@ fn read_osu_request_cache(file_path: &str) -> Option<HashMap<String, Bytes>> {
     )
 }
 
-fn save_osu_request_cache(file_path: &str, cache: &HashMap<String, Bytes>) -> std::io::Result<()> {
+pub fn save_osu_request_cache(
+    file_path: &str,
+    cache: &HashMap<String, Bytes>,
+) -> std::io::Result<()> {
     let file = File::create(file_path)?;
     let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
 
@@ -80,6 +93,7 @@ impl OsuApiTestClient {
             path,
             client_mod,
             request_cache,
+            has_new_entries: AtomicBool::new(false),
         })
     }
 
@@ -91,7 +105,9 @@ impl OsuApiTestClient {
     }
 
     pub fn save_cache(&self) -> Result<(), AppError> {
-        if let ClientMod::Record = self.client_mod {
+        if matches!(self.client_mod, ClientMod::Record)
+            || self.has_new_entries.load(Ordering::Relaxed)
+        {
             let cache = self
                 .read_cache_lock()
                 .map_err(|_| AppError::RwLock)?
@@ -107,15 +123,40 @@ impl Requester for OsuApiTestClient {
     async fn get_request(&self, url: &str, token: &str) -> Result<Bytes, AppError> {
         match &self.client_mod {
             ClientMod::Replay => {
-                let read_cache_lock = self.read_cache_lock()?;
-                let bytes = read_cache_lock.get(url).unwrap_or_else(|| {
+                if let Some(bytes) = self.read_cache_lock()?.get(url) {
+                    return Ok(bytes.clone());
+                }
+                let record_missing = std::env::var(RECORD_MISSING_RESPONSES)
+                    .is_ok_and(|value| value.to_lowercase() == "true");
+                if !record_missing {
                     panic!(
-                        "Missing cache entry in {} \
-                        Please delete the cache file to record requests again",
-                        self.path
-                    )
-                });
-                Ok(bytes.clone())
+                        "Missing cache entry for {} in {}. Run the tests with {}=true to record \
+                        it, or delete the cache file to record requests again",
+                        url, self.path, RECORD_MISSING_RESPONSES
+                    );
+                }
+
+                // Entry is deleted with the `osu_test_cache` example to refresh it. Replay mode
+                // only has the test token, so a real one is requested
+                let token = self
+                    .working_client
+                    .get_client_credentials_token()
+                    .await
+                    .unwrap_or_else(|error| {
+                        panic!(
+                            "Missing cache entry in {} and osu! API is not reachable to record it: \
+                            {}",
+                            self.path, error
+                        )
+                    });
+                let bytes = self
+                    .working_client
+                    .get_request(url, &token.access_token)
+                    .await?;
+                self.write_cache_lock()?
+                    .insert(url.to_string(), bytes.clone());
+                self.has_new_entries.store(true, Ordering::Relaxed);
+                Ok(bytes)
             }
 
             ClientMod::Record => {
@@ -0,0 +1,41 @@
+use http::{header::ACCEPT_ENCODING, HeaderMap};
+use mapper_influences_backend_rs::handlers::compression::preferred_encoding;
+
+fn priority(algorithms: &[&str]) -> Vec<String> {
+    algorithms
+        .iter()
+        .map(|algorithm| algorithm.to_string())
+        .collect()
+}
+
+#[test]
+fn test_preferred_encoding_follows_priority() {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        ACCEPT_ENCODING,
+        "gzip;q=1.0, zstd;q=0.5, br".parse().unwrap(),
+    );
+
+    let priority = priority(&["zstd", "gzip"]);
+    assert_eq!(preferred_encoding(&headers, &priority), Some("zstd"));
+
+    headers.insert(ACCEPT_ENCODING, "gzip, zstd;q=0".parse().unwrap());
+    assert_eq!(preferred_encoding(&headers, &priority), Some("gzip"));
+}
+
+#[test]
+fn test_preferred_encoding_wildcard_and_fallbacks() {
+    let priority = priority(&["br", "gzip"]);
+    let mut headers = HeaderMap::new();
+    assert_eq!(preferred_encoding(&headers, &priority), None);
+
+    headers.insert(ACCEPT_ENCODING, "deflate".parse().unwrap());
+    assert_eq!(preferred_encoding(&headers, &priority), None);
+
+    headers.insert(ACCEPT_ENCODING, "*".parse().unwrap());
+    assert_eq!(preferred_encoding(&headers, &priority), Some("br"));
+
+    // explicit weights take precedence over the wildcard
+    headers.insert(ACCEPT_ENCODING, "BR;q=0, *;q=0.1".parse().unwrap());
+    assert_eq!(preferred_encoding(&headers, &priority), Some("gzip"));
+}
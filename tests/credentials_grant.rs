@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use common::mock_requester::MockRequester;
+use mapper_influences_backend_rs::osu_api::credentials_grant::CredentialsGrantClient;
+use tokio::time::sleep;
+
+mod common;
+
+#[tokio::test(start_paused = true)]
+async fn test_token_refresh() {
+    let requester = MockRequester::new();
+    requester.set_token_expires_in(600);
+    let client = CredentialsGrantClient::new(requester.clone())
+        .await
+        .unwrap();
+
+    // Token is requested lazily
+    sleep(Duration::from_secs(1000)).await;
+    assert_eq!(requester.token_request_count(), 0);
+    assert_eq!(client.get_access_token().await.unwrap(), "mock-token-1");
+
+    // Refreshed 120 seconds before it expires
+    sleep(Duration::from_secs(479)).await;
+    assert_eq!(
+        client.get_token_only().unwrap().as_deref(),
+        Some("mock-token-1")
+    );
+    requester.set_token_expires_in(60);
+    sleep(Duration::from_secs(2)).await;
+    assert_eq!(
+        client.get_token_only().unwrap().as_deref(),
+        Some("mock-token-2")
+    );
+
+    // Next refresh uses the expiry of the new token. It's shorter than the buffer, so it's
+    // refreshed halfway through
+    sleep(Duration::from_secs(28)).await;
+    assert_eq!(
+        client.get_token_only().unwrap().as_deref(),
+        Some("mock-token-2")
+    );
+    sleep(Duration::from_secs(2)).await;
+    assert_eq!(
+        client.get_token_only().unwrap().as_deref(),
+        Some("mock-token-3")
+    );
+    assert_eq!(requester.token_request_count(), 3);
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use common::{
+    admin_jwt, init_test_env_with_requester,
+    mock_requester::{mock_user, MockRequester},
+    test_db_client,
+};
+use http::header::COOKIE;
+use mapper_influences_backend_rs::{
+    daily_update::update_once, database::user::UserSmall,
+    osu_api::credentials_grant::CredentialsGrantClient,
+};
+use serde_json::{json, Value};
+
+mod common;
+
+#[tokio::test]
+async fn test_daily_update_marks_deleted_users() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, testcontainer_handle) = init_test_env_with_requester(requester.clone()).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    requester.remove_user(3);
+    let db = test_db_client(&testcontainer_handle).await;
+    let credentials_grant_client = CredentialsGrantClient::new(requester.clone())
+        .await
+        .unwrap();
+    let summary = update_once(
+        credentials_grant_client.clone(),
+        db.clone(),
+        vec![2, 3],
+        Duration::from_millis(1),
+    )
+    .await;
+    assert_eq!(summary.updated, 1);
+    assert_eq!(summary.deleted, vec![3]);
+    assert!(summary.failed.is_empty());
+
+    // Rate limits and server errors are retried in the next run instead
+    requester.fail_user_with_status(2, 429);
+    let summary = update_once(
+        credentials_grant_client,
+        db.clone(),
+        vec![2],
+        Duration::from_millis(1),
+    )
+    .await;
+    assert!(summary.deleted.is_empty());
+    assert_eq!(summary.failed, vec![2]);
+    let deleted: Option<bool> = db
+        .get_inner_ref()
+        .query("SELECT VALUE deleted FROM ONLY user:2")
+        .await
+        .unwrap()
+        .take(0)
+        .unwrap();
+    assert_ne!(deleted, Some(true));
+
+    let leaderboard: Vec<Value> = test_server.get("/leaderboard/user").await.json();
+    assert!(leaderboard.is_empty());
+}
+
+#[tokio::test]
+async fn test_daily_update_keeps_first_seen_time() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_user(mock_user(4, "other mapper"));
+    let (test_server, testcontainer_handle) = init_test_env_with_requester(requester.clone()).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    for user_id in [3, 4] {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, &cookie)
+            .json(&json!({ "user_id": user_id.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    let db = test_db_client(&testcontainer_handle).await;
+    db.set_authenticated(3).await.unwrap();
+    db.set_authenticated(4).await.unwrap();
+
+    // 3 is updated after 4 is created, it should still be older
+    let credentials_grant_client = CredentialsGrantClient::new(requester).await.unwrap();
+    let summary = update_once(
+        credentials_grant_client,
+        db,
+        vec![3],
+        Duration::from_millis(1),
+    )
+    .await;
+    assert_eq!(summary.updated, 1);
+
+    let users: Vec<UserSmall> = test_server
+        .get("/users/recent")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    let user_ids: Vec<u32> = users.iter().map(|user| user.id).collect();
+    assert_eq!(user_ids, vec![4, 3]);
+}
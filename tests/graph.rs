@@ -0,0 +1,59 @@
+use std::collections::HashSet;
+
+use common::{init_test_env_with_requester, mock_requester::MockRequester, test_db_client};
+use serde_json::Value;
+
+mod common;
+
+#[tokio::test]
+async fn test_graph_stream_reads_every_chunk() {
+    let (test_server, testcontainer_handle) =
+        init_test_env_with_requester(MockRequester::new()).await;
+
+    // More users and influences than a single stream chunk. Everyone is influenced by peppy
+    let user_ids: Vec<u32> = (3..1204).collect();
+    let db = test_db_client(&testcontainer_handle).await;
+    db.get_inner_ref()
+        .query(
+            "
+            FOR $id IN $user_ids {
+                LET $user = type::thing('user', $id);
+                CREATE $user CONTENT (SELECT * OMIT id, created_at, updated_at FROM ONLY user:2);
+                RELATE $user->influenced_by->user:2;
+            };
+            ",
+        )
+        .bind(("user_ids", user_ids.clone()))
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+
+    let response = test_server.get("/graph/stream").await;
+    response.assert_status_ok();
+    let lines: Vec<Value> = response
+        .text()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let node_ids: Vec<u64> = lines
+        .iter()
+        .filter(|line| line["type"] == "node")
+        .map(|node| node["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(node_ids.len(), user_ids.len() + 1);
+    assert_eq!(
+        node_ids.iter().collect::<HashSet<_>>().len(),
+        node_ids.len()
+    );
+
+    let links: Vec<&Value> = lines.iter().filter(|line| line["type"] == "link").collect();
+    assert_eq!(links.len(), user_ids.len());
+    let sources: HashSet<u64> = links
+        .iter()
+        .map(|link| link["source"].as_u64().unwrap())
+        .collect();
+    assert_eq!(sources.len(), user_ids.len());
+    assert!(links.iter().all(|link| link["target"] == 2));
+}
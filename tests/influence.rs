@@ -0,0 +1,898 @@
+use common::{
+    admin_jwt, init_test_env_with_requester,
+    mock_requester::{mock_beatmap, mock_user, MockRequester},
+    test_db_client,
+};
+use http::{header::COOKIE, StatusCode};
+use mapper_influences_backend_rs::{
+    database::{
+        influence::{Influence, InfluenceTree},
+        user::User,
+    },
+    handlers::{
+        idempotency::{IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED},
+        influence::UserConnections,
+    },
+    osu_api::{request::Requester, BeatmapEnum, GetID},
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+mod common;
+
+#[tokio::test]
+async fn test_influence_add_remove() {
+    const INFLUENCED_TO: u32 = 4452992;
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(INFLUENCED_TO, "mapper"));
+    requester.add_beatmap(mock_beatmap(4823239, 2245776, INFLUENCED_TO));
+    requester.add_beatmap(mock_beatmap(4606684, 2154431, INFLUENCED_TO));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    let added: Influence = test_server
+        .post(&format!("/influence/{}", INFLUENCED_TO))
+        .add_header(COOKIE, &cookie)
+        .json(&json!({
+            "user_id": INFLUENCED_TO.to_string(),
+            "beatmaps": [4823239, 4606684],
+        }))
+        .await
+        .json();
+    assert_eq!(added.user.id, INFLUENCED_TO);
+
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(influences.len(), 1);
+    assert_eq!(influences[0].user.id, INFLUENCED_TO);
+    assert!(!influences[0].beatmaps.is_empty());
+    assert!(influences[0]
+        .beatmaps
+        .iter()
+        .all(|beatmap| matches!(beatmap, BeatmapEnum::All(_))));
+
+    let deleted: Influence = test_server
+        .delete(&format!("/influence/{}", INFLUENCED_TO))
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(deleted.user.id, INFLUENCED_TO);
+    assert_eq!(
+        deleted
+            .beatmaps
+            .iter()
+            .map(GetID::get_id)
+            .collect::<Vec<_>>(),
+        added.beatmaps.iter().map(GetID::get_id).collect::<Vec<_>>()
+    );
+
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert!(influences.is_empty());
+}
+
+#[tokio::test]
+async fn test_influence_add_missing_user() {
+    const MISSING_USER: u32 = 12345;
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    let response = test_server
+        .post(&format!("/influence/{}", MISSING_USER))
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "user_id": MISSING_USER.to_string() }))
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert!(influences.is_empty());
+}
+
+#[tokio::test]
+async fn test_influence_has_beatmap_filter() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_user(mock_user(4, "another mapper"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    requester.add_beatmap(mock_beatmap(11, 101, 4));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    for (user_id, beatmap_id) in [(3, 10), (4, 11)] {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, cookie.clone())
+            .json(&json!({
+                "user_id": user_id.to_string(),
+                "beatmaps": [beatmap_id],
+            }))
+            .await
+            .assert_status_ok();
+    }
+
+    let response = test_server
+        .get("/influence/influences/2?has_beatmap=11")
+        .add_header(COOKIE, cookie)
+        .await;
+    assert_eq!(response.header("x-total-count"), "1");
+    let influences: Vec<Influence> = response.json();
+    assert_eq!(influences.len(), 1);
+    assert_eq!(influences[0].user.id, 4);
+}
+
+#[tokio::test]
+async fn test_mentions_order_with_equal_counts() {
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    // Added in reverse order to make sure the order isn't coming from insertion
+    for user_id in (3..=5).rev() {
+        let jwt = admin_jwt(&test_server, user_id).await;
+        test_server
+            .post("/influence/2")
+            .add_header(COOKIE, format!("user_token={}", jwt))
+            .json(&json!({ "user_id": "2" }))
+            .await
+            .assert_status_ok();
+    }
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    let mentions: Vec<Influence> = test_server
+        .get("/influence/mentions/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    let mention_ids: Vec<u32> = mentions.iter().map(|mention| mention.user.id).collect();
+    assert_eq!(mention_ids, vec![3, 4, 5]);
+    assert!(mentions
+        .iter()
+        .all(|mention| mention.user.influences == Some(1)));
+
+    let page: Vec<Influence> = test_server
+        .get("/influence/mentions/2?start=1&limit=1")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].user.id, 4);
+}
+
+#[tokio::test]
+async fn test_mention_counter() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    let user: User = test_server
+        .get("/users/3")
+        .add_header(COOKIE, cookie.clone())
+        .await
+        .json();
+    assert_eq!(user.mentions, Some(1));
+
+    test_server
+        .delete("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .await
+        .assert_status_ok();
+    let user: User = test_server
+        .get("/users/3")
+        .add_header(COOKIE, cookie)
+        .await
+        .json();
+    assert_eq!(user.mentions, Some(0));
+}
+
+#[tokio::test]
+async fn test_reconcile_mentions() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    let db = test_db_client(&testcontainer_handle).await;
+    assert!(db.get_mention_discrepancies().await.unwrap().is_empty());
+
+    // Counters are only kept in sync by the event, manual updates can break them
+    db.get_inner_ref()
+        .query("UPDATE user:2 SET mentions = 7; UPDATE user:3 SET mentions = 5;")
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+    let mut discrepancies: Vec<(u32, Option<u32>, u32)> = db
+        .get_mention_discrepancies()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|discrepancy| (discrepancy.id, discrepancy.stored, discrepancy.actual))
+        .collect();
+    discrepancies.sort();
+    assert_eq!(discrepancies, vec![(2, Some(7), 0), (3, Some(5), 1)]);
+
+    db.reconcile_mentions(&[2, 3]).await.unwrap();
+    assert!(db.get_mention_discrepancies().await.unwrap().is_empty());
+    let user: User = test_server
+        .get("/users/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert_eq!(user.mentions, Some(1));
+}
+
+#[tokio::test]
+async fn test_request_multiple_partial_failure() {
+    let requester = MockRequester::new();
+    for beatmap_id in 1..=120 {
+        requester.add_beatmap(mock_beatmap(beatmap_id, beatmap_id, 2));
+    }
+    // second chunk of 50 ids
+    requester.fail_requests_with(75);
+
+    let ids: Vec<u32> = (1..=120).collect();
+    let base_url = "https://osu.ppy.sh/api/v2/beatmaps";
+
+    let strict_result = requester
+        .clone()
+        .request_multiple(base_url, &ids, "token")
+        .await;
+    assert!(strict_result.is_err());
+
+    let response = requester
+        .clone()
+        .request_multiple_partial(base_url, &ids, "token")
+        .await;
+    assert_eq!(response.values.len(), 70);
+    let mut failed_ids = response.failed_ids;
+    failed_ids.sort();
+    assert_eq!(failed_ids, (51..=100).collect::<Vec<u32>>());
+}
+
+#[tokio::test]
+async fn test_bulk_influences() {
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    requester.add_beatmap(mock_beatmap(10, 100, 4));
+    requester.add_beatmap(mock_beatmap(11, 101, 5));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let mut cookies = HashMap::new();
+    for user_id in [2, 3] {
+        let jwt = admin_jwt(&test_server, user_id).await;
+        cookies.insert(user_id, format!("user_token={}", jwt));
+    }
+
+    // user 2 is influenced by 4 and 5, user 3 by 4. user 4 has no influences
+    for (user_id, influenced_to, beatmap_id) in [(2, 4, 10), (2, 5, 11), (3, 4, 10)] {
+        test_server
+            .post(&format!("/influence/{}", influenced_to))
+            .add_header(COOKIE, cookies[&user_id].clone())
+            .json(&json!({
+                "user_id": influenced_to.to_string(),
+                "beatmaps": [beatmap_id],
+            }))
+            .await
+            .assert_status_ok();
+    }
+
+    let response = test_server
+        .post("/influence/influences/bulk")
+        .add_header(COOKIE, cookies[&2].clone())
+        .json(&json!([2, 3, 4, 2]))
+        .await;
+    response.assert_status_ok();
+    let influences: HashMap<u32, Vec<Influence>> = response.json();
+    assert_eq!(influences.len(), 3);
+
+    let user_2_influences: Vec<u32> = influences[&2]
+        .iter()
+        .map(|influence| influence.user.id)
+        .collect();
+    assert_eq!(user_2_influences, vec![4, 5]);
+    assert_eq!(influences[&3].len(), 1);
+    assert_eq!(influences[&3][0].user.id, 4);
+    assert!(influences[&4].is_empty());
+    // beatmaps are hydrated
+    assert!(matches!(
+        influences[&3][0].beatmaps.as_slice(),
+        [BeatmapEnum::All(_)]
+    ));
+
+    let too_many_users: Vec<u32> = (1..=51).collect();
+    test_server
+        .post("/influence/influences/bulk")
+        .add_header(COOKIE, cookies[&2].clone())
+        .json(&too_many_users)
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_get_single_influence() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_user(mock_user(4, "another mapper"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({
+            "user_id": "3",
+            "description": "great mapper",
+            "beatmaps": [10],
+        }))
+        .await
+        .assert_status_ok();
+
+    let influence: Influence = test_server
+        .get("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .await
+        .json();
+    assert_eq!(influence.user.id, 3);
+    assert_eq!(influence.description, "great mapper");
+    assert!(matches!(
+        influence.beatmaps.as_slice(),
+        [BeatmapEnum::All(_)]
+    ));
+
+    test_server
+        .get("/influence/4")
+        .add_header(COOKIE, cookie)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_influence_keeps_missing_beatmap_ids() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    let (test_server, testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "user_id": "3", "beatmaps": [10] }))
+        .await
+        .assert_status_ok();
+
+    // 11 was deleted from osu! after it was added
+    let db = test_db_client(&testcontainer_handle).await;
+    db.get_inner_ref()
+        .query("UPDATE influenced_by SET beatmaps = [11, 10] WHERE in = user:2 AND out = user:3")
+        .await
+        .unwrap()
+        .check()
+        .unwrap();
+
+    let influence: Influence = test_server
+        .get("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .await
+        .json();
+    assert!(matches!(
+        influence.beatmaps.as_slice(),
+        [BeatmapEnum::Id(11), BeatmapEnum::All(_)]
+    ));
+
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, cookie)
+        .await
+        .json();
+    assert!(matches!(
+        influences[0].beatmaps.as_slice(),
+        [BeatmapEnum::Id(11), BeatmapEnum::All(_)]
+    ));
+}
+
+#[tokio::test]
+async fn test_influence_lists_missing_user() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    for list in ["influences", "mentions"] {
+        // existing user without influences or mentions
+        let response = test_server
+            .get(&format!("/influence/{}/2", list))
+            .add_header(COOKIE, cookie.clone())
+            .await;
+        response.assert_status_ok();
+        assert!(response.json::<Vec<Influence>>().is_empty());
+
+        test_server
+            .get(&format!("/influence/{}/999", list))
+            .add_header(COOKIE, cookie.clone())
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn test_idempotent_influence_add() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    let first = test_server
+        .post("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .add_header(IDEMPOTENCY_KEY.clone(), "retry-key")
+        .json(&json!({ "user_id": "3" }))
+        .await;
+    first.assert_status_ok();
+    assert!(first.headers().get(&IDEMPOTENT_REPLAYED).is_none());
+
+    let replayed = test_server
+        .post("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .add_header(IDEMPOTENCY_KEY.clone(), "retry-key")
+        .json(&json!({ "user_id": "3" }))
+        .await;
+    replayed.assert_status_ok();
+    assert_eq!(replayed.header(IDEMPOTENT_REPLAYED.clone()), "true");
+    assert_eq!(replayed.text(), first.text());
+
+    // reusing the key with a different body isn't replayed
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .add_header(IDEMPOTENCY_KEY.clone(), "retry-key")
+        .json(&json!({ "user_id": "3", "description": "different" }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    // the handler only ran once
+    let logs: Vec<Value> = test_server
+        .get("/admin/audit")
+        .add_query_param("actor", 2)
+        .add_header(COOKIE, cookie.clone())
+        .await
+        .json();
+    let add_count = logs
+        .iter()
+        .filter(|log| log["action"] == "ADD_INFLUENCE")
+        .count();
+    assert_eq!(add_count, 1);
+
+    // keys are scoped to the endpoint
+    let deleted = test_server
+        .delete("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .add_header(IDEMPOTENCY_KEY.clone(), "retry-key")
+        .await;
+    deleted.assert_status_ok();
+    assert!(deleted.headers().get(&IDEMPOTENT_REPLAYED).is_none());
+
+    test_server
+        .delete("/influence/3")
+        .add_header(COOKIE, cookie)
+        .add_header(IDEMPOTENCY_KEY.clone(), "")
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_influence_status() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_user(mock_user(4, "other mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    let status: HashMap<u32, bool> = test_server
+        .post("/influence/status")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!([3, 4, 999, 3]))
+        .await
+        .json();
+    assert_eq!(status, HashMap::from([(3, true), (4, false), (999, false)]));
+
+    test_server
+        .post("/influence/status")
+        .add_header(COOKIE, cookie)
+        .json(&(0..201).collect::<Vec<u32>>())
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_user_connections() {
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let mut cookies = HashMap::new();
+    for user_id in 2..=5 {
+        let jwt = admin_jwt(&test_server, user_id).await;
+        cookies.insert(user_id, format!("user_token={}", jwt));
+    }
+
+    // 2 adds 3 and 4 as influences, 4 and 5 add 2
+    for (user_id, influenced_to) in [(2, 3), (2, 4), (4, 2), (5, 2)] {
+        test_server
+            .post(&format!("/influence/{}", influenced_to))
+            .add_header(COOKIE, cookies[&user_id].clone())
+            .json(&json!({ "user_id": influenced_to.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    let connections: UserConnections = test_server
+        .get("/users/2/connections")
+        .add_header(COOKIE, cookies[&2].clone())
+        .await
+        .json();
+    let influence_ids: Vec<u32> = connections.influences.iter().map(|i| i.user.id).collect();
+    assert_eq!(influence_ids, vec![3, 4]);
+    assert_eq!(connections.influence_count, 2);
+    assert_eq!(connections.mentions.len(), 2);
+    assert_eq!(connections.mention_count, 2);
+
+    // Lists are paginated separately, totals stay the same
+    let page: UserConnections = test_server
+        .get("/users/2/connections?influences_start=1&mentions_limit=1")
+        .add_header(COOKIE, cookies[&2].clone())
+        .await
+        .json();
+    assert_eq!(page.influences.len(), 1);
+    assert_eq!(page.influences[0].user.id, 4);
+    assert_eq!(page.influence_count, 2);
+    assert_eq!(page.mentions.len(), 1);
+    assert_eq!(page.mention_count, 2);
+}
+
+#[tokio::test]
+async fn test_influence_tree() {
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let mut cookies = HashMap::new();
+    for user_id in [2, 4] {
+        let jwt = admin_jwt(&test_server, user_id).await;
+        cookies.insert(user_id, format!("user_token={}", jwt));
+    }
+
+    // 2 -> 3, 2 -> 4 -> 5, and 4 -> 2 is a cycle
+    for (user_id, influenced_to) in [(2, 3), (2, 4), (4, 2), (4, 5)] {
+        test_server
+            .post(&format!("/influence/{}", influenced_to))
+            .add_header(COOKIE, cookies[&user_id].clone())
+            .json(&json!({ "user_id": influenced_to.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    let tree: InfluenceTree = test_server
+        .get("/users/2/influence-tree?depth=3")
+        .add_header(COOKIE, cookies[&2].clone())
+        .await
+        .json();
+    assert!(!tree.truncated);
+    assert_eq!(tree.root.id, 2);
+    let first_level: Vec<u32> = tree.root.influences.iter().map(|node| node.id).collect();
+    assert_eq!(first_level, vec![3, 4]);
+    assert!(tree.root.influences[0].influences.is_empty());
+    let second_level = &tree.root.influences[1].influences;
+    assert_eq!(second_level.len(), 2);
+    // Root isn't expanded again
+    assert_eq!(second_level[0].id, 2);
+    assert!(second_level[0].repeated);
+    assert!(second_level[0].influences.is_empty());
+    assert_eq!(second_level[1].id, 5);
+    assert!(!second_level[1].repeated);
+
+    let shallow_tree: InfluenceTree = test_server
+        .get("/users/2/influence-tree?depth=1")
+        .add_header(COOKIE, cookies[&2].clone())
+        .await
+        .json();
+    assert!(shallow_tree.root.influences[1].influences.is_empty());
+
+    test_server
+        .get("/users/99/influence-tree")
+        .add_header(COOKIE, cookies[&2].clone())
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_pin_influence() {
+    let requester = MockRequester::new();
+    for user_id in 2..=4 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    for user_id in [3, 4] {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, &cookie)
+            .json(&json!({ "user_id": user_id.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    let pinned: Influence = test_server
+        .post("/influence/3/pin")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert!(pinned.pinned);
+
+    // Pinning another influence unpins the first one
+    let pinned: Influence = test_server
+        .post("/influence/4/pin")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert!(pinned.pinned);
+    let pinned_ids = |influences: Vec<Influence>| -> Vec<u32> {
+        influences
+            .into_iter()
+            .filter(|influence| influence.pinned)
+            .map(|influence| influence.user.id)
+            .collect()
+    };
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(pinned_ids(influences), vec![4]);
+
+    // Missing influence doesn't unpin the current one
+    test_server
+        .post("/influence/5/pin")
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(pinned_ids(influences), vec![4]);
+
+    let unpinned: Influence = test_server
+        .delete("/influence/4/pin")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert!(!unpinned.pinned);
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert!(pinned_ids(influences).is_empty());
+}
+
+#[tokio::test]
+async fn test_failed_influence_add_is_rolled_back() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) =
+        init_test_env_with_requester(requester.clone()).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    // Second relation fails as a duplicate, target upsert shouldn't be kept either
+    requester.add_user(mock_user(3, "renamed mapper"));
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(influences.len(), 1);
+    assert_eq!(influences[0].user.username, "mapper");
+}
+
+#[tokio::test]
+async fn test_bulk_influence_type_update() {
+    let requester = MockRequester::new();
+    for user_id in 2..=4 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    for user_id in [3, 4] {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, &cookie)
+            .json(&json!({ "user_id": user_id.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+    let influence_types = |influences: Vec<Influence>| -> Vec<(u32, u8)> {
+        influences
+            .into_iter()
+            .map(|influence| (influence.user.id, influence.influence_type))
+            .collect()
+    };
+
+    let updated: Vec<Influence> = test_server
+        .patch("/influence/types")
+        .add_header(COOKIE, &cookie)
+        .json(&json!([
+            { "influenced_to": 3, "type_id": 2 },
+            { "influenced_to": 4, "type_id": 3 },
+        ]))
+        .await
+        .json();
+    assert_eq!(influence_types(updated), vec![(3, 2), (4, 3)]);
+
+    // Missing influences are skipped, the rest are still updated
+    let updated: Vec<Influence> = test_server
+        .patch("/influence/types")
+        .add_header(COOKIE, &cookie)
+        .json(&json!([
+            { "influenced_to": 3, "type_id": 1 },
+            { "influenced_to": 5, "type_id": 1 },
+        ]))
+        .await
+        .json();
+    assert_eq!(influence_types(updated), vec![(3, 1)]);
+
+    // A single invalid type rejects the whole request
+    test_server
+        .patch("/influence/types")
+        .add_header(COOKIE, &cookie)
+        .json(&json!([
+            { "influenced_to": 3, "type_id": 2 },
+            { "influenced_to": 4, "type_id": 4 },
+        ]))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    let influences: Vec<Influence> = test_server
+        .get("/influence/influences/2")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(influence_types(influences), vec![(3, 1), (4, 3)]);
+}
+
+#[tokio::test]
+async fn test_update_influence_tag() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    // Limit is in characters, this is 150 bytes
+    let tag = "日".repeat(50);
+    let influence: Influence = test_server
+        .patch("/influence/3/tag")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "tag": tag }))
+        .await
+        .json();
+    assert_eq!(influence.tag, Some(tag));
+
+    test_server
+        .patch("/influence/3/tag")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "tag": "日".repeat(51) }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    let influence: Influence = test_server
+        .patch("/influence/3/tag")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "tag": null }))
+        .await
+        .json();
+    assert_eq!(influence.tag, None);
+
+    test_server
+        .patch("/influence/4/tag")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "tag": "missing" }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,52 @@
+use common::{
+    admin_jwt, init_test_env_with_requester,
+    mock_requester::{mock_user, MockRequester},
+};
+use http::{header::COOKIE, StatusCode};
+use serde_json::json;
+
+mod common;
+
+// Separate test binary since the limit is read from the environment once per process
+#[tokio::test]
+async fn test_influence_limit() {
+    std::env::set_var("MAX_INFLUENCES_PER_USER", "2");
+
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    for user_id in [3, 4] {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, cookie.clone())
+            .json(&json!({ "user_id": user_id.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    test_server
+        .post("/influence/5")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "user_id": "5" }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    // removing one makes room again
+    test_server
+        .delete("/influence/4")
+        .add_header(COOKIE, cookie.clone())
+        .await
+        .assert_status_ok();
+    test_server
+        .post("/influence/5")
+        .add_header(COOKIE, cookie)
+        .json(&json!({ "user_id": "5" }))
+        .await
+        .assert_status_ok();
+}
@@ -1,4 +1,10 @@
-use common::init_test_env;
+use common::{
+    admin_jwt, init_test_env, init_test_env_with_requester, init_ws_test_env_with_requester,
+    mock_requester::{mock_beatmap, mock_user, MockRequester},
+};
+use http::header::COOKIE;
+use mapper_influences_backend_rs::{database::leaderboard::LeaderboardBeatmap, osu_api::GetID};
+use serde_json::{json, Value};
 
 mod common;
 
@@ -9,3 +15,229 @@ async fn test_beatmap_leaderboard() {
     let _response = test_server.get("/leaderboard/beatmap").await;
     test_requester.save_cache().expect("failed to save cache");
 }
+
+#[tokio::test]
+async fn test_user_leaderboard_tie_order() {
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 5).await;
+    for user_id in (2..=4).rev() {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, format!("user_token={}", jwt))
+            .json(&json!({ "user_id": user_id.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    let leaderboard: Vec<Value> = test_server.get("/leaderboard/user").await.json();
+    let user_ids: Vec<u64> = leaderboard
+        .iter()
+        .map(|entry| entry["user"]["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(user_ids, vec![2, 3, 4]);
+}
+
+#[tokio::test]
+async fn test_beatmap_leaderboard_tie_order() {
+    let requester = MockRequester::new();
+    for user_id in 2..=4 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    requester.add_beatmap(mock_beatmap(11, 101, 3));
+    requester.add_beatmap(mock_beatmap(12, 102, 4));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    for (user_id, beatmaps) in [(4, vec![12]), (3, vec![11, 10])] {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, format!("user_token={}", jwt))
+            .json(&json!({ "user_id": user_id.to_string(), "beatmaps": beatmaps }))
+            .await
+            .assert_status_ok();
+    }
+
+    let leaderboard: Vec<LeaderboardBeatmap> = test_server.get("/leaderboard/beatmap").await.json();
+    let beatmapset_ids: Vec<u32> = leaderboard
+        .iter()
+        .map(|entry| entry.beatmap.get_id())
+        .collect();
+    assert_eq!(beatmapset_ids, vec![100, 101, 102]);
+
+    let mut paginated_ids = Vec::new();
+    for start in 0..3 {
+        let page: Vec<LeaderboardBeatmap> = test_server
+            .get(&format!("/leaderboard/beatmap?start={}&limit=1", start))
+            .await
+            .json();
+        paginated_ids.extend(page.iter().map(|entry| entry.beatmap.get_id()));
+    }
+    assert_eq!(paginated_ids, beatmapset_ids);
+}
+
+#[tokio::test]
+async fn test_beatmap_leaderboard_mode_filter() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(3, "mapper 3"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    let mut taiko_beatmap = mock_beatmap(11, 101, 3);
+    taiko_beatmap["mode"] = json!("taiko");
+    requester.add_beatmap(taiko_beatmap);
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3", "beatmaps": [10, 11] }))
+        .await
+        .assert_status_ok();
+
+    for (query, expected_ids) in [
+        ("", vec![100, 101]),
+        ("?mode=osu", vec![100]),
+        ("?mode=taiko", vec![101]),
+        ("?mode=mania", vec![]),
+    ] {
+        let leaderboard: Vec<LeaderboardBeatmap> = test_server
+            .get(&format!("/leaderboard/beatmap{}", query))
+            .await
+            .json();
+        let beatmapset_ids: Vec<u32> = leaderboard
+            .iter()
+            .map(|entry| entry.beatmap.get_id())
+            .collect();
+        assert_eq!(beatmapset_ids, expected_ids, "query: {}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_beatmap_leaderboard_mode_pagination() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(3, "mapper 3"));
+    for (beatmap_id, beatmapset_id, mode) in [
+        (10, 100, "osu"),
+        (11, 101, "taiko"),
+        (12, 102, "osu"),
+        (13, 103, "osu"),
+    ] {
+        let mut beatmap = mock_beatmap(beatmap_id, beatmapset_id, 3);
+        beatmap["mode"] = json!(mode);
+        requester.add_beatmap(beatmap);
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3", "beatmaps": [10, 11, 12, 13] }))
+        .await
+        .assert_status_ok();
+
+    let response = test_server.get("/leaderboard/beatmap?mode=osu").await;
+    assert_eq!(response.header("x-total-count"), "3");
+    let leaderboard: Vec<LeaderboardBeatmap> = response.json();
+    let beatmapset_ids: Vec<u32> = leaderboard
+        .iter()
+        .map(|entry| entry.beatmap.get_id())
+        .collect();
+    assert_eq!(beatmapset_ids.len(), 3);
+    assert!(!beatmapset_ids.contains(&101));
+
+    // Offsets are about the filtered list, also past the cached window
+    let mut paginated_ids = Vec::new();
+    for start in [0, 1, 2, 3, 1000] {
+        let response = test_server
+            .get(&format!(
+                "/leaderboard/beatmap?mode=osu&start={}&limit=1",
+                start
+            ))
+            .await;
+        assert_eq!(response.header("x-total-count"), "3");
+        let page: Vec<LeaderboardBeatmap> = response.json();
+        paginated_ids.extend(page.iter().map(|entry| entry.beatmap.get_id()));
+    }
+    assert_eq!(paginated_ids, beatmapset_ids);
+}
+
+#[tokio::test]
+async fn test_user_leaderboard_beyond_cache() {
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 5).await;
+    for user_id in 2..=4 {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, format!("user_token={}", jwt))
+            .json(&json!({ "user_id": user_id.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    // start + limit goes past the default cache size of 500, so this is read from database
+    let response = test_server.get("/leaderboard/user?start=1&limit=500").await;
+    assert_eq!(response.header("x-total-count"), "3");
+    let leaderboard: Vec<Value> = response.json();
+    let user_ids: Vec<u64> = leaderboard
+        .iter()
+        .map(|entry| entry["user"]["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(user_ids, vec![3, 4]);
+
+    let response = test_server.get("/leaderboard/user").await;
+    assert_eq!(response.header("x-total-count"), "3");
+
+    // Limit is capped at the cache size
+    let response = test_server
+        .get("/leaderboard/user?start=1&limit=4294967295")
+        .await;
+    response.assert_status_ok();
+    assert!(response
+        .header("link")
+        .to_str()
+        .unwrap()
+        .contains("start=0&limit=500"));
+}
+
+#[tokio::test]
+async fn test_user_leaderboard_websocket_snapshot() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_ws_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+    // Refreshes the snapshot right away instead of on the next tick
+    test_server
+        .post("/admin/leaderboard/recompute")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .assert_status_ok();
+
+    let mut websocket = test_server
+        .get_websocket("/ws/leaderboard")
+        .await
+        .into_websocket()
+        .await;
+    // the latest snapshot is sent right after connecting
+    let snapshot: Vec<Value> = websocket.receive_json().await;
+    assert!(!snapshot.is_empty());
+    assert_eq!(snapshot[0]["user"]["id"], 3);
+}
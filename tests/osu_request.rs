@@ -0,0 +1,41 @@
+use axum::{routing::get, Router};
+use http::StatusCode;
+use mapper_influences_backend_rs::{
+    error::AppError,
+    http_client::build_http_client,
+    osu_api::request::{OsuApiRequestClient, Requester},
+};
+
+#[tokio::test]
+async fn test_error_statuses() {
+    let app = Router::new()
+        .route(
+            "/missing",
+            get(|| async { (StatusCode::NOT_FOUND, r#"{"error":null}"#) }),
+        )
+        .route(
+            "/limited",
+            get(|| async {
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    r#"{"error":"Too Many Attempts."}"#,
+                )
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let client = OsuApiRequestClient::new(build_http_client().unwrap(), 1);
+    // Missing resources keep their body, callers tell them apart from it
+    let body = client
+        .get_request(&format!("http://{}/missing", address), "token")
+        .await
+        .unwrap();
+    assert_eq!(&body[..], br#"{"error":null}"#);
+    let error = client
+        .get_request(&format!("http://{}/limited", address), "token")
+        .await
+        .unwrap_err();
+    assert!(matches!(error, AppError::UpstreamStatus(429)));
+}
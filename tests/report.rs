@@ -0,0 +1,119 @@
+use common::{
+    admin_jwt, init_test_env_with_requester,
+    mock_requester::{mock_user, MockRequester},
+};
+use http::{header::COOKIE, StatusCode};
+use serde_json::{json, Value};
+
+mod common;
+
+#[tokio::test]
+async fn test_report_and_resolve() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    let report: Value = test_server
+        .post("/report")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({
+            "target_type": "INFLUENCE",
+            "target_id": 2,
+            "influenced_to": 3,
+            "reason": "  spam  ",
+        }))
+        .await
+        .json();
+    assert_eq!(report["reason"], "spam");
+    assert_eq!(report["influenced_to"], 3);
+
+    test_server
+        .post("/report")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "target_type": "INFLUENCE", "target_id": 2, "reason": "spam" }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    test_server
+        .post("/report")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "target_type": "USER", "target_id": 999, "reason": "spam" }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    test_server
+        .post("/report")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "target_type": "USER", "target_id": 3, "reason": " " }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Length is counted in characters
+    test_server
+        .post("/report")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "target_type": "USER", "target_id": 3, "reason": "日".repeat(1001) }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    test_server
+        .post("/report")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "target_type": "USER", "target_id": 3, "reason": "日".repeat(1000) }))
+        .await
+        .assert_status_ok();
+
+    for _ in 0..3 {
+        test_server
+            .post("/report")
+            .add_header(COOKIE, cookie.clone())
+            .json(&json!({ "target_type": "USER", "target_id": 3, "reason": "bio" }))
+            .await
+            .assert_status_ok();
+    }
+    test_server
+        .post("/report")
+        .add_header(COOKIE, cookie.clone())
+        .json(&json!({ "target_type": "USER", "target_id": 3, "reason": "bio" }))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    let response = test_server
+        .get("/admin/reports")
+        .add_header(COOKIE, cookie.clone())
+        .await;
+    assert_eq!(response.header("x-total-count"), "5");
+    let open_reports: Vec<Value> = response.json();
+    assert_eq!(open_reports[0]["id"], report["id"]);
+
+    let resolved: Value = test_server
+        .post(&format!(
+            "/admin/reports/{}/resolve",
+            report["id"].as_str().unwrap()
+        ))
+        .add_header(COOKIE, cookie.clone())
+        .await
+        .json();
+    assert_eq!(resolved["resolved_by"], 2);
+
+    let resolved_reports: Vec<Value> = test_server
+        .get("/admin/reports")
+        .add_query_param("resolved", true)
+        .add_header(COOKIE, cookie.clone())
+        .await
+        .json();
+    assert_eq!(resolved_reports.len(), 1);
+
+    test_server
+        .post("/admin/reports/missing/resolve")
+        .add_header(COOKIE, cookie)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,70 @@
+use mapper_influences_backend_rs::sanitize::sanitize_user_text;
+
+#[test]
+fn test_html_tags_are_removed() {
+    assert_eq!(sanitize_user_text("hello <b>world</b>"), "hello world");
+    assert_eq!(
+        sanitize_user_text("<script>alert(1)</script>text"),
+        "alert(1)text"
+    );
+    assert_eq!(
+        sanitize_user_text("<img src=x onerror=alert(1)>mapper"),
+        "mapper"
+    );
+    assert_eq!(
+        sanitize_user_text("before <!-- <b>comment</b> --> after"),
+        "before  after"
+    );
+    assert_eq!(sanitize_user_text("unclosed <div tag"), "unclosed div tag");
+}
+
+#[test]
+fn test_nested_html_tags_are_removed() {
+    assert_eq!(
+        sanitize_user_text("<<script>script>alert(1)<</script>/script>"),
+        "alert(1)"
+    );
+    assert_eq!(sanitize_user_text("<<b>img src=x onerror=alert(1)>"), "");
+    assert_eq!(
+        sanitize_user_text("[click](java<<b>script:alert(1))"),
+        "[click](alert(1))"
+    );
+}
+
+#[test]
+fn test_deeply_nested_html_tags_are_removed() {
+    let text = format!("{}script>alert(1)", "<".repeat(1000) + &"b>".repeat(999));
+    assert!(!sanitize_user_text(&text).contains('<'));
+    let text = format!("[click](java{}script:alert(1))", "<".repeat(20));
+    assert_eq!(sanitize_user_text(&text), "[click](alert(1))");
+}
+
+#[test]
+fn test_plain_text_is_kept() {
+    let text = "I <3 this mapper, 5 < 6 & [profile](https://osu.ppy.sh/users/2) data: 日本語";
+    assert_eq!(sanitize_user_text(text), text);
+}
+
+#[test]
+fn test_unsafe_link_schemes_are_removed() {
+    assert_eq!(
+        sanitize_user_text("[click](javascript:alert(1))"),
+        "[click](alert(1))"
+    );
+    assert_eq!(
+        sanitize_user_text("[click]( JaVa\tScRiPt:alert(1))"),
+        "[click](alert(1))"
+    );
+    assert_eq!(
+        sanitize_user_text("[click](java<b>script:alert(1))"),
+        "[click](alert(1))"
+    );
+    assert_eq!(
+        sanitize_user_text("[click](javascript:javascript:alert(1))"),
+        "[click](alert(1))"
+    );
+    assert_eq!(
+        sanitize_user_text("![image](data:image/png;base64,AAAA)"),
+        "![image](image/png;base64,AAAA)"
+    );
+}
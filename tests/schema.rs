@@ -0,0 +1,28 @@
+use common::{init_test_env_with_requester, mock_requester::MockRequester};
+use http::StatusCode;
+use serde_json::Value;
+
+mod common;
+
+#[tokio::test]
+async fn test_type_schema() {
+    let (test_server, _testcontainer_handle) =
+        init_test_env_with_requester(MockRequester::new()).await;
+
+    let schema: Value = test_server.get("/schema/User").await.json();
+    assert_eq!(schema["title"], "User");
+    assert!(schema["properties"]["username"].is_object());
+    assert!(schema["required"]
+        .as_array()
+        .unwrap()
+        .contains(&Value::from("id")));
+
+    let schema: Value = test_server.get("/schema/Activity").await.json();
+    assert_eq!(schema["title"], "Activity");
+    assert!(schema["definitions"]["UserSmall"].is_object());
+
+    test_server
+        .get("/schema/AppState")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
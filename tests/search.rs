@@ -0,0 +1,289 @@
+use common::{
+    admin_jwt, init_test_env_with_requester,
+    mock_requester::{mock_beatmap, mock_user, MockRequester},
+};
+use http::{header::COOKIE, StatusCode};
+use mapper_influences_backend_rs::{
+    database::user::UserSmall,
+    handlers::osu_search::{rank_user_search, BeatmapExistence, SingularBeatmapset},
+    osu_api::BeatmapsetSmall,
+};
+use serde_json::json;
+use std::collections::HashMap;
+
+mod common;
+
+#[tokio::test]
+async fn test_beatmap_search_query_forwarding() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) =
+        init_test_env_with_requester(requester.clone()).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    test_server
+        .get("/search/map?q=hello%20world&m=0&unknown=1")
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status_ok();
+    // same parameters in a different order should hit the cache
+    test_server
+        .get("/search/map?m=0&q=hello+world")
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status_ok();
+
+    assert_eq!(requester.search_queries(), vec!["q=hello+world&m=0"]);
+}
+
+#[tokio::test]
+async fn test_search_query_too_long() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) =
+        init_test_env_with_requester(requester.clone()).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    // counted in characters, not bytes
+    test_server
+        .get(&format!("/search/user/{}", "日".repeat(100)))
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status_ok();
+    test_server
+        .get(&format!("/search/user/{}", "日".repeat(101)))
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    let long_query = "a".repeat(1000);
+    test_server
+        .get(&format!("/search/user/{}", long_query))
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    test_server
+        .get(&format!("/search/map?q={}", long_query))
+        .add_header(COOKIE, &cookie)
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    // rejected before reaching osu!
+    assert!(requester.search_queries().is_empty());
+}
+
+fn db_user(id: u32, mentions: u32) -> UserSmall {
+    serde_json::from_value(json!({
+        "id": id,
+        "username": format!("user{}", id),
+        "avatar_url": format!("https://a.ppy.sh/{}?", id),
+        "groups": [],
+        "country_code": "TR",
+        "country_name": "Turkey",
+        "ranked_maps": 1,
+        "ranked_mapper": true,
+        "mentions": mentions,
+        "previous_usernames": [],
+    }))
+    .expect("invalid test user")
+}
+
+#[test]
+fn test_rank_user_search() {
+    let osu_order = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    // without database users osu! order is kept
+    assert_eq!(rank_user_search(&osu_order, &[], 3), vec![1, 2, 3]);
+
+    // database users close to the top are moved up, mentions move them further
+    let db_users = [db_user(3, 0), db_user(4, 50)];
+    assert_eq!(rank_user_search(&osu_order, &db_users, 3), vec![4, 1, 3]);
+
+    // a database user far down doesn't pass the best osu! matches
+    let db_users = [db_user(9, 1000)];
+    assert_eq!(rank_user_search(&osu_order, &db_users, 3), vec![1, 2, 3]);
+}
+
+#[tokio::test]
+async fn test_user_search_limit() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    for user_id in 10..25 {
+        requester.add_user(mock_user(user_id, &format!("mapper{}", user_id)));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    for (query, expected_count) in [("", 3), ("?limit=5", 5), ("?limit=100", 10)] {
+        let users: Vec<UserSmall> = test_server
+            .get(&format!("/search/user/mapper{}", query))
+            .add_header(COOKIE, &cookie)
+            .await
+            .json();
+        assert_eq!(users.len(), expected_count);
+    }
+}
+
+#[tokio::test]
+async fn test_user_search_database_fallback() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "Fallback Mapper"));
+    let mut renamed_user = mock_user(4, "newname");
+    renamed_user["previous_usernames"] = json!(["Fallback Old"]);
+    requester.add_user(renamed_user);
+    let (test_server, _testcontainer_handle) =
+        init_test_env_with_requester(requester.clone()).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    // adding influences saves the users in the database
+    for user_id in [3, 4] {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, &cookie)
+            .json(&json!({ "user_id": user_id.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    requester.fail_user_search();
+    let users: Vec<UserSmall> = test_server
+        .get("/search/user/fallback?limit=5")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    let mut user_ids: Vec<u32> = users.iter().map(|user| user.id).collect();
+    user_ids.sort();
+    assert_eq!(user_ids, vec![3, 4]);
+}
+
+#[tokio::test]
+async fn test_bulk_beatmap_search() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    requester.add_beatmap(mock_beatmap(11, 101, 3));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    // 12 doesn't exist
+    let beatmaps: HashMap<u32, BeatmapsetSmall> = test_server
+        .post("/search/map/bulk")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "beatmaps": [10, 11, 12] }))
+        .await
+        .json();
+    assert_eq!(beatmaps.len(), 2);
+    assert_eq!(beatmaps[&10].id, 100);
+    assert_eq!(beatmaps[&11].user_name, "mapper");
+    assert_eq!(
+        beatmaps[&10].covers.card.as_deref(),
+        Some("https://assets.ppy.sh/beatmaps/100/covers/card.jpg")
+    );
+    assert_eq!(
+        beatmaps[&10].covers.card_2x.as_deref(),
+        Some("https://assets.ppy.sh/beatmaps/100/covers/card@2x.jpg")
+    );
+    // Mock doesn't return slimcover variants
+    assert_eq!(beatmaps[&10].covers.slimcover, None);
+    assert_eq!(beatmaps[&10].covers.slimcover_2x, None);
+
+    let too_many_beatmaps: Vec<u32> = (1..=101).collect();
+    test_server
+        .post("/search/map/bulk")
+        .add_header(COOKIE, &cookie)
+        .json(&json!({ "beatmaps": too_many_beatmaps }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_singular_beatmap_version_filter() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    for beatmap_id in 10..=12 {
+        requester.add_beatmap(mock_beatmap(beatmap_id, 100, 3));
+    }
+    requester.add_beatmap(mock_beatmap(20, 101, 3));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    let beatmap_ids = |beatmapset: &SingularBeatmapset| -> Vec<u32> {
+        beatmapset
+            .beatmapset
+            .beatmaps
+            .iter()
+            .map(|beatmap| beatmap.id)
+            .collect()
+    };
+
+    // Only the requested difficulty without the filter
+    let beatmapset: SingularBeatmapset = test_server
+        .get("/search/map/11")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(beatmapset.requested_id, 11);
+    assert_eq!(beatmap_ids(&beatmapset), vec![11]);
+
+    // Requested difficulty is kept even if it doesn't match
+    let beatmapset: SingularBeatmapset = test_server
+        .get("/search/map/11")
+        .add_query_param("version", "DIFFICULTY 12")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(beatmapset.requested_id, 11);
+    assert_eq!(beatmap_ids(&beatmapset), vec![11, 12]);
+
+    let beatmapset: SingularBeatmapset = test_server
+        .get("/search/map/11?version=")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(beatmap_ids(&beatmapset), vec![10, 11, 12]);
+}
+
+#[tokio::test]
+async fn test_beatmap_exists() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+
+    let existence: BeatmapExistence = test_server
+        .get("/search/map/10/exists")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert!(existence.exists);
+    let beatmap = existence.beatmap.unwrap();
+    assert_eq!(beatmap.beatmapset_id, 100);
+    assert_eq!(beatmap.version, "Difficulty 10");
+
+    let response = test_server
+        .get("/search/map/11/exists")
+        .add_header(COOKIE, &cookie)
+        .await;
+    response.assert_status_ok();
+    let existence: BeatmapExistence = response.json();
+    assert!(!existence.exists);
+    assert!(existence.beatmap.is_none());
+}
@@ -1,9 +1,22 @@
-use common::init_test_env;
-use http::header::COOKIE;
+use common::{
+    admin_jwt, assert_activity, init_test_env, init_test_env_with_requester,
+    mock_requester::{mock_beatmap, mock_user, MockRequester},
+    test_db_client,
+};
+use http::{
+    header::{COOKIE, SET_COOKIE},
+    StatusCode,
+};
 use mapper_influences_backend_rs::{
-    database::user::User,
-    handlers::{auth::AdminLogin, BeatmapRequest},
+    database::{
+        audit::AuditAction,
+        user::{User, UserSmall},
+    },
+    handlers::{user::TokenStatus, BeatmapRequest},
+    osu_api::{BeatmapEnum, BeatmapsetSmall, GameMode, GetID},
 };
+use serde_json::{json, Value};
+use std::time::Duration;
 
 mod common;
 
@@ -12,21 +25,517 @@ async fn test_user_beatmap_add() {
     const TEST_LABEL: &str = "UserBeatmapAdd";
     let (test_server, test_requester, _testcontainer_handle) = init_test_env(TEST_LABEL).await;
 
-    let oauth_body = AdminLogin::new(std::env::var("ADMIN_PASSWORD").unwrap(), 2);
-    let jwt = test_server
-        .post("/oauth/admin")
-        .json(&oauth_body)
-        .await
-        .text();
+    let jwt = admin_jwt(&test_server, 2).await;
 
     let _result: User = test_server
         .patch("/users/map")
         .add_header(COOKIE, format!("user_token={}", jwt))
         .json(&BeatmapRequest {
             ids: vec![4823239, 4606684, 4606684].into_iter().collect(),
+            ..Default::default()
         })
         .await
         .json();
 
     test_requester.save_cache().expect("failed to save cache");
 }
+
+#[tokio::test]
+async fn test_user_beatmap_add_mocked() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    requester.add_beatmap(mock_beatmap(11, 101, 2));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let user: User = test_server
+        .patch("/users/map")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&BeatmapRequest {
+            ids: vec![10, 11].into_iter().collect(),
+            ..Default::default()
+        })
+        .await
+        .json();
+
+    let mut beatmapset_ids: Vec<u32> = user
+        .beatmaps
+        .iter()
+        .map(|beatmap| {
+            assert!(matches!(beatmap, BeatmapEnum::All(_)));
+            beatmap.get_id()
+        })
+        .collect();
+    beatmapset_ids.sort();
+    assert_eq!(beatmapset_ids, vec![100, 101]);
+}
+
+#[tokio::test]
+async fn test_user_beatmap_add_missing_map() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_beatmap(mock_beatmap(10, 100, 2));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let response = test_server
+        .patch("/users/map")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&BeatmapRequest {
+            ids: vec![10, 12].into_iter().collect(),
+            ..Default::default()
+        })
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+
+    let user: User = test_server
+        .get("/users/me")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert!(user.beatmaps.is_empty());
+}
+
+#[tokio::test]
+async fn test_user_beatmap_add_missing_mapset() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_beatmap(mock_beatmap(10, 100, 2));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let response = test_server
+        .patch("/users/map")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&BeatmapRequest {
+            beatmapset_ids: vec![100, 101],
+            ..Default::default()
+        })
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+
+    let user: User = test_server
+        .get("/users/me")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert!(user.beatmaps.is_empty());
+}
+
+#[tokio::test]
+async fn test_user_beatmap_readd() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_beatmap(mock_beatmap(10, 100, 2));
+    let (test_server, testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let mut responses = Vec::new();
+    for _ in 0..2 {
+        let user: User = test_server
+            .patch("/users/map")
+            .add_header(COOKIE, format!("user_token={}", jwt))
+            .json(&BeatmapRequest {
+                ids: vec![10].into_iter().collect(),
+                ..Default::default()
+            })
+            .await
+            .json();
+        responses.push(user);
+    }
+
+    assert_eq!(responses[0].beatmaps, responses[1].beatmaps);
+    assert_eq!(responses[1].beatmaps.len(), 1);
+
+    // Only the first add creates an activity
+    let db = test_db_client(&testcontainer_handle).await;
+    let activity_count: Option<u32> = db
+        .get_inner_ref()
+        .query(
+            r#"
+            RETURN count(
+                SELECT id FROM activity WHERE user = user:2 AND event_type = "ADD_USER_BEATMAP"
+            )
+            "#,
+        )
+        .await
+        .unwrap()
+        .take(0)
+        .unwrap();
+    assert_eq!(activity_count, Some(1));
+}
+
+#[tokio::test]
+async fn test_user_all_beatmaps() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    requester.add_beatmap(mock_beatmap(11, 101, 3));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    test_server
+        .patch("/users/map")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&BeatmapRequest {
+            ids: vec![10].into_iter().collect(),
+            ..Default::default()
+        })
+        .await
+        .assert_status_ok();
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({
+            "user_id": "3",
+            "beatmaps": [10, 11],
+        }))
+        .await
+        .assert_status_ok();
+
+    let beatmapsets: Vec<BeatmapsetSmall> = test_server
+        .get("/users/2/all-beatmaps")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    let mut beatmapset_ids: Vec<u32> = beatmapsets.iter().map(|set| set.id).collect();
+    beatmapset_ids.sort();
+    assert_eq!(beatmapset_ids, vec![100, 101]);
+}
+
+#[tokio::test]
+async fn test_token_status() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let response = test_server
+        .get("/users/me/token")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await;
+    assert!(!response.text().contains(&jwt));
+    let status: TokenStatus = response.json();
+    assert!(status.seconds_remaining > 0);
+    assert!(status.expires_at > status.seconds_remaining);
+}
+
+#[tokio::test]
+async fn test_bio_too_long() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let response = test_server
+        .patch("/users/bio")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "bio": "a".repeat(5001) }))
+        .await;
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.json::<Value>()["max_length"], 5000);
+
+    test_server
+        .patch("/users/bio")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "bio": "a".repeat(5000) }))
+        .await
+        .assert_status_ok();
+
+    // multibyte characters count as one
+    test_server
+        .patch("/users/bio")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "bio": "あ".repeat(5000) }))
+        .await
+        .assert_status_ok();
+    test_server
+        .patch("/users/bio")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "bio": "あ".repeat(5001) }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_recent_users() {
+    let requester = MockRequester::new();
+    for user_id in 2..=5 {
+        requester.add_user(mock_user(user_id, &format!("mapper {}", user_id)));
+    }
+    let (test_server, testcontainer_handle) = init_test_env_with_requester(requester).await;
+
+    let jwt = admin_jwt(&test_server, 2).await;
+    let cookie = format!("user_token={}", jwt);
+    // Users are created in this order
+    for user_id in 3..=5 {
+        test_server
+            .post(&format!("/influence/{}", user_id))
+            .add_header(COOKIE, &cookie)
+            .json(&json!({ "user_id": user_id.to_string() }))
+            .await
+            .assert_status_ok();
+    }
+
+    // 5 is only added as an influence
+    let db = test_db_client(&testcontainer_handle).await;
+    db.set_authenticated(3).await.unwrap();
+    db.set_authenticated(4).await.unwrap();
+
+    let response = test_server
+        .get("/users/recent")
+        .add_header(COOKIE, &cookie)
+        .await;
+    assert_eq!(response.header("x-total-count"), "2");
+    let users: Vec<UserSmall> = response.json();
+    let user_ids: Vec<u32> = users.iter().map(|user| user.id).collect();
+    assert_eq!(user_ids, vec![4, 3]);
+
+    let page: Vec<UserSmall> = test_server
+        .get("/users/recent?start=1&limit=1")
+        .add_header(COOKIE, &cookie)
+        .await
+        .json();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].id, 3);
+}
+
+#[tokio::test]
+async fn test_user_beatmap_expected_mode() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_beatmap(mock_beatmap(10, 100, 3));
+    let mut taiko_beatmap = mock_beatmap(11, 100, 3);
+    taiko_beatmap["mode"] = json!("taiko");
+    requester.add_beatmap(taiko_beatmap);
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let response = test_server
+        .patch("/users/map")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&BeatmapRequest {
+            ids: vec![10, 11].into_iter().collect(),
+            expected_mode: Some(GameMode::Osu),
+            ..Default::default()
+        })
+        .await;
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    let error: Value = response.json();
+    assert_eq!(error["beatmap_ids"], json!([11]));
+
+    // Only the difficulties in the expected mode are added from beatmapsets
+    let user: User = test_server
+        .patch("/users/map")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&BeatmapRequest {
+            beatmapset_ids: vec![100].into_iter().collect(),
+            expected_mode: Some(GameMode::Osu),
+            ..Default::default()
+        })
+        .await
+        .json();
+    let beatmap_ids: Vec<u32> = user.beatmaps.iter().map(GetID::get_id).collect();
+    assert_eq!(beatmap_ids, vec![10]);
+}
+
+#[tokio::test]
+async fn test_user_beatmap_order_is_stable() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    for beatmap_id in 10..=12 {
+        requester.add_beatmap(mock_beatmap(beatmap_id, beatmap_id + 90, 2));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    // Re-adding 10 at the end shouldn't move it
+    for beatmap_id in [12, 10, 11, 10] {
+        test_server
+            .patch("/users/map")
+            .add_header(COOKIE, format!("user_token={}", jwt))
+            .json(&BeatmapRequest {
+                ids: [beatmap_id].into_iter().collect(),
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+    }
+
+    for _ in 0..2 {
+        let user: User = test_server
+            .get("/users/2")
+            .add_header(COOKIE, format!("user_token={}", jwt))
+            .await
+            .json();
+        let beatmapset_ids: Vec<u32> = user.beatmaps.iter().map(GetID::get_id).collect();
+        assert_eq!(beatmapset_ids, vec![102, 100, 101]);
+    }
+}
+
+#[tokio::test]
+async fn test_user_beatmap_add_keeps_request_order() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    for beatmap_id in 10..=12 {
+        requester.add_beatmap(mock_beatmap(beatmap_id, beatmap_id + 90, 2));
+    }
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    let user: User = test_server
+        .patch("/users/map")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&BeatmapRequest {
+            ids: vec![12, 10, 12, 11],
+            ..Default::default()
+        })
+        .await
+        .json();
+    let beatmapset_ids: Vec<u32> = user.beatmaps.iter().map(GetID::get_id).collect();
+    assert_eq!(beatmapset_ids, vec![102, 100, 101]);
+}
+
+#[tokio::test]
+async fn test_user_last_active() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    let (test_server, _testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+
+    // Logging in doesn't count
+    let user: User = test_server
+        .get("/users/2")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert!(user.last_active.is_none());
+
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+
+    let user: User = test_server
+        .get("/users/2")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert!(user.last_active.is_some());
+    // Being added as an influence isn't an activity of the added user
+    let influence: User = test_server
+        .get("/users/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .json();
+    assert!(influence.last_active.is_none());
+}
+
+#[tokio::test]
+async fn test_purge_user() {
+    let requester = MockRequester::new();
+    requester.add_user(mock_user(2, "peppy"));
+    requester.add_user(mock_user(3, "mapper"));
+    requester.add_user(mock_user(4, "another mapper"));
+    let (test_server, testcontainer_handle) = init_test_env_with_requester(requester).await;
+    let jwt = admin_jwt(&test_server, 2).await;
+    let other_jwt = admin_jwt(&test_server, 4).await;
+
+    test_server
+        .post("/influence/3")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "user_id": "3" }))
+        .await
+        .assert_status_ok();
+    test_server
+        .post("/influence/2")
+        .add_header(COOKIE, format!("user_token={}", other_jwt))
+        .json(&json!({ "user_id": "2" }))
+        .await
+        .assert_status_ok();
+    test_server
+        .patch("/users/bio")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "bio": "hello" }))
+        .await
+        .assert_status_ok();
+    test_server
+        .post("/report")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "target_type": "USER", "target_id": 3, "reason": "spam" }))
+        .await
+        .assert_status_ok();
+
+    let response = test_server
+        .delete("/users/me?anonymize=true")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await;
+    response.assert_status_ok();
+    assert!(response
+        .header(SET_COOKIE)
+        .to_str()
+        .unwrap()
+        .starts_with("user_token=deleted"));
+    test_server
+        .get("/users/me")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let user: User = test_server
+        .get("/users/2")
+        .add_header(COOKIE, format!("user_token={}", other_jwt))
+        .await
+        .json();
+    assert_eq!(user.username, "Deleted user");
+    assert_eq!(user.bio, "");
+    assert_eq!(user.mentions, Some(0));
+    let influence: User = test_server
+        .get("/users/3")
+        .add_header(COOKIE, format!("user_token={}", other_jwt))
+        .await
+        .json();
+    assert_eq!(influence.mentions, Some(0));
+    test_server
+        .get("/influence/2")
+        .add_header(COOKIE, format!("user_token={}", other_jwt))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let db = test_db_client(&testcontainer_handle).await;
+    let activities = db.get_activities(100, 0).await.unwrap();
+    for activity in activities {
+        let activity = serde_json::to_value(activity).unwrap();
+        assert_ne!(activity["user"]["id"], 2);
+        assert_ne!(activity["influence"]["id"], 2);
+    }
+    let audit_logs = db.get_audit_logs(Some(2), None, 0, 100).await.unwrap();
+    assert!(audit_logs
+        .iter()
+        .any(|audit_log| audit_log.action == AuditAction::EditBio));
+    assert!(audit_logs
+        .iter()
+        .filter(|audit_log| audit_log.action != AuditAction::PurgeUser)
+        .all(|audit_log| audit_log.details.is_none()));
+    let reports = db.get_reports(false, 0, 100).await.unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].reason, "");
+
+    // Activities the purge created are skipped, the ones after it are shown
+    let jwt = admin_jwt(&test_server, 2).await;
+    test_server
+        .patch("/users/bio")
+        .add_header(COOKIE, format!("user_token={}", jwt))
+        .json(&json!({ "bio": "hello again" }))
+        .await
+        .assert_status_ok();
+    let activity = assert_activity(&test_server, 2, "EDIT_BIO", Duration::from_secs(5)).await;
+    assert_eq!(activity["bio"], "hello again");
+}